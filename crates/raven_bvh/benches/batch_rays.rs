@@ -0,0 +1,118 @@
+#![feature(test)]
+extern crate test;
+
+use bevy::{
+    ecs::system::{RunSystemOnce, SystemState},
+    math::bounding::RayCast3d,
+    prelude::*,
+};
+use rand::prelude::*;
+use rand_chacha::{ChaChaRng, rand_core::SeedableRng};
+use raven_bvh::{build_tlas, prelude::*};
+use test::{Bencher, black_box};
+
+/// Rays cast a frame, like a few AI vision cones
+const RAY_COUNT: usize = 1024;
+/// Members on each side of the grid
+const GRID_SIZE: i32 = 20;
+const SPACING: f32 = 4.0;
+
+#[test]
+fn batch_matches_single_rays() {
+    let (mut world, tlas_e) = setup_world();
+    let rays = random_rays(RAY_COUNT);
+    let mut state = SystemState::<TlasCast>::new(&mut world);
+    let tlas_cast = state.get(&world);
+
+    // every other member, the callbacks are shared by the batch's threads
+    let odd = |e: Entity| e.index() % 2 == 1;
+    for settings in [
+        TlasCastSettings::default(),
+        TlasCastSettings::default().with_filter(&odd),
+    ] {
+        let batch = tlas_cast.intersect_batch(&rays, tlas_e, &settings);
+        assert_eq!(batch.len(), rays.len());
+        for (ray, hit) in rays.iter().zip(batch) {
+            let single = tlas_cast.cast_ray(ray, tlas_e, &settings);
+            assert_eq!(
+                hit.map(|h| (h.entity, h.distance)),
+                single.map(|h| (h.entity, h.distance))
+            );
+        }
+    }
+}
+
+#[bench]
+fn rays_1k_loop(b: &mut Bencher) {
+    let (mut world, tlas_e) = setup_world();
+    let rays = random_rays(RAY_COUNT);
+    let mut state = SystemState::<TlasCast>::new(&mut world);
+    let tlas_cast = state.get(&world);
+    let settings = TlasCastSettings::default();
+    b.iter(|| {
+        let hits = rays
+            .iter()
+            .map(|ray| tlas_cast.cast_ray(ray, tlas_e, &settings))
+            .collect::<Vec<_>>();
+        black_box(hits);
+    });
+}
+
+#[bench]
+fn rays_1k_batch(b: &mut Bencher) {
+    let (mut world, tlas_e) = setup_world();
+    let rays = random_rays(RAY_COUNT);
+    let mut state = SystemState::<TlasCast>::new(&mut world);
+    let tlas_cast = state.get(&world);
+    let settings = TlasCastSettings::default();
+    b.iter(|| {
+        black_box(tlas_cast.intersect_batch(&rays, tlas_e, &settings));
+    });
+}
+
+/// A grid of spheres and cubes in one TLAS
+fn setup_world() -> (World, Entity) {
+    let mut world = World::new();
+    let mut blases = Assets::<Blas>::default();
+    let shapes = [
+        blases.add(Blas::try_from(&Sphere::new(1.0).mesh().build()).unwrap()),
+        blases.add(Blas::try_from(&Cuboid::new(1.5, 1.5, 1.5).mesh().build()).unwrap()),
+    ];
+    world.insert_resource(blases);
+
+    let tlas_e = world.spawn(Tlas::default()).id();
+    for x in 0..GRID_SIZE {
+        for z in 0..GRID_SIZE {
+            let transform = Transform::from_xyz(x as f32 * SPACING, 0.0, z as f32 * SPACING)
+                .with_rotation(Quat::from_rotation_y((x * z) as f32));
+            world.spawn((
+                MeshBlas(shapes[((x + z) % 2) as usize].clone()),
+                GlobalTransform::from(transform),
+                TlasTarget(tlas_e),
+            ));
+        }
+    }
+    world.run_system_once(build_tlas).unwrap();
+    (world, tlas_e)
+}
+
+/// Rays from above the grid towards random points on it, some miss
+fn random_rays(count: usize) -> Vec<RayCast3d> {
+    let mut rng = ChaChaRng::seed_from_u64(0);
+    let extent = GRID_SIZE as f32 * SPACING;
+    (0..count)
+        .map(|_| {
+            let origin = vec3(
+                rng.random_range(0.0..extent),
+                10.0,
+                rng.random_range(0.0..extent),
+            );
+            let target = vec3(
+                rng.random_range(-SPACING..extent + SPACING),
+                0.0,
+                rng.random_range(-SPACING..extent + SPACING),
+            );
+            RayCast3d::new(origin, Dir3A::new((target - origin).into()).unwrap(), 1e30)
+        })
+        .collect()
+}
@@ -1,8 +1,74 @@
+use std::collections::HashSet;
+
 use bevy::{color::palettes::tailwind, prelude::*, render::mesh::MeshPlugin};
 use raven_bvh::prelude::*;
 
 #[test]
 fn test_bvh_camera() {
+    let file_path = "tmp/bevy.png";
+    render(TlasCameraMode::Barycentric, file_path, 1);
+    println!("Camera image saved to: {}", file_path);
+}
+
+#[test]
+fn test_bvh_camera_modes() {
+    for (mode, file_path) in [
+        (TlasCameraMode::Depth { far: 30.0 }, "tmp/bevy_depth.png"),
+        (TlasCameraMode::Normal, "tmp/bevy_normal.png"),
+        (TlasCameraMode::EntityId, "tmp/bevy_entity.png"),
+        (
+            TlasCameraMode::Heatmap { max_visits: 64 },
+            "tmp/bevy_heatmap.png",
+        ),
+    ] {
+        let _ = std::fs::remove_file(file_path);
+        render(mode, file_path, 1);
+        let image = image::open(file_path)
+            .unwrap_or_else(|e| panic!("{file_path} not saved: {e}"))
+            .to_rgb8();
+        // the top row looks above the horizon, the centre column at the ground between the spheres
+        let sky = image.get_pixel(128, 0).0;
+        match mode {
+            TlasCameraMode::Depth { .. } => {
+                assert_eq!(sky, [0, 0, 0], "{file_path} miss isn't black");
+                // the ground gets closer towards the bottom of the image
+                let column = (100..256)
+                    .map(|y| image.get_pixel(128, y).0[0])
+                    .collect::<Vec<_>>();
+                assert!(column.is_sorted(), "{file_path} {column:?}");
+                assert!(column[0] < column[column.len() - 1], "{file_path}");
+            }
+            TlasCameraMode::Heatmap { .. } => {
+                let colors = image.pixels().map(|p| p.0).collect::<HashSet<_>>();
+                assert!(colors.len() > 1, "{file_path} is uniform");
+                assert_ne!(sky, image.get_pixel(128, 200).0, "{file_path}");
+            }
+            _ => assert_eq!(sky, [0, 0, 0], "{file_path} miss isn't black"),
+        }
+        println!("Camera image saved to: {}", file_path);
+    }
+}
+
+#[test]
+fn test_bvh_camera_path_trace() {
+    let file_path = "tmp/bevy_path_trace.png";
+    let mode = TlasCameraMode::PathTrace {
+        bounces: 2,
+        sky: LinearRgba::rgb(0.4, 0.5, 0.7),
+    };
+    let _ = std::fs::remove_file(file_path);
+    let app = render(mode, file_path, 16);
+    let mut cameras = app.world().try_query::<&TlasCamera>().unwrap();
+    let camera = cameras.single(app.world()).unwrap();
+    assert_eq!(camera.sample_count, 16);
+    // saved once, after the last sample
+    assert!(camera.save_path.is_none());
+    assert!(std::path::Path::new(file_path).exists());
+    println!("Camera image saved to: {}", file_path);
+}
+
+/// Renders the scene for ``frames``, saving the image to ``file_path``
+fn render(mode: TlasCameraMode, file_path: &'static str, frames: u32) -> App {
     // Setup app
     let mut app = App::new();
 
@@ -15,62 +81,51 @@ fn test_bvh_camera() {
         //AssetPlugin::default(),
         BvhPlugin,
     ))
+    .insert_resource(CameraOutput {
+        mode,
+        file_path,
+        frames,
+    })
     .add_systems(Startup, setup)
     .add_systems(PostStartup, add_bvh_to_tlas);
 
     // Run systems
-    app.update();
-
-    let TestEntities { camera } = app.world().resource::<TestEntities>();
-
-    // Check resulting changes
-    let image = {
-        let handle = app
-            .world()
-            .get::<TlasCamera>(*camera)
-            .expect("Camera image not found")
-            .image
-            .clone()
-            .expect("Image not found");
-        app.world()
-            .resource::<Assets<Image>>()
-            .get(&handle)
-            .expect("Camera image asset not found")
-            .clone()
-            .try_into_dynamic()
-            .expect("Failed to convert image to dynamic")
-    };
-    let file_path = "tmp/bevy.png";
-    image.save(file_path).unwrap();
-    println!("Camera image saved to: {}", file_path);
+    for _ in 0..frames {
+        app.update();
+    }
 
     //assert_eq!(app.world().get::<Enemy>(enemy_id).unwrap().hit_points, 4);
+    app
 }
 
 #[derive(Resource)]
-struct TestEntities {
-    pub camera: Entity,
+struct CameraOutput {
+    mode: TlasCameraMode,
+    file_path: &'static str,
+    frames: u32,
 }
 
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
+    output: Res<CameraOutput>,
     //mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
     // tlas
     let tlas = commands.spawn(Tlas::default()).id();
 
-    let camera = commands
-        .spawn((
-            Camera3d::default(),
-            Camera {
-                hdr: true,
-                ..default()
-            },
-            Transform::from_xyz(0.0, 2.0, 10.0).looking_at(Vec3::ZERO, Vec3::Y),
-            TlasCamera::new(256, 256, tlas),
-        ))
-        .id();
+    commands.spawn((
+        Camera3d::default(),
+        Camera {
+            hdr: true,
+            ..default()
+        },
+        Transform::from_xyz(0.0, 2.0, 10.0).looking_at(Vec3::ZERO, Vec3::Y),
+        TlasCamera::new(256, 256, tlas)
+            .with_mode(output.mode)
+            .with_save_path(output.file_path)
+            .with_save_sample_count(output.frames),
+    ));
 
     // light
     commands.spawn((
@@ -112,8 +167,6 @@ fn setup(
             SpawnBvhForTlas(tlas),
         ));
     }
-
-    commands.insert_resource(TestEntities { camera });
 }
 
 // add all the bvh to the tlas
@@ -18,18 +18,19 @@ use test::{Bencher, black_box};
 #[test]
 fn scene_1k_1024() {
     // Setup app
-    let mut app = setup_app::<10, 100, 1024>();
+    let file_path = "tmp/bevy_1k_1024x1024.png";
+    let mut app = setup_app::<10, 100, 1024>(SceneSettings {
+        save_path: Some(file_path),
+        ..default()
+    });
 
     // Run systems
     app.update();
+    println!("Camera image saved to: {}", file_path);
 
     // Check resulting changes
     let image = get_image(app);
 
-    let file_path = "tmp/bevy_1k_1024x1024.png";
-    image.save(file_path).unwrap();
-    println!("Camera image saved to: {}", file_path);
-
     // Check against a reference image
     let mut hasher = DefaultHasher::new();
     let ref_image =
@@ -45,16 +46,17 @@ fn scene_1k_1024() {
 
 #[test]
 fn scene_100k_1024() {
-    let mut app = setup_app::<100, 1000, 1024>();
+    let file_path = "tmp/bevy_100k_1024x1024.png";
+    let mut app = setup_app::<100, 1000, 1024>(SceneSettings {
+        save_path: Some(file_path),
+        ..default()
+    });
 
     app.update();
+    println!("Camera image saved to: {}", file_path);
 
     let image = get_image(app);
 
-    let file_path = "tmp/bevy_100k_1024x1024.png";
-    image.save(file_path).unwrap();
-    println!("Camera image saved to: {}", file_path);
-
     // Check against a reference image
     let mut hasher = DefaultHasher::new();
     let ref_image =
@@ -71,7 +73,7 @@ fn scene_100k_1024() {
 #[bench]
 fn random_scene_1k_256(b: &mut Bencher) {
     b.iter(|| {
-        let mut app = setup_app::<10, 100, 256>();
+        let mut app = setup_app::<10, 100, 256>(SceneSettings::default());
 
         app.update();
 
@@ -88,7 +90,7 @@ fn random_scene_1k_256(b: &mut Bencher) {
 #[bench]
 pub fn random_scene_100k_256(b: &mut Bencher) {
     b.iter(|| {
-        let mut app = setup_app::<100, 1000, 256>();
+        let mut app = setup_app::<100, 1000, 256>(SceneSettings::default());
         app.update();
         let image = get_image(app);
 
@@ -99,12 +101,179 @@ pub fn random_scene_100k_256(b: &mut Bencher) {
     });
 }
 
+/// Build qualities compared by the benches below
+fn qualities() -> [(&'static str, BlasBuildQuality); 5] {
+    [
+        ("binned_8", BlasBuildQuality::Binned { bins: 8 }),
+        ("binned_32", BlasBuildQuality::Binned { bins: 32 }),
+        ("full_sweep", BlasBuildQuality::FullSweep),
+        ("spatial", BlasBuildQuality::spatial()),
+        (
+            "spatial_no_alpha",
+            BlasBuildQuality::Spatial {
+                bins: 32,
+                alpha: 0.0,
+            },
+        ),
+    ]
+}
+
+/// Prints the tree stats of each quality, run with ``--nocapture``
+#[test]
+fn build_quality_stats() {
+    let tris = gen_random_triangles(1000, 4.0, &mut ChaChaRng::seed_from_u64(0));
+    for (name, quality) in qualities() {
+        let stats = Blas::build(tris.clone(), quality).stats();
+        println!("{name}: {stats:?}");
+        assert_eq!(stats.leaf_count * 2 - 1, stats.node_count);
+        assert!(stats.triangle_references >= tris.len());
+    }
+}
+
+fn bench_build(b: &mut Bencher, quality: BlasBuildQuality) {
+    let tris = gen_random_triangles(1000, 4.0, &mut ChaChaRng::seed_from_u64(0));
+    b.iter(|| black_box(Blas::build(tris.clone(), quality)));
+}
+
+#[bench]
+fn build_1k_binned_8(b: &mut Bencher) {
+    bench_build(b, qualities()[0].1);
+}
+
+#[bench]
+fn build_1k_binned_32(b: &mut Bencher) {
+    bench_build(b, qualities()[1].1);
+}
+
+#[bench]
+fn build_1k_full_sweep(b: &mut Bencher) {
+    bench_build(b, qualities()[2].1);
+}
+
+#[bench]
+fn build_1k_spatial(b: &mut Bencher) {
+    bench_build(b, qualities()[3].1);
+}
+
+/// Times only the rendering, the scene is built once up front
+fn bench_trace(b: &mut Bencher, settings: SceneSettings) {
+    let mut app = setup_app::<100, 1000, 256>(settings);
+    app.update();
+    b.iter(|| {
+        app.update();
+        black_box(app.world().resource::<TestEntities>().camera);
+    });
+}
+
+#[bench]
+fn trace_100k_256_binned_8(b: &mut Bencher) {
+    bench_trace(
+        b,
+        SceneSettings {
+            quality: qualities()[0].1,
+            ..default()
+        },
+    );
+}
+
+#[bench]
+fn trace_100k_256_binned_32(b: &mut Bencher) {
+    bench_trace(
+        b,
+        SceneSettings {
+            quality: qualities()[1].1,
+            ..default()
+        },
+    );
+}
+
+#[bench]
+fn trace_100k_256_full_sweep(b: &mut Bencher) {
+    bench_trace(
+        b,
+        SceneSettings {
+            quality: qualities()[2].1,
+            ..default()
+        },
+    );
+}
+
+#[bench]
+fn trace_100k_256_spatial(b: &mut Bencher) {
+    bench_trace(
+        b,
+        SceneSettings {
+            quality: qualities()[3].1,
+            ..default()
+        },
+    );
+}
+
+#[bench]
+fn trace_100k_256_single_rays(b: &mut Bencher) {
+    bench_trace(
+        b,
+        SceneSettings {
+            ray_packets: false,
+            ..default()
+        },
+    );
+}
+
+#[bench]
+fn trace_100k_256_bvh4(b: &mut Bencher) {
+    bench_trace(
+        b,
+        SceneSettings {
+            width: BlasWidth::Four,
+            ray_packets: false,
+            ..default()
+        },
+    );
+}
+
+#[bench]
+fn trace_100k_256_bvh8(b: &mut Bencher) {
+    bench_trace(
+        b,
+        SceneSettings {
+            width: BlasWidth::Eight,
+            ray_packets: false,
+            ..default()
+        },
+    );
+}
+
 #[derive(Resource)]
 struct TestEntities {
     pub camera: Entity,
 }
 
-fn setup_app<const GROUP_COUNT: usize, const TRI_PER_GROUP: usize, const RESOLUTION: u32>() -> App {
+/// How the scene's Blases are built and traced
+#[derive(Resource, Clone, Copy)]
+struct SceneSettings {
+    quality: BlasBuildQuality,
+    width: BlasWidth,
+    /// Camera traces 2x2 ray packets, packets always use the binary tree
+    ray_packets: bool,
+    /// Camera saves its image here
+    save_path: Option<&'static str>,
+}
+
+impl Default for SceneSettings {
+    fn default() -> Self {
+        Self {
+            quality: BlasBuildQuality::default(),
+            width: BlasWidth::Two,
+            ray_packets: true,
+            save_path: None,
+        }
+    }
+}
+
+fn setup_app<const GROUP_COUNT: usize, const TRI_PER_GROUP: usize, const RESOLUTION: u32>(
+    settings: SceneSettings,
+) -> App {
     let mut app = App::new();
     app.add_plugins((
         MinimalPlugins,
@@ -115,6 +284,7 @@ fn setup_app<const GROUP_COUNT: usize, const TRI_PER_GROUP: usize, const RESOLUT
         //AssetPlugin::default(),
         BvhPlugin,
     ))
+    .insert_resource(settings)
     .add_systems(
         Startup,
         setup_tri_scene::<GROUP_COUNT, TRI_PER_GROUP, RESOLUTION>,
@@ -127,30 +297,14 @@ fn setup_app<const GROUP_COUNT: usize, const TRI_PER_GROUP: usize, const RESOLUT
 fn setup_tri_scene<const GROUP_COUNT: usize, const TRI_PER_GROUP: usize, const RESOLUTION: u32>(
     mut commands: Commands,
     mut bvhs: ResMut<Assets<Blas>>,
+    settings: Res<SceneSettings>,
 ) {
-    fn random_vec3(rng: &mut impl Rng) -> Vec3A {
-        vec3a(
-            rng.random_range(-1.0..=1.0),
-            rng.random_range(-1.0..=1.0),
-            rng.random_range(-1.0..=1.0),
-        )
-    }
-
-    fn gen_random_triangles(size: u32, scale: f32, rng: &mut impl Rng) -> Vec<Tri> {
-        (0..size)
-            .map(|_| {
-                // TODO: there should already be a random vec3 impl somewhere
-                let r0 = random_vec3(rng);
-                let r1 = random_vec3(rng);
-                let r2 = random_vec3(rng);
-
-                let v0 = r0 * scale;
-                Tri::new(v0, v0 + r1, v0 + r2)
-            })
-            .collect::<Vec<_>>()
-    }
-
     let tlas = commands.spawn(Tlas::default()).id();
+    let mut tlas_camera =
+        TlasCamera::new(RESOLUTION, RESOLUTION, tlas).with_ray_packets(settings.ray_packets);
+    if let Some(save_path) = settings.save_path {
+        tlas_camera = tlas_camera.with_save_path(save_path);
+    }
     let camera = commands
         .spawn((
             Camera3d::default(),
@@ -159,7 +313,7 @@ fn setup_tri_scene<const GROUP_COUNT: usize, const TRI_PER_GROUP: usize, const R
                 rotation: Quat::from_axis_angle(Vec3::X, -PI / 6.0),
                 ..Default::default()
             },
-            TlasCamera::new(RESOLUTION, RESOLUTION, tlas),
+            tlas_camera,
         ))
         .id();
 
@@ -171,13 +325,15 @@ fn setup_tri_scene<const GROUP_COUNT: usize, const TRI_PER_GROUP: usize, const R
         for j in 0..side_count {
             // let id = i * side_count + j;
             let tris = gen_random_triangles(TRI_PER_GROUP as u32, 4.0, &mut rng);
+            let mut blas = Blas::build(tris, settings.quality);
+            blas.collapse(settings.width);
             commands.spawn((
                 Transform::from_xyz(
                     i as f32 * offset - side_offset + (offset * 0.5),
                     0.0,
                     j as f32 * offset - side_offset + (offset * 0.5),
                 ),
-                MeshBlas(bvhs.add(Blas::new(tris))),
+                MeshBlas(bvhs.add(blas)),
                 TlasTarget(tlas), // Will make the tlas track this entity
             ));
         }
@@ -186,6 +342,28 @@ fn setup_tri_scene<const GROUP_COUNT: usize, const TRI_PER_GROUP: usize, const R
     commands.insert_resource(TestEntities { camera });
 }
 
+fn random_vec3(rng: &mut impl Rng) -> Vec3A {
+    vec3a(
+        rng.random_range(-1.0..=1.0),
+        rng.random_range(-1.0..=1.0),
+        rng.random_range(-1.0..=1.0),
+    )
+}
+
+fn gen_random_triangles(size: u32, scale: f32, rng: &mut impl Rng) -> Vec<Tri> {
+    (0..size)
+        .map(|_| {
+            // TODO: there should already be a random vec3 impl somewhere
+            let r0 = random_vec3(rng);
+            let r1 = random_vec3(rng);
+            let r2 = random_vec3(rng);
+
+            let v0 = r0 * scale;
+            Tri::new(v0, v0 + r1, v0 + r2)
+        })
+        .collect::<Vec<_>>()
+}
+
 fn get_image(app: App) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
     let TestEntities { camera } = app.world().resource::<TestEntities>();
 
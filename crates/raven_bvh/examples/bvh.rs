@@ -51,7 +51,7 @@ fn setup(
 
     // Example of addings Bvh directly from a mesh
     let ground_mesh = Plane3d::new(Vec3::Y, Vec2::splat(500.)).mesh().build();
-    let ground_bvh = Blas::from(&ground_mesh);
+    let ground_bvh = Blas::try_from(&ground_mesh).unwrap();
     commands.spawn((
         Name::new("Ground"),
         Transform::from_xyz(0.0, 0.0, 0.0),
@@ -66,7 +66,7 @@ fn setup(
 
     // Like other assets, the same bvh can be uses on multiple entities
     let box_mesh = Cuboid::new(1.0, 1.0, 1.0).mesh().build();
-    let box_bvh = Blas::from(&box_mesh);
+    let box_bvh = Blas::try_from(&box_mesh).unwrap();
     let box_mesh_handle = meshes.add(box_mesh);
     let box_bvh_handle = bvhs.add(box_bvh);
     let mat = materials.add(StandardMaterial {
@@ -1,4 +1,7 @@
-use bevy::{math::bounding::Aabb3d, prelude::*};
+use bevy::{
+    math::{Affine3A, bounding::Aabb3d},
+    prelude::*,
+};
 
 pub trait Aabb3dExt {
     fn init() -> Self;
@@ -8,6 +11,9 @@ pub trait Aabb3dExt {
     fn expand(&mut self, point: Vec3A);
 
     fn expand_aabb(&mut self, aabb: &Aabb3d);
+
+    /// Bounds of this aabb after an affine transform, may be larger than the transformed box.
+    fn transform(&self, affine: &Affine3A) -> Aabb3d;
 }
 
 impl Aabb3dExt for Aabb3d {
@@ -33,9 +39,22 @@ impl Aabb3dExt for Aabb3d {
         self.max = self.max.max(point);
     }
 
+    /// Union with ``aabb``, an empty [Aabb3dExt::init] box leaves it unchanged.
     #[inline]
     fn expand_aabb(&mut self, aabb: &Aabb3d) {
-        self.expand(aabb.min);
-        self.expand(aabb.max);
+        self.min = self.min.min(aabb.min);
+        self.max = self.max.max(aabb.max);
+    }
+
+    #[inline]
+    fn transform(&self, affine: &Affine3A) -> Aabb3d {
+        let center = affine.transform_point3a((self.min + self.max) * 0.5);
+        let half = (self.max - self.min) * 0.5;
+        let m = affine.matrix3;
+        let half = m.x_axis.abs() * half.x + m.y_axis.abs() * half.y + m.z_axis.abs() * half.z;
+        Aabb3d {
+            min: center - half,
+            max: center + half,
+        }
     }
 }
@@ -0,0 +1,492 @@
+use std::fmt::{self, Display};
+
+use bevy::{
+    asset::{
+        AssetLoader, AsyncWriteExt, LoadContext,
+        io::{Reader, SliceReader, Writer},
+        meta::{AssetAction, AssetMeta},
+        processor::{Process, ProcessContext, ProcessError},
+        saver::{AssetSaver, SavedAsset},
+    },
+    gltf::{Gltf, GltfError, GltfLoader, GltfLoaderSettings},
+    math::bounding::Aabb3d,
+    platform::collections::HashMap,
+    prelude::*,
+};
+
+use crate::{blas::*, build::BlasBuildQuality};
+
+/// Start of a serialized [Blas], see [Blas::to_bytes].
+const BLAS_MAGIC: &[u8; 4] = b"RBLS";
+/// Start of a glTF file with baked Blases in front of it, see [GltfBlasProcessor].
+const BAKED_GLTF_MAGIC: &[u8; 4] = b"RBGL";
+/// Bumped when the binary layout changes, old files fail to load rather than load wrong.
+const FORMAT_VERSION: u32 = 2;
+
+const HAS_NORMALS: u32 = 1;
+const HAS_UV0: u32 = 1 << 1;
+const KEEPS_ATTRIBUTES: u32 = 1 << 2;
+
+/// Label of the [GltfBlas] in a glTF loaded by [GltfBlasLoader].
+pub const GLTF_BLAS_LABEL: &str = "Blas";
+
+/// Error reading serialized [Blas] data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlasFormatError {
+    /// Doesn't start with the expected magic bytes, isn't a [Blas].
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// Ran out of bytes, the data is truncated.
+    UnexpectedEnd,
+    /// A node or triangle index is out of range.
+    InvalidIndex,
+}
+
+impl Display for BlasFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlasFormatError::BadMagic => write!(f, "not a serialized Blas"),
+            BlasFormatError::UnsupportedVersion(version) => write!(
+                f,
+                "Blas format version {version} is not supported, expected {FORMAT_VERSION}"
+            ),
+            BlasFormatError::UnexpectedEnd => write!(f, "Blas data is truncated"),
+            BlasFormatError::InvalidIndex => write!(f, "Blas data has an index out of range"),
+        }
+    }
+}
+
+impl std::error::Error for BlasFormatError {}
+
+/// Error from [BlasLoader] or [GltfBlasLoader].
+#[derive(Debug)]
+pub enum BlasLoadError {
+    Io(std::io::Error),
+    Format(BlasFormatError),
+    Gltf(GltfError),
+}
+
+impl Display for BlasLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlasLoadError::Io(err) => write!(f, "failed to read Blas: {err}"),
+            BlasLoadError::Format(err) => write!(f, "failed to load Blas: {err}"),
+            BlasLoadError::Gltf(err) => write!(f, "failed to load glTF: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for BlasLoadError {}
+
+impl From<std::io::Error> for BlasLoadError {
+    fn from(err: std::io::Error) -> Self {
+        BlasLoadError::Io(err)
+    }
+}
+
+impl From<BlasFormatError> for BlasLoadError {
+    fn from(err: BlasFormatError) -> Self {
+        BlasLoadError::Format(err)
+    }
+}
+
+impl From<GltfError> for BlasLoadError {
+    fn from(err: GltfError) -> Self {
+        BlasLoadError::Gltf(err)
+    }
+}
+
+impl Blas {
+    /// Serializes to a compact little endian binary format, load it back with [Blas::from_bytes] or [BlasLoader].
+    ///
+    /// Nodes, triangles, ``triangle_indexs`` and any kept attributes are written, centroids are recomputed on load.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let attributes = self.attributes.as_ref();
+        let normals = attributes.map_or(&[][..], |a| &a.normals[..]);
+        let uv0 = attributes.map_or(&[][..], |a| &a.uv0[..]);
+        let mut flags = 0;
+        if attributes.is_some() {
+            flags |= KEEPS_ATTRIBUTES;
+        }
+        if !normals.is_empty() {
+            flags |= HAS_NORMALS;
+        }
+        if !uv0.is_empty() {
+            flags |= HAS_UV0;
+        }
+
+        let mut bytes = Vec::with_capacity(
+            24 + self.nodes.len() * 32 + self.tris.len() * 36 + self.triangle_indexs.len() * 4,
+        );
+        bytes.extend_from_slice(BLAS_MAGIC);
+        put_u32(&mut bytes, FORMAT_VERSION);
+        put_u32(&mut bytes, self.nodes.len() as u32);
+        put_u32(&mut bytes, self.tris.len() as u32);
+        // spatial splits can reference a triangle more than once
+        put_u32(&mut bytes, self.triangle_indexs.len() as u32);
+        put_u32(&mut bytes, flags);
+
+        for node in self.nodes.iter() {
+            put_vec3a(&mut bytes, node.aabb.min);
+            put_vec3a(&mut bytes, node.aabb.max);
+            put_u32(&mut bytes, node.left_first);
+            put_u32(&mut bytes, node.tri_count);
+        }
+        for tri in self.tris.iter() {
+            put_vec3a(&mut bytes, tri.vertex0);
+            put_vec3a(&mut bytes, tri.vertex1);
+            put_vec3a(&mut bytes, tri.vertex2);
+        }
+        for i in self.triangle_indexs.iter() {
+            put_u32(&mut bytes, *i as u32);
+        }
+        for normal in normals.iter().flatten() {
+            put_vec3a(&mut bytes, *normal);
+        }
+        for uv in uv0.iter().flatten() {
+            put_f32(&mut bytes, uv.x);
+            put_f32(&mut bytes, uv.y);
+        }
+        bytes
+    }
+
+    /// Reads a [Blas] written by [Blas::to_bytes].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Blas, BlasFormatError> {
+        let mut reader = ByteReader { bytes, pos: 0 };
+        if reader.take(4)? != BLAS_MAGIC {
+            return Err(BlasFormatError::BadMagic);
+        }
+        let version = reader.u32()?;
+        if version != FORMAT_VERSION {
+            return Err(BlasFormatError::UnsupportedVersion(version));
+        }
+        let node_count = reader.u32()? as usize;
+        let tri_count = reader.u32()? as usize;
+        let index_count = reader.u32()? as usize;
+        let flags = reader.u32()?;
+
+        // check the sizes up front, so bad counts can't allocate more than the data
+        let attribute_size = match flags & HAS_NORMALS != 0 {
+            true => 36,
+            false => 0,
+        } + match flags & HAS_UV0 != 0 {
+            true => 24,
+            false => 0,
+        };
+        let expected = node_count * 32 + tri_count * (36 + attribute_size) + index_count * 4;
+        if reader.remaining() < expected {
+            return Err(BlasFormatError::UnexpectedEnd);
+        }
+
+        let mut nodes = Vec::with_capacity(node_count);
+        for i in 0..node_count {
+            let node = BlasNode {
+                aabb: Aabb3d {
+                    min: reader.vec3a()?,
+                    max: reader.vec3a()?,
+                },
+                left_first: reader.u32()?,
+                tri_count: reader.u32()?,
+            };
+            // children come after their parent, so a corrupt tree can't loop back on itself,
+            // only an empty Blas has a branch without children, its lone root
+            let left = node.left_first as usize;
+            let in_range = match node.is_leaf() {
+                true => left + node.tri_count as usize <= index_count,
+                false => (i < left && left + 1 < node_count) || (node_count == 1 && tri_count == 0),
+            };
+            if !in_range {
+                return Err(BlasFormatError::InvalidIndex);
+            }
+            nodes.push(node);
+        }
+
+        let mut tris = Vec::with_capacity(tri_count);
+        for _ in 0..tri_count {
+            tris.push(Tri::new(reader.vec3a()?, reader.vec3a()?, reader.vec3a()?));
+        }
+
+        let mut triangle_indexs = Vec::with_capacity(index_count);
+        for _ in 0..index_count {
+            let i = reader.u32()? as usize;
+            if i >= tri_count {
+                return Err(BlasFormatError::InvalidIndex);
+            }
+            triangle_indexs.push(i);
+        }
+
+        let mut attributes = None;
+        if flags & KEEPS_ATTRIBUTES != 0 {
+            let mut kept = BlasAttributes::default();
+            if flags & HAS_NORMALS != 0 {
+                for _ in 0..tri_count {
+                    kept.normals
+                        .push([reader.vec3a()?, reader.vec3a()?, reader.vec3a()?]);
+                }
+            }
+            if flags & HAS_UV0 != 0 {
+                for _ in 0..tri_count {
+                    kept.uv0
+                        .push([reader.vec2()?, reader.vec2()?, reader.vec2()?]);
+                }
+            }
+            attributes = Some(kept);
+        }
+
+        Ok(Blas {
+            nodes,
+            tris,
+            triangle_indexs,
+            attributes,
+            quality: BlasBuildQuality::default(),
+            wide: None,
+        })
+    }
+}
+
+fn put_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_f32(bytes: &mut Vec<u8>, value: f32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_vec3a(bytes: &mut Vec<u8>, value: Vec3A) {
+    put_f32(bytes, value.x);
+    put_f32(bytes, value.y);
+    put_f32(bytes, value.z);
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], BlasFormatError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + count)
+            .ok_or(BlasFormatError::UnexpectedEnd)?;
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, BlasFormatError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("took 4 bytes")))
+    }
+
+    fn u64(&mut self) -> Result<u64, BlasFormatError> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().expect("took 8 bytes")))
+    }
+
+    fn f32(&mut self) -> Result<f32, BlasFormatError> {
+        self.u32().map(f32::from_bits)
+    }
+
+    fn vec2(&mut self) -> Result<Vec2, BlasFormatError> {
+        Ok(vec2(self.f32()?, self.f32()?))
+    }
+
+    fn vec3a(&mut self) -> Result<Vec3A, BlasFormatError> {
+        Ok(vec3a(self.f32()?, self.f32()?, self.f32()?))
+    }
+}
+
+/// Loads ``.blas`` files written by [Blas::to_bytes] or [BlasSaver].
+#[derive(Default)]
+pub struct BlasLoader;
+
+impl AssetLoader for BlasLoader {
+    type Asset = Blas;
+    type Settings = ();
+    type Error = BlasLoadError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Blas, BlasLoadError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(Blas::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["blas"]
+    }
+}
+
+/// Saves a [Blas] in the format [BlasLoader] reads.
+#[derive(Default)]
+pub struct BlasSaver;
+
+impl AssetSaver for BlasSaver {
+    type Asset = Blas;
+    type Settings = ();
+    type OutputLoader = BlasLoader;
+    type Error = std::io::Error;
+
+    async fn save(
+        &self,
+        writer: &mut Writer,
+        asset: SavedAsset<'_, Blas>,
+        _settings: &(),
+    ) -> Result<(), std::io::Error> {
+        writer.write_all(&asset.to_bytes()).await
+    }
+}
+
+/// Blases baked by [GltfBlasProcessor] for each mesh primitive in a glTF, keyed by the primitive's
+/// label such as ``Mesh0/Primitive0``.
+///
+/// Load it from the glTF with the [GLTF_BLAS_LABEL] label and add it as a [SceneBlas] for
+/// [crate::helpers::SpawnSceneBvhForTlas] to use.
+#[derive(Asset, TypePath, Debug, Default)]
+pub struct GltfBlas {
+    pub primitives: HashMap<String, Handle<Blas>>,
+}
+
+/// Added next to [crate::helpers::SpawnSceneBvhForTlas] to use baked Blases for the scene's meshes
+/// instead of building them.
+///
+/// Holding the handle is what keeps the baked Blases loaded, so load it when spawning the scene.
+#[derive(Component, Debug, Clone)]
+pub struct SceneBlas(pub Handle<GltfBlas>);
+
+/// Loads glTF files processed by [GltfBlasProcessor], adding a [GltfBlas] with the [GLTF_BLAS_LABEL]
+/// label to the regular glTF assets.
+///
+/// Has no extensions, it is picked by the processed asset's meta. Unprocessed files load as plain glTF
+/// with an empty [GltfBlas].
+pub struct GltfBlasLoader {
+    pub gltf: GltfLoader,
+}
+
+impl AssetLoader for GltfBlasLoader {
+    type Asset = Gltf;
+    type Settings = GltfLoaderSettings;
+    type Error = BlasLoadError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &GltfLoaderSettings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Gltf, BlasLoadError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        let mut gltf_bytes = &bytes[..];
+        let mut primitives = HashMap::default();
+        if bytes.starts_with(BAKED_GLTF_MAGIC) {
+            let mut reader = ByteReader {
+                bytes: &bytes,
+                pos: BAKED_GLTF_MAGIC.len(),
+            };
+            let version = reader.u32()?;
+            if version != FORMAT_VERSION {
+                return Err(BlasFormatError::UnsupportedVersion(version).into());
+            }
+            for _ in 0..reader.u32()? {
+                let label_len = reader.u32()? as usize;
+                let label = String::from_utf8_lossy(reader.take(label_len)?).into_owned();
+                let blas_len = reader.u64()? as usize;
+                let blas = Blas::from_bytes(reader.take(blas_len)?)?;
+                let handle = load_context.add_labeled_asset(format!("{label}/Blas"), blas);
+                primitives.insert(label, handle);
+            }
+            gltf_bytes = &bytes[reader.pos..];
+        }
+        load_context.add_labeled_asset(GLTF_BLAS_LABEL.to_string(), GltfBlas { primitives });
+
+        let mut reader = SliceReader::new(gltf_bytes);
+        Ok(self.gltf.load(&mut reader, settings, load_context).await?)
+    }
+}
+
+/// Asset processor that builds a [Blas] for every triangle mesh primitive in a glTF, and writes them
+/// in front of the unchanged glTF for [GltfBlasLoader].
+///
+/// Opt in per file with a ``.meta`` next to it, with Bevy's ``asset_processor`` feature enabled:
+/// ```ron
+/// (
+///     meta_format_version: "1.0",
+///     asset: Process(
+///         processor: "raven_bvh::asset::GltfBlasProcessor",
+///         settings: (),
+///     ),
+/// )
+/// ```
+pub struct GltfBlasProcessor;
+
+impl Process for GltfBlasProcessor {
+    type Settings = ();
+    type OutputLoader = GltfBlasLoader;
+
+    async fn process(
+        &self,
+        context: &mut ProcessContext<'_>,
+        _meta: AssetMeta<(), Self>,
+        writer: &mut Writer,
+    ) -> Result<GltfLoaderSettings, ProcessError> {
+        let meta = AssetMeta::<GltfLoader, ()>::new(AssetAction::Load {
+            loader: core::any::type_name::<GltfLoader>().to_string(),
+            settings: GltfLoaderSettings::default(),
+        });
+        let gltf = context.load_source_asset(meta).await?;
+
+        // sorted so the output only changes when the meshes do
+        let mut labels = gltf
+            .iter_labels()
+            .map(|label| label.to_string())
+            .collect::<Vec<_>>();
+        labels.sort();
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(BAKED_GLTF_MAGIC);
+        put_u32(&mut bytes, FORMAT_VERSION);
+        let count_pos = bytes.len();
+        put_u32(&mut bytes, 0);
+        let mut count = 0u32;
+        for label in labels {
+            let Some(mesh) = gltf
+                .get_labeled(label.clone())
+                .and_then(|asset| asset.get::<Mesh>())
+            else {
+                continue;
+            };
+            // baking is offline, so spend the time on the best tree, lines and points are left out
+            let Ok(blas) = Blas::from_mesh_with_quality(mesh, false, BlasBuildQuality::spatial())
+            else {
+                continue;
+            };
+            let blas = blas.to_bytes();
+            put_u32(&mut bytes, label.len() as u32);
+            bytes.extend_from_slice(label.as_bytes());
+            put_u64(&mut bytes, blas.len() as u64);
+            bytes.extend_from_slice(&blas);
+            count += 1;
+        }
+        bytes[count_pos..count_pos + 4].copy_from_slice(&count.to_le_bytes());
+        bytes.extend_from_slice(context.asset_bytes());
+
+        writer
+            .write_all(&bytes)
+            .await
+            .map_err(|err| ProcessError::AssetSaveError(Box::new(err)))?;
+        Ok(GltfLoaderSettings::default())
+    }
+}
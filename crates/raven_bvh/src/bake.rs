@@ -0,0 +1,407 @@
+use std::fmt::Display;
+
+use bevy::{
+    asset::RenderAssetUsages,
+    math::bounding::RayCast3d,
+    prelude::*,
+    render::{
+        mesh::{PrimitiveTopology, VertexAttributeValues},
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+    tasks::{ComputeTaskPool, ParallelSlice},
+};
+
+use crate::{
+    BvhSystems,
+    tlas::{TlasCast, TlasCastSettings},
+    util::{cosine_hemisphere, pcg, random},
+};
+
+/// Points baked per parallel task.
+const CHUNK_SIZE: usize = 64;
+
+/// Bakes ambient occlusion and sun shadow into vertex colours or lightmaps on the CPU, by casting
+/// rays into a [crate::tlas::Tlas]. Works headlessly, without a GPU.
+///
+/// Add [BakeLighting] to the [Mesh3d] entities to bake, [BakeProgress] events report how far along
+/// each is.
+pub struct BvhBakePlugin;
+
+impl Plugin for BvhBakePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<BakeProgress>()
+            .add_systems(PostUpdate, bake_lighting.after(BvhSystems::Update));
+    }
+}
+
+/// Bakes this [Mesh3d] against a TLAS it's a member of, see [BvhBakePlugin].
+///
+/// Starts once the mesh has loaded and the entity is in the TLAS, so add it once the rest of the scene
+/// is there too. Removed when the bake is done.
+#[derive(Component, Debug, Clone)]
+pub struct BakeLighting {
+    pub tlas: Entity,
+    pub output: BakeOutput,
+    pub settings: BakeSettings,
+}
+
+impl BakeLighting {
+    pub fn new(tlas: Entity, output: BakeOutput) -> Self {
+        Self {
+            tlas,
+            output,
+            settings: BakeSettings::default(),
+        }
+    }
+
+    pub fn with_settings(mut self, settings: BakeSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+}
+
+/// Where a [BakeLighting] writes ambient occlusion in RGB and sun visibility in alpha.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BakeOutput {
+    /// Replaces the [Mesh3d] with a copy holding the bake in [Mesh::ATTRIBUTE_COLOR].
+    VertexColor,
+    /// Adds a [BakedLightmap] of this size, laid out by [Mesh::ATTRIBUTE_UV_1].
+    ///
+    /// Texels no triangle covers are left white.
+    Lightmap { width: u32, height: u32 },
+}
+
+/// Settings for a [BakeLighting], the same settings and scene always bake the same result.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BakeSettings {
+    /// Hemisphere rays per vertex or texel, 64 by default.
+    pub samples: u32,
+    /// Occluders further than this don't darken the ambient occlusion, 1.0 by default.
+    pub max_distance: f32,
+    /// Direction towards the sun, ``None`` skips the sun shadow and leaves alpha at 1.
+    pub sun_direction: Option<Dir3>,
+    /// Offset of the rays from the surface, so they don't hit it.
+    pub bias: f32,
+    /// Vertices or texels baked each frame, 4096 by default.
+    pub points_per_frame: u32,
+    pub seed: u32,
+}
+
+impl Default for BakeSettings {
+    fn default() -> Self {
+        Self {
+            samples: 64,
+            max_distance: 1.0,
+            sun_direction: None,
+            bias: 1e-3,
+            points_per_frame: 4096,
+            seed: 0,
+        }
+    }
+}
+
+/// Result of a [BakeOutput::Lightmap], ambient occlusion in RGB and sun visibility in alpha.
+#[derive(Component, Debug, Clone)]
+pub struct BakedLightmap(pub Handle<Image>);
+
+/// Sent every frame a [BakeLighting] makes progress.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct BakeProgress {
+    pub entity: Entity,
+    /// Vertices or texels baked so far.
+    pub done: u32,
+    pub total: u32,
+}
+
+impl BakeProgress {
+    pub fn is_finished(&self) -> bool {
+        self.done == self.total
+    }
+
+    /// Progress in ``0..=1``.
+    pub fn fraction(&self) -> f32 {
+        match self.total {
+            0 => 1.0,
+            total => self.done as f32 / total as f32,
+        }
+    }
+}
+
+/// Why a mesh can't be baked.
+#[derive(Debug, Clone)]
+pub enum BakeError {
+    /// The mesh lacks a ``Float32x3`` position or normal, or a ``Float32x2`` UV1 for lightmaps.
+    MissingAttribute(&'static str),
+    /// Lightmaps are rasterized from a triangle list.
+    UnsupportedTopology(PrimitiveTopology),
+}
+
+impl Display for BakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BakeError::MissingAttribute(name) => write!(f, "mesh has no usable {name} attribute"),
+            BakeError::UnsupportedTopology(topology) => {
+                write!(f, "can't bake a lightmap for {topology:?} meshes")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BakeError {}
+
+/// A world space surface point to bake.
+#[derive(Debug, Clone, Copy)]
+struct BakePoint {
+    position: Vec3,
+    normal: Vec3,
+    /// Vertex or texel written.
+    index: u32,
+}
+
+/// A [BakeLighting] in progress.
+#[derive(Component)]
+pub struct BakeJob {
+    points: Vec<BakePoint>,
+    results: Vec<Vec4>,
+}
+
+impl BakeJob {
+    fn new(mesh: &Mesh, trans: &GlobalTransform, output: BakeOutput) -> Result<Self, BakeError> {
+        let positions = mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .and_then(VertexAttributeValues::as_float3)
+            .ok_or(BakeError::MissingAttribute(Mesh::ATTRIBUTE_POSITION.name))?;
+        let normals = mesh
+            .attribute(Mesh::ATTRIBUTE_NORMAL)
+            .and_then(VertexAttributeValues::as_float3)
+            .ok_or(BakeError::MissingAttribute(Mesh::ATTRIBUTE_NORMAL.name))?;
+
+        let affine = trans.affine();
+        let normal_matrix = affine.matrix3.inverse().transpose();
+        let world_point = |position: Vec3, normal: Vec3, index: u32| BakePoint {
+            position: affine.transform_point3(position),
+            normal: Vec3::from(normal_matrix * Vec3A::from(normal)).normalize_or_zero(),
+            index,
+        };
+
+        let points = match output {
+            BakeOutput::VertexColor => positions
+                .iter()
+                .zip(normals)
+                .enumerate()
+                .map(|(i, (p, n))| world_point(Vec3::from(*p), Vec3::from(*n), i as u32))
+                .collect(),
+            BakeOutput::Lightmap { width, height } => {
+                if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+                    return Err(BakeError::UnsupportedTopology(mesh.primitive_topology()));
+                }
+                let Some(VertexAttributeValues::Float32x2(uvs)) =
+                    mesh.attribute(Mesh::ATTRIBUTE_UV_1)
+                else {
+                    return Err(BakeError::MissingAttribute(Mesh::ATTRIBUTE_UV_1.name));
+                };
+                let indices = match mesh.indices() {
+                    Some(indices) => indices.iter().collect::<Vec<_>>(),
+                    None => (0..positions.len()).collect(),
+                };
+
+                // the first triangle covering a texel centre bakes it
+                let size = vec2(width as f32, height as f32);
+                let mut covered = vec![false; (width * height) as usize];
+                let mut points = Vec::new();
+                for tri in indices.chunks_exact(3) {
+                    let tri = [tri[0], tri[1], tri[2]];
+                    let uv = tri.map(|i| Vec2::from(uvs[i]) * size);
+                    let area = (uv[1] - uv[0]).perp_dot(uv[2] - uv[0]);
+                    if area.abs() < 1e-8 {
+                        continue;
+                    }
+                    let min = uv[0].min(uv[1]).min(uv[2]).floor().max(Vec2::ZERO);
+                    let max = uv[0].max(uv[1]).max(uv[2]).ceil().min(size);
+                    for y in min.y as u32..max.y as u32 {
+                        for x in min.x as u32..max.x as u32 {
+                            let index = (y * width + x) as usize;
+                            let centre = vec2(x as f32, y as f32) + 0.5;
+                            let w0 = (uv[2] - uv[1]).perp_dot(centre - uv[1]) / area;
+                            let w1 = (uv[0] - uv[2]).perp_dot(centre - uv[2]) / area;
+                            let w2 = 1.0 - w0 - w1;
+                            if covered[index] || w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                                continue;
+                            }
+                            covered[index] = true;
+                            let lerp = |v: [[f32; 3]; 3]| {
+                                Vec3::from(v[0]) * w0
+                                    + Vec3::from(v[1]) * w1
+                                    + Vec3::from(v[2]) * w2
+                            };
+                            points.push(world_point(
+                                lerp(tri.map(|i| positions[i])),
+                                lerp(tri.map(|i| normals[i])),
+                                index as u32,
+                            ));
+                        }
+                    }
+                }
+                points
+            }
+        };
+        Ok(BakeJob {
+            results: Vec::with_capacity(points.len()),
+            points,
+        })
+    }
+
+    /// Bakes the next ``settings.points_per_frame`` points in parallel.
+    fn step(&mut self, tlas_cast: &TlasCast, tlas_e: Entity, settings: &BakeSettings) {
+        let start = self.results.len();
+        let end = (start + settings.points_per_frame.max(1) as usize).min(self.points.len());
+        let chunks = (&self.points[start..end]).par_chunk_map(
+            ComputeTaskPool::get(),
+            CHUNK_SIZE,
+            |i, points| {
+                points
+                    .iter()
+                    .enumerate()
+                    .map(|(j, point)| {
+                        let seed = (start + i * CHUNK_SIZE + j) as u32;
+                        bake_point(tlas_cast, tlas_e, point, settings, seed)
+                    })
+                    .collect::<Vec<_>>()
+            },
+        );
+        self.results.extend(chunks.into_iter().flatten());
+    }
+
+    fn progress(&self, entity: Entity) -> BakeProgress {
+        BakeProgress {
+            entity,
+            done: self.results.len() as u32,
+            total: self.points.len() as u32,
+        }
+    }
+}
+
+/// Ambient occlusion from cosine weighted hemisphere rays and a shadow ray towards the sun.
+fn bake_point(
+    tlas_cast: &TlasCast,
+    tlas_e: Entity,
+    point: &BakePoint,
+    settings: &BakeSettings,
+    point_seed: u32,
+) -> Vec4 {
+    let cast_settings = TlasCastSettings::default();
+    let origin = point.position + point.normal * settings.bias;
+    let unoccluded = |direction: Vec3, max: f32| {
+        Dir3A::new(direction.into()).is_ok_and(|direction| {
+            let ray = RayCast3d::new(origin, direction, max);
+            tlas_cast
+                .intersect_tlas_any(&ray, tlas_e, &cast_settings)
+                .is_none()
+        })
+    };
+
+    let mut seed = settings.seed ^ point_seed.wrapping_mul(0x9E37_79B9);
+    pcg(&mut seed);
+    let open = (0..settings.samples)
+        .filter(|_| {
+            let direction = cosine_hemisphere(point.normal, random(&mut seed), random(&mut seed));
+            unoccluded(direction, settings.max_distance)
+        })
+        .count();
+    let ao = open as f32 / settings.samples.max(1) as f32;
+
+    let sun = match settings.sun_direction {
+        Some(sun) => (point.normal.dot(*sun) > 0.0 && unoccluded(*sun, 1e30f32)) as u8 as f32,
+        None => 1.0,
+    };
+    vec4(ao, ao, ao, sun)
+}
+
+/// Steps each [BakeLighting] and writes its output once done.
+#[allow(clippy::type_complexity)]
+pub fn bake_lighting(
+    mut commands: Commands,
+    mut requests: Query<(
+        Entity,
+        &BakeLighting,
+        &Mesh3d,
+        &GlobalTransform,
+        Option<&mut BakeJob>,
+    )>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
+    tlas_cast: TlasCast,
+    mut progress: EventWriter<BakeProgress>,
+) {
+    for (e, bake, mesh3d, trans, job) in requests.iter_mut() {
+        let mut new_job = None;
+        let job = match job {
+            Some(job) => job.into_inner(),
+            None => {
+                let Some(mesh) = meshes.get(&mesh3d.0) else {
+                    continue;
+                };
+                if !tlas_cast
+                    .tlases
+                    .get(bake.tlas)
+                    .is_ok_and(|tlas| tlas.leaf_entities.contains(&e))
+                {
+                    continue;
+                }
+                match BakeJob::new(mesh, trans, bake.output) {
+                    Ok(job) => new_job.insert(job),
+                    Err(err) => {
+                        warn!("Can't bake {e}: {err}");
+                        commands.entity(e).remove::<BakeLighting>();
+                        continue;
+                    }
+                }
+            }
+        };
+
+        job.step(&tlas_cast, bake.tlas, &bake.settings);
+        let job_progress = job.progress(e);
+        progress.write(job_progress);
+
+        if job_progress.is_finished() {
+            match bake.output {
+                BakeOutput::VertexColor => {
+                    let Some(mesh) = meshes.get(&mesh3d.0) else {
+                        continue;
+                    };
+                    let mut colors = vec![[1.0; 4]; mesh.count_vertices()];
+                    for (point, color) in job.points.iter().zip(&job.results) {
+                        colors[point.index as usize] = color.to_array();
+                    }
+                    let mut mesh = mesh.clone();
+                    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+                    commands.entity(e).insert(Mesh3d(meshes.add(mesh)));
+                }
+                BakeOutput::Lightmap { width, height } => {
+                    let mut image = Image::new_fill(
+                        Extent3d {
+                            width,
+                            height,
+                            depth_or_array_layers: 1,
+                        },
+                        TextureDimension::D2,
+                        &[255; 4],
+                        TextureFormat::Rgba8Unorm,
+                        RenderAssetUsages::default(),
+                    );
+                    if let Some(data) = &mut image.data {
+                        for (point, color) in job.points.iter().zip(&job.results) {
+                            let offset = point.index as usize * 4;
+                            let color = (*color * 255.0).round().to_array().map(|c| c as u8);
+                            data[offset..offset + 4].copy_from_slice(&color);
+                        }
+                    }
+                    commands.entity(e).insert(BakedLightmap(images.add(image)));
+                }
+            }
+            commands.entity(e).remove::<(BakeLighting, BakeJob)>();
+        } else if let Some(job) = new_job {
+            commands.entity(e).insert(job);
+        }
+    }
+}
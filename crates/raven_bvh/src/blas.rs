@@ -1,5 +1,6 @@
-use crate::{BIN_COUNT, aabb::Aabb3dExt};
+use crate::{aabb::Aabb3dExt, build::BlasBuildQuality, wide::BlasWide};
 use bevy::{math::bounding::Aabb3d, prelude::*, render::mesh::*};
+use std::fmt::{self, Display};
 
 /// Note: we really want this to be 32 bytes, so things layout in on nice 64 bytes pages in memory, using Vec3A instead of Vec3 in
 /// aabb, puts us at 48, instead of 32
@@ -55,58 +56,183 @@ pub struct Blas {
     pub nodes: Vec<BlasNode>,
     pub tris: Vec<Tri>,
     pub triangle_indexs: Vec<usize>,
+    /// Vertex attributes, only kept when asked for, see [Blas::from_mesh].
+    pub attributes: Option<BlasAttributes>,
+    /// How the tree was built, rebuilds use it too.
+    pub quality: BlasBuildQuality,
+    /// Wide layout for SIMD traversal, see [Blas::collapse].
+    pub wide: Option<BlasWide>,
 }
 
-impl From<&Mesh> for Blas {
-    fn from(mesh: &Mesh) -> Self {
-        assert!(
-            matches!(mesh.primitive_topology(), PrimitiveTopology::TriangleList),
-            "`Bvh::from` can only work on `TriangleList`s"
-        );
+/// Add with the spawn helpers to keep vertex normals and UVs in the [Blas], see [Blas::from_mesh].
+#[derive(Component, Default)]
+pub struct BlasKeepAttributes;
 
-        let verts = match mesh
-            .attribute(Mesh::ATTRIBUTE_POSITION)
-            .expect(" Bvh needs Position Attribute")
-        {
-            VertexAttributeValues::Float32x3(vec) => {
-                vec.iter().map(|vec| vec3a(vec[0], vec[1], vec[2]))
-            }
-            _ => unimplemented!(),
-        }
-        .collect::<Vec<_>>();
-
-        // handle indexed geometry and non-indexed geometry
-        let triangles = match mesh.indices() {
-            Some(indexes) => {
-                let indexes = match indexes {
-                    Indices::U32(vec) => vec.iter().map(|i| *i as usize).collect::<Vec<_>>(),
-                    Indices::U16(vec) => vec.iter().map(|i| *i as usize).collect::<Vec<_>>(),
-                };
+impl TryFrom<&Mesh> for Blas {
+    type Error = BlasMeshError;
 
-                let mut triangles = Vec::with_capacity(indexes.len() / 3);
-                for tri_indexes in indexes.chunks(3) {
-                    triangles.push(Tri::new(
-                        verts[tri_indexes[0]],
-                        verts[tri_indexes[1]],
-                        verts[tri_indexes[2]],
-                    ));
-                }
-                triangles
+    fn try_from(mesh: &Mesh) -> Result<Self, Self::Error> {
+        Blas::from_mesh(mesh, false)
+    }
+}
+
+/// Why a [Blas] can't be built from a [Mesh].
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlasMeshError {
+    /// Only ``TriangleList`` and ``TriangleStrip`` have surfaces to hit, lines and points don't.
+    UnsupportedTopology(PrimitiveTopology),
+    MissingPositions,
+    UnsupportedPositionFormat(VertexFormat),
+    /// An index past the end of the vertices.
+    IndexOutOfRange(usize),
+    /// Too few indices for a triangle, or only degenerate strip triangles.
+    NoTriangles,
+}
+
+impl Display for BlasMeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlasMeshError::UnsupportedTopology(topology) => {
+                write!(f, "can't build a Blas from {topology:?}, only triangles")
             }
-            None => {
-                let mut triangles = Vec::with_capacity(verts.len() / 3);
-                for v in verts.chunks(3) {
-                    triangles.push(Tri::new(v[0], v[1], v[2]));
-                }
-                triangles
+            BlasMeshError::MissingPositions => write!(f, "mesh has no vertex positions"),
+            BlasMeshError::UnsupportedPositionFormat(format) => {
+                write!(f, "vertex positions in {format:?} are not supported")
             }
-        };
-        Self::new(triangles)
+            BlasMeshError::IndexOutOfRange(index) => {
+                write!(f, "mesh index {index} is past the end of the vertices")
+            }
+            BlasMeshError::NoTriangles => write!(f, "mesh has no triangles"),
+        }
+    }
+}
+
+impl std::error::Error for BlasMeshError {}
+
+/// Per triangle vertex attributes kept from the source mesh, indexed by triangle like [Blas::tris].
+///
+/// A list is empty when the mesh didn't have the attribute.
+#[derive(Default, Debug, Clone)]
+pub struct BlasAttributes {
+    pub normals: Vec<[Vec3A; 3]>,
+    pub uv0: Vec<[Vec2; 3]>,
+}
+
+impl BlasAttributes {
+    /// Interpolated normal for ``tri_index`` at barycentric ``u``, ``v``, in mesh space.
+    pub fn normal(&self, tri_index: usize, u: f32, v: f32) -> Option<Vec3A> {
+        self.normals
+            .get(tri_index)
+            .map(|n| (n[0] * (1.0 - u - v) + n[1] * u + n[2] * v).normalize_or_zero())
+    }
+
+    /// Interpolated UV0 for ``tri_index`` at barycentric ``u``, ``v``.
+    pub fn uv0(&self, tri_index: usize, u: f32, v: f32) -> Option<Vec2> {
+        self.uv0
+            .get(tri_index)
+            .map(|uv| uv[0] * (1.0 - u - v) + uv[1] * u + uv[2] * v)
     }
 }
 
 impl Blas {
+    /// Builds a BVH from a ``TriangleList`` or ``TriangleStrip`` mesh, when ``keep_attributes`` is set
+    /// vertex normals and UV0 are kept for [crate::tlas::TlasHit].
+    pub fn from_mesh(mesh: &Mesh, keep_attributes: bool) -> Result<Self, BlasMeshError> {
+        Self::from_mesh_with_quality(mesh, keep_attributes, BlasBuildQuality::default())
+    }
+
+    /// Like [Blas::from_mesh], built with a chosen [BlasBuildQuality].
+    pub fn from_mesh_with_quality(
+        mesh: &Mesh,
+        keep_attributes: bool,
+        quality: BlasBuildQuality,
+    ) -> Result<Self, BlasMeshError> {
+        let mut blas = Blas {
+            attributes: keep_attributes.then(BlasAttributes::default),
+            quality,
+            ..default()
+        };
+        blas.update_from_mesh(mesh, None)?;
+        Ok(blas)
+    }
+
+    /// Updates the BVH to the mesh's current vertex positions, skinned by ``joint_matrices`` when given,
+    /// see [crate::refit::skin_joint_matrices].
+    ///
+    /// Refits when the triangle count is unchanged, otherwise fully rebuilds. Kept attributes are updated too.
+    /// On error the BVH is left as it was.
+    pub fn update_from_mesh(
+        &mut self,
+        mesh: &Mesh,
+        joint_matrices: Option<&[Mat4]>,
+    ) -> Result<(), BlasMeshError> {
+        let mut verts = mesh_positions(
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+                .ok_or(BlasMeshError::MissingPositions)?,
+        )?;
+        let tri_indexes = mesh_triangles(mesh, verts.len())?;
+
+        let mut normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals))
+                if self.attributes.is_some() && normals.len() == verts.len() =>
+            {
+                normals.iter().map(|n| Vec3A::from_array(*n)).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        if let Some(joint_matrices) = joint_matrices {
+            skin_vertices(mesh, joint_matrices, &mut verts, &mut normals);
+        }
+
+        let triangles = tri_indexes
+            .iter()
+            .map(|[a, b, c]| Tri::new(verts[*a], verts[*b], verts[*c]))
+            .collect::<Vec<_>>();
+
+        if self.nodes.is_empty() || triangles.len() != self.tris.len() {
+            let attributes = self.attributes.take();
+            let width = self.width();
+            *self = Self::build(triangles, self.quality);
+            self.attributes = attributes;
+            self.collapse(width);
+        } else {
+            self.refit(triangles);
+        }
+
+        if let Some(attributes) = &mut self.attributes {
+            attributes.normals = match normals.is_empty() {
+                true => Vec::new(),
+                false => tri_indexes
+                    .iter()
+                    .map(|tri| tri.map(|i| normals[i]))
+                    .collect(),
+            };
+            attributes.uv0 = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+                Some(VertexAttributeValues::Float32x2(uvs)) if uvs.len() == verts.len() => {
+                    tri_indexes
+                        .iter()
+                        .map(|tri| tri.map(|i| Vec2::from_array(uvs[i])))
+                        .collect()
+                }
+                _ => Vec::new(),
+            };
+        }
+        Ok(())
+    }
+
+    /// No triangles, so nothing can be hit.
+    pub fn is_empty(&self) -> bool {
+        self.tris.is_empty()
+    }
+
+    /// Builds with the default [BlasBuildQuality].
     pub fn new(triangles: Vec<Tri>) -> Blas {
+        Self::build(triangles, BlasBuildQuality::default())
+    }
+
+    /// Builds with a chosen [BlasBuildQuality], see [Blas::stats] to compare them.
+    pub fn build(triangles: Vec<Tri>, quality: BlasBuildQuality) -> Blas {
         let count = triangles.len() as u32;
         let mut nodes = Vec::with_capacity(64);
 
@@ -128,33 +254,61 @@ impl Blas {
             tris: triangles,
             nodes,
             triangle_indexs: (0..count as usize).collect::<Vec<_>>(),
+            attributes: None,
+            quality,
+            wide: None,
         };
 
-        // build the BVH
-        bvh.update_node_bounds(0);
-        bvh.subdivide_node(0);
+        // build the BVH, an empty one keeps its empty root
+        if count == 0 {
+            return bvh;
+        }
+        match quality {
+            BlasBuildQuality::Binned { bins } => {
+                bvh.update_node_bounds(0);
+                bvh.subdivide_node(0, bins.max(2));
+            }
+            BlasBuildQuality::FullSweep => bvh.build_sweep(None),
+            BlasBuildQuality::Spatial { bins, alpha } => {
+                bvh.build_sweep(Some((bins.max(2), alpha)))
+            }
+        }
         bvh
     }
 
-    // pub fn refit(&mut self, triangles: &[Tri]) {
-    //     for i in (0..(self.open_node - 1)).rev() {
-    //         if i != 1 {
-    //             let node = &mut self.nodes[i];
-    //             if node.is_leaf() {
-    //                 // leaf node: adjust bounds to contained triangles
-    //                 self.update_node_bounds(i, triangles);
-    //                 continue;
-    //             }
-    //             // interior node: adjust bounds to child node bounds
-
-    //             let leftChild = &self.nodes[node.left_first as usize];
-    //             let rightChild = &self.nodes[(node.left_first + 1) as usize];
-
-    //             node.aabb_min = leftChild.aabb_min.min(rightChild.aabb_min);
-    //             node.aabb_max = leftChild.aabb_max.max(rightChild.aabb_max);
-    //         }
-    //     }
-    // }
+    /// Moves the triangles and refits the node bounds without changing the tree, so it is much
+    /// faster than a rebuild but the tree gets worse the further triangles move from where it was built.
+    ///
+    /// ``triangles`` must be the same triangles in the same order as the BVH was built with.
+    pub fn refit(&mut self, triangles: Vec<Tri>) {
+        assert_eq!(
+            triangles.len(),
+            self.tris.len(),
+            "refit needs the same triangle count, rebuild instead"
+        );
+        self.tris = triangles;
+
+        // children are always after their parent, so walking back updates children first
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            if node.is_leaf() {
+                // leaf node: adjust bounds to contained triangles
+                self.update_node_bounds(i);
+            } else if node.tri_count == 0 && node.left_first != 0 {
+                // interior node: adjust bounds to child node bounds
+                let left = self.nodes[node.left_first as usize].aabb;
+                let right = self.nodes[node.left_first as usize + 1].aabb;
+                self.nodes[i].aabb = Aabb3d {
+                    min: left.min.min(right.min),
+                    max: left.max.max(right.max),
+                };
+            }
+        }
+
+        if self.wide.is_some() {
+            self.collapse(self.width());
+        }
+    }
 
     fn update_node_bounds(&mut self, node_idx: usize) {
         let node = &mut self.nodes[node_idx];
@@ -169,11 +323,11 @@ impl Blas {
         }
     }
 
-    fn subdivide_node(&mut self, node_idx: usize) {
+    fn subdivide_node(&mut self, node_idx: usize, bins: usize) {
         let node = &self.nodes[node_idx];
 
         // determine split axis using SAH
-        let (axis, split_pos, split_cost) = self.find_best_split_plane(node);
+        let (axis, split_pos, split_cost) = self.find_best_split_plane(node, bins);
         let nosplit_cost = node.calculate_cost();
         if split_cost >= nosplit_cost {
             return;
@@ -216,11 +370,11 @@ impl Blas {
         self.update_node_bounds(right_child_idx as usize);
 
         // recurse
-        self.subdivide_node(left_child_idx as usize);
-        self.subdivide_node(right_child_idx as usize);
+        self.subdivide_node(left_child_idx as usize, bins);
+        self.subdivide_node(right_child_idx as usize, bins);
     }
 
-    fn find_best_split_plane(&self, node: &BlasNode) -> (usize, f32, f32) {
+    fn find_best_split_plane(&self, node: &BlasNode, bins: usize) -> (usize, f32, f32) {
         // determine split axis using SAH
         let mut best_axis = 0;
         let mut split_pos = 0.0f32;
@@ -238,41 +392,41 @@ impl Blas {
                 continue;
             }
             // populate bins
-            let mut bin = [Bin::default(); BIN_COUNT];
-            let mut scale = BIN_COUNT as f32 / (bounds_max - bounds_min);
+            let mut bin = vec![Bin::default(); bins];
+            let mut scale = bins as f32 / (bounds_max - bounds_min);
             for i in 0..node.tri_count {
                 let triangle = &self.tris[self.triangle_indexs[(node.left_first + i) as usize]];
                 let bin_idx =
-                    (BIN_COUNT - 1).min(((triangle.centroid[a] - bounds_min) * scale) as usize);
+                    (bins - 1).min(((triangle.centroid[a] - bounds_min) * scale) as usize);
                 bin[bin_idx].tri_count += 1;
                 bin[bin_idx].bounds.expand(triangle.vertex0);
                 bin[bin_idx].bounds.expand(triangle.vertex1);
                 bin[bin_idx].bounds.expand(triangle.vertex2);
             }
 
-            // gather data for the BINS - 1 planes between the bins
-            let mut left_area = [0.0f32; BIN_COUNT - 1];
-            let mut right_area = [0.0f32; BIN_COUNT - 1];
-            let mut left_count = [0u32; BIN_COUNT - 1];
-            let mut right_count = [0u32; BIN_COUNT - 1];
+            // gather data for the bins - 1 planes between the bins
+            let mut left_area = vec![0.0f32; bins - 1];
+            let mut right_area = vec![0.0f32; bins - 1];
+            let mut left_count = vec![0u32; bins - 1];
+            let mut right_count = vec![0u32; bins - 1];
             let mut left_box = Aabb3d::init();
             let mut right_box = Aabb3d::init();
             let mut left_sum = 0u32;
             let mut right_sum = 0u32;
-            for i in 0..(BIN_COUNT - 1) {
+            for i in 0..(bins - 1) {
                 left_sum += bin[i].tri_count;
                 left_count[i] = left_sum;
                 left_box.expand_aabb(&bin[i].bounds);
                 left_area[i] = left_box.area();
-                right_sum += bin[BIN_COUNT - 1 - i].tri_count;
-                right_count[BIN_COUNT - 2 - i] = right_sum;
-                right_box.expand_aabb(&bin[BIN_COUNT - 1 - i].bounds);
-                right_area[BIN_COUNT - 2 - i] = right_box.area();
+                right_sum += bin[bins - 1 - i].tri_count;
+                right_count[bins - 2 - i] = right_sum;
+                right_box.expand_aabb(&bin[bins - 1 - i].bounds);
+                right_area[bins - 2 - i] = right_box.area();
             }
 
-            // calculate SAH cost for the 7 planes
-            scale = (bounds_max - bounds_min) / BIN_COUNT as f32;
-            for i in 0..BIN_COUNT - 1 {
+            // calculate SAH cost for the planes
+            scale = (bounds_max - bounds_min) / bins as f32;
+            for i in 0..bins - 1 {
                 let plane_cost =
                     left_count[i] as f32 * left_area[i] + right_count[i] as f32 * right_area[i];
                 if plane_cost < best_cost {
@@ -305,6 +459,110 @@ impl Tri {
     }
 }
 
+/// Vertex positions as floats, normalized formats are mapped to -1..1 or 0..1 and a 4th component is ignored.
+///
+/// [Mesh::insert_attribute] only takes ``Float32x3`` for [Mesh::ATTRIBUTE_POSITION], others come from
+/// a custom attribute sharing its id, like quantized glTF positions.
+fn mesh_positions(values: &VertexAttributeValues) -> Result<Vec<Vec3A>, BlasMeshError> {
+    fn convert<T: Copy, const N: usize>(values: &[[T; N]], f: impl Fn(T) -> f32) -> Vec<Vec3A> {
+        values
+            .iter()
+            .map(|v| vec3a(f(v[0]), f(v[1]), f(v[2])))
+            .collect()
+    }
+
+    let positions = match values {
+        VertexAttributeValues::Float32x3(v) => convert(v, |x| x),
+        VertexAttributeValues::Float32x4(v) => convert(v, |x| x),
+        VertexAttributeValues::Sint32x3(v) => convert(v, |x| x as f32),
+        VertexAttributeValues::Sint32x4(v) => convert(v, |x| x as f32),
+        VertexAttributeValues::Uint32x3(v) => convert(v, |x| x as f32),
+        VertexAttributeValues::Uint32x4(v) => convert(v, |x| x as f32),
+        VertexAttributeValues::Sint16x4(v) => convert(v, f32::from),
+        VertexAttributeValues::Uint16x4(v) => convert(v, f32::from),
+        VertexAttributeValues::Snorm16x4(v) => {
+            convert(v, |x| (f32::from(x) / i16::MAX as f32).max(-1.0))
+        }
+        VertexAttributeValues::Unorm16x4(v) => convert(v, |x| f32::from(x) / u16::MAX as f32),
+        VertexAttributeValues::Sint8x4(v) => convert(v, f32::from),
+        VertexAttributeValues::Uint8x4(v) => convert(v, f32::from),
+        VertexAttributeValues::Snorm8x4(v) => {
+            convert(v, |x| (f32::from(x) / i8::MAX as f32).max(-1.0))
+        }
+        VertexAttributeValues::Unorm8x4(v) => convert(v, |x| f32::from(x) / u8::MAX as f32),
+        _ => {
+            return Err(BlasMeshError::UnsupportedPositionFormat(
+                VertexFormat::from(values),
+            ));
+        }
+    };
+    Ok(positions)
+}
+
+/// Vertex indexes of each triangle, strips are unrolled keeping their winding and dropping degenerate triangles.
+fn mesh_triangles(mesh: &Mesh, vertex_count: usize) -> Result<Vec<[usize; 3]>, BlasMeshError> {
+    // handle indexed geometry and non-indexed geometry
+    let indexes = match mesh.indices() {
+        Some(Indices::U32(vec)) => vec.iter().map(|i| *i as usize).collect::<Vec<_>>(),
+        Some(Indices::U16(vec)) => vec.iter().map(|i| *i as usize).collect::<Vec<_>>(),
+        None => (0..vertex_count).collect::<Vec<_>>(),
+    };
+    if let Some(index) = indexes.iter().find(|i| **i >= vertex_count) {
+        return Err(BlasMeshError::IndexOutOfRange(*index));
+    }
+
+    let tri_indexes: Vec<[usize; 3]> = match mesh.primitive_topology() {
+        PrimitiveTopology::TriangleList => indexes
+            .chunks_exact(3)
+            .map(|tri| [tri[0], tri[1], tri[2]])
+            .collect(),
+        // every other triangle is flipped, like Mesh::triangles
+        PrimitiveTopology::TriangleStrip => indexes
+            .windows(3)
+            .enumerate()
+            .map(|(i, tri)| match i % 2 {
+                0 => [tri[0], tri[1], tri[2]],
+                _ => [tri[1], tri[0], tri[2]],
+            })
+            .filter(|[a, b, c]| a != b && b != c && a != c)
+            .collect(),
+        topology => return Err(BlasMeshError::UnsupportedTopology(topology)),
+    };
+    if tri_indexes.is_empty() {
+        return Err(BlasMeshError::NoTriangles);
+    }
+    Ok(tri_indexes)
+}
+
+/// Blends each vertex by its joint weights, see [Blas::update_from_mesh].
+fn skin_vertices(mesh: &Mesh, joint_matrices: &[Mat4], verts: &mut [Vec3A], normals: &mut [Vec3A]) {
+    let (
+        Some(VertexAttributeValues::Uint16x4(joints)),
+        Some(VertexAttributeValues::Float32x4(weights)),
+    ) = (
+        mesh.attribute(Mesh::ATTRIBUTE_JOINT_INDEX),
+        mesh.attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT),
+    )
+    else {
+        return;
+    };
+
+    for (i, (joints, weights)) in joints.iter().zip(weights.iter()).enumerate() {
+        let mut skin = Mat4::ZERO;
+        for (joint, weight) in joints.iter().zip(weights.iter()) {
+            if let Some(joint_matrix) = joint_matrices.get(*joint as usize) {
+                skin += *joint_matrix * *weight;
+            }
+        }
+        if let Some(vert) = verts.get_mut(i) {
+            *vert = skin.transform_point3a(*vert);
+        }
+        if let Some(normal) = normals.get_mut(i) {
+            *normal = skin.transform_vector3a(*normal).normalize_or_zero();
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 struct Bin {
     bounds: Aabb3d,
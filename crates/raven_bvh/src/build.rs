@@ -0,0 +1,403 @@
+use bevy::{math::bounding::Aabb3d, prelude::*};
+
+use crate::{
+    BIN_COUNT,
+    aabb::Aabb3dExt,
+    blas::{Blas, BlasNode, Tri},
+};
+
+/// Deepest a sweep or spatial build will go before making a leaf.
+const MAX_DEPTH: usize = 64;
+/// Cost of visiting a node relative to testing a triangle, used by [Blas::stats] and the sweep builders.
+const TRAVERSAL_COST: f32 = 1.0;
+
+/// How a [Blas] is built, trading build time for traversal speed.
+///
+/// Use [Blas::stats] to compare the results.
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub enum BlasBuildQuality {
+    /// SAH evaluated at ``bins - 1`` planes per axis, fast to build, good for meshes that rebuild often.
+    Binned { bins: usize },
+    /// SAH evaluated between every triangle on every axis, slower to build but a better tree.
+    FullSweep,
+    /// Full sweep plus spatial splits (SBVH), which clip triangles straddling a plane into both
+    /// children so large or long triangles overlap less, best for static level geometry.
+    ///
+    /// Spatial splits are only tried when the best object split's children overlap by more than
+    /// ``alpha`` of the root's surface area. A triangle may be in more than one leaf.
+    Spatial { bins: usize, alpha: f32 },
+}
+
+impl Default for BlasBuildQuality {
+    fn default() -> Self {
+        BlasBuildQuality::Binned { bins: BIN_COUNT }
+    }
+}
+
+impl BlasBuildQuality {
+    /// [BlasBuildQuality::Spatial] with commonly used settings.
+    pub fn spatial() -> Self {
+        BlasBuildQuality::Spatial {
+            bins: 32,
+            alpha: 1e-5,
+        }
+    }
+}
+
+/// Quality of a built [Blas], see [Blas::stats].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Reflect)]
+pub struct BlasStats {
+    /// Expected cost of a random ray, traversal and triangle tests both cost 1, lower is better.
+    pub sah_cost: f32,
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub max_depth: usize,
+    pub average_depth: f32,
+    pub min_leaf_tris: usize,
+    pub max_leaf_tris: usize,
+    pub average_leaf_tris: f32,
+    /// Triangles referenced by leaves, more than the triangle count when spatial splits duplicated some.
+    pub triangle_references: usize,
+}
+
+impl Blas {
+    /// SAH cost, depth and leaf statistics of the tree.
+    pub fn stats(&self) -> BlasStats {
+        let mut stats = BlasStats::default();
+        let Some(root) = self.nodes.first() else {
+            return stats;
+        };
+        let root_area = root.aabb.area().max(f32::EPSILON);
+        stats.node_count = self.nodes.len();
+        stats.min_leaf_tris = usize::MAX;
+
+        let mut depth_sum = 0;
+        let mut cost = 0.0;
+        let mut stack = vec![(0usize, 0usize)];
+        while let Some((node_idx, depth)) = stack.pop() {
+            let node = &self.nodes[node_idx];
+            // an empty root is a leaf too
+            if node.is_leaf() || node.left_first == 0 {
+                let tris = node.tri_count as usize;
+                stats.leaf_count += 1;
+                stats.triangle_references += tris;
+                stats.min_leaf_tris = stats.min_leaf_tris.min(tris);
+                stats.max_leaf_tris = stats.max_leaf_tris.max(tris);
+                stats.max_depth = stats.max_depth.max(depth);
+                depth_sum += depth;
+                cost += node.aabb.area() * tris as f32;
+            } else {
+                cost += TRAVERSAL_COST * node.aabb.area();
+                stack.push((node.left_first as usize, depth + 1));
+                stack.push((node.left_first as usize + 1, depth + 1));
+            }
+        }
+
+        stats.sah_cost = cost / root_area;
+        stats.average_depth = depth_sum as f32 / stats.leaf_count as f32;
+        stats.average_leaf_tris = stats.triangle_references as f32 / stats.leaf_count as f32;
+        stats
+    }
+
+    /// Builds the tree from triangle references, sweeping every split on every axis, with
+    /// ``spatial`` set to ``(bins, alpha)`` spatial splits are tried too.
+    pub(crate) fn build_sweep(&mut self, spatial: Option<(usize, f32)>) {
+        let refs = self
+            .tris
+            .iter()
+            .enumerate()
+            .map(|(tri, triangle)| Reference {
+                tri,
+                aabb: tri_bounds(triangle),
+            })
+            .collect::<Vec<_>>();
+        let bounds = union(&refs);
+
+        self.nodes.clear();
+        self.nodes.push(BlasNode::default());
+        self.triangle_indexs.clear();
+        let mut builder = SweepBuilder {
+            blas: self,
+            spatial,
+            root_area: bounds.area(),
+        };
+        builder.build_node(0, refs, bounds, 0);
+    }
+}
+
+/// A triangle, or the part of one in a node after a spatial split.
+#[derive(Debug, Clone, Copy)]
+struct Reference {
+    tri: usize,
+    aabb: Aabb3d,
+}
+
+enum Split {
+    Object { axis: usize, count: usize },
+    Spatial { axis: usize, position: f32 },
+}
+
+struct SweepBuilder<'a> {
+    blas: &'a mut Blas,
+    spatial: Option<(usize, f32)>,
+    root_area: f32,
+}
+
+impl SweepBuilder<'_> {
+    fn build_node(
+        &mut self,
+        node_idx: usize,
+        mut refs: Vec<Reference>,
+        bounds: Aabb3d,
+        depth: usize,
+    ) {
+        self.blas.nodes[node_idx].aabb = bounds;
+        if refs.len() <= 1 || depth >= MAX_DEPTH {
+            self.make_leaf(node_idx, &refs);
+            return;
+        }
+
+        // best object split, keeping its children's bounds to decide on spatial splits
+        let mut best_cost = f32::MAX;
+        let mut best_split = None;
+        let mut overlap = None;
+        let mut right_area = vec![0.0f32; refs.len()];
+        for axis in 0..3 {
+            sort_axis(&mut refs, axis);
+            let mut right_box = Aabb3d::init();
+            for i in (1..refs.len()).rev() {
+                right_box.expand_aabb(&refs[i].aabb);
+                right_area[i] = right_box.area();
+            }
+            let mut left_box = Aabb3d::init();
+            for i in 1..refs.len() {
+                left_box.expand_aabb(&refs[i - 1].aabb);
+                let cost = i as f32 * left_box.area() + (refs.len() - i) as f32 * right_area[i];
+                if cost < best_cost {
+                    best_cost = cost;
+                    best_split = Some(Split::Object { axis, count: i });
+                    overlap = Some((left_box, i));
+                }
+            }
+        }
+
+        if let Some((bins, alpha)) = self.spatial
+            && let Some((left_box, count)) = overlap
+            && let Some(Split::Object { axis, .. }) = best_split
+        {
+            // find the right box of the best object split again
+            sort_axis(&mut refs, axis);
+            let right_box = union(&refs[count..]);
+            let overlap_min = left_box.min.max(right_box.min);
+            let overlap_max = left_box.max.min(right_box.max);
+            let overlap_area = match overlap_min.cmplt(overlap_max).all() {
+                true => Aabb3d {
+                    min: overlap_min,
+                    max: overlap_max,
+                }
+                .area(),
+                false => 0.0,
+            };
+            if overlap_area > alpha * self.root_area
+                && let Some((axis, position, cost)) = self.find_spatial_split(&refs, &bounds, bins)
+                && cost < best_cost
+            {
+                best_cost = cost;
+                best_split = Some(Split::Spatial { axis, position });
+            }
+        }
+
+        // splitting adds a node to visit, so small nodes stay leaves
+        if best_cost + TRAVERSAL_COST * bounds.area() >= refs.len() as f32 * bounds.area() {
+            self.make_leaf(node_idx, &refs);
+            return;
+        }
+
+        let (left, right) = match best_split {
+            Some(Split::Object { axis, count }) => {
+                sort_axis(&mut refs, axis);
+                let right = refs.split_off(count);
+                (refs, right)
+            }
+            Some(Split::Spatial { axis, position }) => {
+                self.spatial_partition(&refs, axis, position)
+            }
+            None => (Vec::new(), Vec::new()),
+        };
+        // clipping can leave a side empty, keep them together
+        if left.is_empty() || right.is_empty() {
+            let mut refs = left;
+            refs.extend(right);
+            self.make_leaf(node_idx, &refs);
+            return;
+        }
+
+        // children are kept in pairs after their parent
+        let left_idx = self.blas.nodes.len();
+        self.blas.nodes.push(BlasNode::default());
+        self.blas.nodes.push(BlasNode::default());
+        self.blas.nodes[node_idx].left_first = left_idx as u32;
+        self.blas.nodes[node_idx].tri_count = 0;
+
+        let left_bounds = union(&left);
+        let right_bounds = union(&right);
+        self.build_node(left_idx, left, left_bounds, depth + 1);
+        self.build_node(left_idx + 1, right, right_bounds, depth + 1);
+    }
+
+    fn make_leaf(&mut self, node_idx: usize, refs: &[Reference]) {
+        let node = &mut self.blas.nodes[node_idx];
+        node.left_first = self.blas.triangle_indexs.len() as u32;
+        node.tri_count = refs.len() as u32;
+        self.blas
+            .triangle_indexs
+            .extend(refs.iter().map(|reference| reference.tri));
+    }
+
+    /// Best spatial split plane over ``bins`` bins per axis of the node bounds, as ``(axis, position, cost)``.
+    fn find_spatial_split(
+        &self,
+        refs: &[Reference],
+        bounds: &Aabb3d,
+        bins: usize,
+    ) -> Option<(usize, f32, f32)> {
+        let mut best: Option<(usize, f32, f32)> = None;
+        for axis in 0..3 {
+            let bounds_min = bounds.min[axis];
+            let extent = bounds.max[axis] - bounds_min;
+            if extent <= 0.0 {
+                continue;
+            }
+            let scale = bins as f32 / extent;
+            let bin_width = extent / bins as f32;
+            let bin_of = |value: f32| {
+                ((value - bounds_min) * scale)
+                    .max(0.0)
+                    .min((bins - 1) as f32) as usize
+            };
+
+            let mut bin_bounds = vec![Aabb3d::init(); bins];
+            let mut entries = vec![0u32; bins];
+            let mut exits = vec![0u32; bins];
+            for reference in refs {
+                let first = bin_of(reference.aabb.min[axis]);
+                let last = bin_of(reference.aabb.max[axis]);
+                entries[first] += 1;
+                exits[last] += 1;
+                let tri = &self.blas.tris[reference.tri];
+                for (bin, bin_box) in bin_bounds.iter_mut().enumerate().take(last + 1).skip(first) {
+                    let min = bounds_min + bin as f32 * bin_width;
+                    if let Some(clipped) =
+                        clip_bounds(tri, &reference.aabb, axis, min, min + bin_width)
+                    {
+                        bin_box.expand_aabb(&clipped);
+                    }
+                }
+            }
+
+            let mut right_area = vec![0.0f32; bins];
+            let mut right_count = vec![0u32; bins];
+            let mut right_box = Aabb3d::init();
+            let mut right_sum = 0;
+            for i in (1..bins).rev() {
+                right_box.expand_aabb(&bin_bounds[i]);
+                right_sum += exits[i];
+                right_area[i] = right_box.area();
+                right_count[i] = right_sum;
+            }
+            let mut left_box = Aabb3d::init();
+            let mut left_sum = 0;
+            for i in 1..bins {
+                left_box.expand_aabb(&bin_bounds[i - 1]);
+                left_sum += entries[i - 1];
+                if left_sum == 0 || right_count[i] == 0 {
+                    continue;
+                }
+                let cost =
+                    left_sum as f32 * left_box.area() + right_count[i] as f32 * right_area[i];
+                if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, bounds_min + i as f32 * bin_width, cost));
+                }
+            }
+        }
+        best
+    }
+
+    /// Splits references at a plane, clipping those straddling it into both sides.
+    fn spatial_partition(
+        &self,
+        refs: &[Reference],
+        axis: usize,
+        position: f32,
+    ) -> (Vec<Reference>, Vec<Reference>) {
+        let mut left = Vec::with_capacity(refs.len());
+        let mut right = Vec::with_capacity(refs.len());
+        for reference in refs {
+            if reference.aabb.max[axis] <= position {
+                left.push(*reference);
+            } else if reference.aabb.min[axis] >= position {
+                right.push(*reference);
+            } else {
+                let tri = &self.blas.tris[reference.tri];
+                if let Some(aabb) = clip_bounds(tri, &reference.aabb, axis, f32::MIN, position) {
+                    left.push(Reference { aabb, ..*reference });
+                }
+                if let Some(aabb) = clip_bounds(tri, &reference.aabb, axis, position, f32::MAX) {
+                    right.push(Reference { aabb, ..*reference });
+                }
+            }
+        }
+        (left, right)
+    }
+}
+
+/// Sorts by center on ``axis``, ties by triangle so sorting again gives the same order.
+fn sort_axis(refs: &mut [Reference], axis: usize) {
+    refs.sort_unstable_by(|a, b| {
+        let center_a = a.aabb.min[axis] + a.aabb.max[axis];
+        let center_b = b.aabb.min[axis] + b.aabb.max[axis];
+        center_a.total_cmp(&center_b).then(a.tri.cmp(&b.tri))
+    });
+}
+
+fn union(refs: &[Reference]) -> Aabb3d {
+    let mut bounds = Aabb3d::init();
+    for reference in refs {
+        bounds.expand_aabb(&reference.aabb);
+    }
+    bounds
+}
+
+fn tri_bounds(tri: &Tri) -> Aabb3d {
+    let mut bounds = Aabb3d::init();
+    bounds.expand(tri.vertex0);
+    bounds.expand(tri.vertex1);
+    bounds.expand(tri.vertex2);
+    bounds
+}
+
+/// Bounds of the part of ``tri`` between ``min`` and ``max`` on ``axis``, kept within ``aabb``.
+fn clip_bounds(tri: &Tri, aabb: &Aabb3d, axis: usize, min: f32, max: f32) -> Option<Aabb3d> {
+    let mut clipped = Aabb3d::init();
+    let verts = [tri.vertex0, tri.vertex1, tri.vertex2];
+    for i in 0..3 {
+        let a = verts[i];
+        let b = verts[(i + 1) % 3];
+        if (min..=max).contains(&a[axis]) {
+            clipped.expand(a);
+        }
+        // where the edge crosses either plane
+        for plane in [min, max] {
+            if (a[axis] - plane) * (b[axis] - plane) < 0.0 {
+                let t = (plane - a[axis]) / (b[axis] - a[axis]);
+                clipped.expand(a + (b - a) * t);
+            }
+        }
+    }
+
+    clipped.min = clipped.min.max(aabb.min);
+    clipped.max = clipped.max.min(aabb.max);
+    clipped.min[axis] = clipped.min[axis].max(min);
+    clipped.max[axis] = clipped.max[axis].min(max);
+    clipped.min.cmple(clipped.max).all().then_some(clipped)
+}
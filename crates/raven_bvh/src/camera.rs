@@ -1,6 +1,11 @@
 use crate::BvhSystems;
 
-use crate::tlas::TlasCast;
+use crate::{
+    packet::RayPacket,
+    path_trace::{PathTraceScene, pixel_seed},
+    tlas::{TlasCast, TlasCastSettings},
+    util::{BlasHit, random},
+};
 
 use bevy::{
     asset::RenderAssetUsages,
@@ -9,6 +14,7 @@ use bevy::{
     render::render_resource::{Extent3d, TextureDimension, TextureFormat},
     tasks::{ComputeTaskPool, ParallelSliceMut},
 };
+use std::path::PathBuf;
 
 /// Not something you would use in production, but great for debugging ray casting
 /// and benchmarking against [`Bvh`] and [`Tlas`].
@@ -18,7 +24,12 @@ impl Plugin for TlasCameraPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
             PostUpdate,
-            (init_camera_image, render_camera, camera_ui)
+            (
+                init_camera_image,
+                render_camera,
+                save_camera_image,
+                camera_ui,
+            )
                 .chain()
                 .after(BvhSystems::Update)
                 .in_set(BvhSystems::Camera),
@@ -26,12 +37,56 @@ impl Plugin for TlasCameraPlugin {
     }
 }
 
+/// What a [TlasCamera] writes for each pixel, misses are black.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum TlasCameraMode {
+    /// The barycentric coordinates of the hit as RGB.
+    #[default]
+    Barycentric,
+    /// Linear depth along the camera forward, white at the camera fading to black at ``far``.
+    Depth { far: f32 },
+    /// World space normal, the vertex normal when the [crate::Blas] kept it, mapped to ``0..1``.
+    Normal,
+    /// A colour hashed from the hit entity.
+    EntityId,
+    /// TLAS and BLAS nodes visited by each ray, blue for none to red at ``max_visits``.
+    ///
+    /// Always traced one ray per pixel, see [TlasCast::traversal_cost].
+    Heatmap { max_visits: u32 },
+    /// Progressive Monte-Carlo path tracing of diffuse [StandardMaterial]s, a reference to validate
+    /// lighting against.
+    ///
+    /// Lit by the emissive colours, the first [DirectionalLight] and ``sky`` for rays leaving the
+    /// scene, with ``bounces`` diffuse bounces. Adds one sample per pixel each frame, restarting when
+    /// the camera, the mode or a light changes, see [TlasCamera::sample_count].
+    PathTrace { bounces: u32, sky: LinearRgba },
+}
+
+/// Size of the square tiles path traced in parallel.
+const TILE_SIZE: u32 = 16;
+
 #[derive(Component)]
 pub struct TlasCamera {
     pub width: u32,
     pub height: u32,
     pub tlas: Entity,
     pub image: Option<Handle<Image>>,
+    /// Trace 2x2 pixel tiles as a [RayPacket], otherwise one ray per pixel.
+    pub ray_packets: bool,
+    pub mode: TlasCameraMode,
+    /// Saves the image as a PNG here once it's rendered, then clears it, for headless apps and tests.
+    ///
+    /// In [TlasCameraMode::PathTrace] the save waits for [TlasCamera::save_sample_count] samples.
+    pub save_path: Option<PathBuf>,
+    /// Samples to accumulate before saving in [TlasCameraMode::PathTrace].
+    pub save_sample_count: u32,
+    /// Samples accumulated per pixel in [TlasCameraMode::PathTrace], set to 0 to restart, like
+    /// after moving objects.
+    pub sample_count: u32,
+    /// Sum of the path traced samples of each pixel.
+    accumulation: Vec<Vec3>,
+    /// View the accumulation was rendered from.
+    accumulated_view: Option<(GlobalTransform, TlasCameraMode)>,
 }
 
 impl TlasCamera {
@@ -41,8 +96,35 @@ impl TlasCamera {
             height,
             tlas,
             image: None,
+            ray_packets: true,
+            mode: TlasCameraMode::default(),
+            save_path: None,
+            save_sample_count: 1,
+            sample_count: 0,
+            accumulation: Vec::new(),
+            accumulated_view: None,
         }
     }
+
+    pub fn with_ray_packets(mut self, ray_packets: bool) -> Self {
+        self.ray_packets = ray_packets;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: TlasCameraMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_save_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.save_path = Some(path.into());
+        self
+    }
+
+    pub fn with_save_sample_count(mut self, save_sample_count: u32) -> Self {
+        self.save_sample_count = save_sample_count;
+        self
+    }
 }
 
 pub fn init_camera_image(
@@ -66,16 +148,32 @@ pub fn init_camera_image(
 }
 
 pub fn render_camera(
-    cameras: Query<(&TlasCamera, &GlobalTransform)>,
+    mut cameras: Query<(&mut TlasCamera, &GlobalTransform)>,
     mut images: ResMut<Assets<Image>>,
     tlas_cast: TlasCast,
+    scene: PathTraceScene,
 ) {
-    for (bvh_camera, trans) in cameras.iter() {
-        if let Some(image) = &bvh_camera.image {
-            let image = images.get_mut(image).unwrap();
+    for (mut bvh_camera, trans) in cameras.iter_mut() {
+        if let Some(image) = bvh_camera.image.clone() {
+            let image = images.get_mut(&image).unwrap();
+
+            // restart the accumulation when the view changes
+            if let TlasCameraMode::PathTrace { .. } = bvh_camera.mode {
+                let view = Some((*trans, bvh_camera.mode));
+                let pixel_count = (bvh_camera.width * bvh_camera.height) as usize;
+                if bvh_camera.accumulated_view != view
+                    || scene.lights_changed()
+                    || bvh_camera.accumulation.len() != pixel_count
+                {
+                    bvh_camera.accumulated_view = view;
+                    bvh_camera.sample_count = 0;
+                }
+                if bvh_camera.sample_count == 0 {
+                    bvh_camera.accumulation = vec![Vec3::ZERO; pixel_count];
+                }
+            }
 
             let vfov: f32 = 45.0; // vertical field of view
-            let focus_dist: f32 = 1.0; // TODO: not using this yet
 
             let aspect_ratio = bvh_camera.width as f32 / bvh_camera.height as f32;
             let theta = vfov * std::f32::consts::PI / 180.0;
@@ -83,48 +181,153 @@ pub fn render_camera(
             let viewport_height = 2.0 * half_height;
             let viewport_width = aspect_ratio * viewport_height;
             let origin = trans.translation();
-            let w = -trans.forward().as_vec3();
+            let forward = trans.forward().as_vec3();
+            let w = -forward;
             let u = trans.right().as_vec3();
             let v = trans.up().as_vec3();
 
-            let horizontal = focus_dist * viewport_width * u;
-            let vertical = focus_dist * viewport_height * v;
+            // image plane 1 unit in front of the camera
+            let horizontal = viewport_width * u;
+            let vertical = viewport_height * v;
 
-            let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - focus_dist * w;
+            let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - w;
 
-            // TODO: Make this acutally tilings, currenty this just takes a slice pixels in a row
-            const PIXEL_TILE_COUNT: usize = 64;
-            const PIXEL_TILE: usize = 4 * PIXEL_TILE_COUNT;
-            if let Some(data) = &mut image.data {
-                data.par_chunk_map_mut(ComputeTaskPool::get(), PIXEL_TILE, |i, pixels| {
-                    for pixel_offset in 0..(pixels.len() / 4) {
-                        // generate pixel offset and ray for this pixel
-                        let index = i * PIXEL_TILE_COUNT + pixel_offset;
-                        let offset = pixel_offset * 4;
-
-                        let x = index as u32 % bvh_camera.width;
-                        let y = index as u32 / bvh_camera.width;
-
-                        let u = x as f32 / bvh_camera.width as f32;
-                        let v = 1.0 - (y as f32 / bvh_camera.height as f32);
-
-                        let direction = lower_left_corner + u * horizontal + v * vertical - origin;
-                        let ray =
-                            RayCast3d::new(origin, Dir3A::new(direction.into()).unwrap(), 1e30f32);
-
-                        // intersect the ray with the TLAS
-                        let color = if let Some((_e, hit)) =
-                            tlas_cast.intersect_tlas(&ray, bvh_camera.tlas)
-                        {
-                            vec3(hit.u, hit.v, 1.0 - (hit.u + hit.v)) * 255.0
-                        } else {
-                            Vec3::ZERO
-                        };
+            let pixel_ray = |x: f32, y: f32| {
+                let u = x / bvh_camera.width as f32;
+                let v = 1.0 - (y / bvh_camera.height as f32);
+                let direction = lower_left_corner + u * horizontal + v * vertical - origin;
+                RayCast3d::new(origin, Dir3A::new(direction.into()).unwrap(), 1e30f32)
+            };
+
+            if let TlasCameraMode::PathTrace { bounces, sky } = bvh_camera.mode {
+                let (width, height) = (bvh_camera.width, bvh_camera.height);
+                let (tlas, sample) = (bvh_camera.tlas, bvh_camera.sample_count);
+                let sky = vec3(sky.red, sky.green, sky.blue);
+                let sun = scene.sun();
+                let (pixel_ray, tlas_cast, scene) = (&pixel_ray, &tlas_cast, &scene);
+                let tiles = ComputeTaskPool::get().scope(|s| {
+                    for y0 in (0..height).step_by(TILE_SIZE as usize) {
+                        for x0 in (0..width).step_by(TILE_SIZE as usize) {
+                            s.spawn(async move {
+                                let mut samples =
+                                    Vec::with_capacity((TILE_SIZE * TILE_SIZE) as usize);
+                                for y in y0..(y0 + TILE_SIZE).min(height) {
+                                    for x in x0..(x0 + TILE_SIZE).min(width) {
+                                        let index = y * width + x;
+                                        let mut seed = pixel_seed(index, sample);
+                                        // jitter inside the pixel to anti-alias
+                                        let ray = pixel_ray(
+                                            x as f32 + random(&mut seed),
+                                            y as f32 + random(&mut seed),
+                                        );
+                                        let radiance = scene.trace_path(
+                                            tlas_cast, tlas, ray, bounces, sky, sun, &mut seed,
+                                        );
+                                        samples.push((index as usize, radiance));
+                                    }
+                                }
+                                samples
+                            });
+                        }
+                    }
+                });
+
+                bvh_camera.sample_count += 1;
+                let sample_count = bvh_camera.sample_count as f32;
+                if let Some(data) = &mut image.data {
+                    for (index, radiance) in tiles.into_iter().flatten() {
+                        let sum = &mut bvh_camera.accumulation[index];
+                        *sum += radiance;
+                        let mean = *sum / sample_count;
+                        let color = srgb(Color::linear_rgb(mean.x, mean.y, mean.z));
+                        data[index * 4..index * 4 + 4].copy_from_slice(&[
+                            color.x as u8,
+                            color.y as u8,
+                            color.z as u8,
+                            255,
+                        ]);
+                    }
+                }
+                continue;
+            }
+            let color = |ray: &RayCast3d, hit: Option<(Entity, BlasHit)>| {
+                let Some((e, hit)) = hit else {
+                    return Vec3::ZERO;
+                };
+                match bvh_camera.mode {
+                    TlasCameraMode::Depth { far } => {
+                        let depth = hit.distance * ray.direction.dot(forward.into());
+                        Vec3::splat((1.0 - depth / far).clamp(0.0, 1.0) * 255.0)
+                    }
+                    TlasCameraMode::Normal => match tlas_cast.tlas_hit(ray, e, hit) {
+                        Some(hit) => (hit.vertex_normal.unwrap_or(hit.normal) * 0.5 + 0.5) * 255.0,
+                        None => Vec3::ZERO,
+                    },
+                    TlasCameraMode::EntityId => {
+                        let hash = e.to_bits().wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 40;
+                        srgb(Color::hsl((hash % 360) as f32, 0.7, 0.6))
+                    }
+                    _ => vec3(hit.u, hit.v, 1.0 - (hit.u + hit.v)) * 255.0,
+                }
+            };
+            let single_ray_color = |ray: &RayCast3d| match bvh_camera.mode {
+                TlasCameraMode::Heatmap { max_visits } => {
+                    let visits = tlas_cast.traversal_cost(
+                        ray,
+                        bvh_camera.tlas,
+                        &TlasCastSettings::default(),
+                    );
+                    let heat = (visits as f32 / max_visits.max(1) as f32).min(1.0);
+                    srgb(Color::hsl((1.0 - heat) * 240.0, 1.0, 0.5))
+                }
+                _ => color(
+                    ray,
+                    tlas_cast.intersect_tlas(ray, bvh_camera.tlas, &TlasCastSettings::default()),
+                ),
+            };
+            let ray_packets = bvh_camera.ray_packets
+                && !matches!(bvh_camera.mode, TlasCameraMode::Heatmap { .. });
 
+            // tiles of 2 rows, traced as 2x2 packets
+            let width = bvh_camera.width;
+            let tile_rows = 4 * width as usize * 2;
+            if let Some(data) = &mut image.data {
+                data.par_chunk_map_mut(ComputeTaskPool::get(), tile_rows, |i, pixels| {
+                    let y0 = i as u32 * 2;
+                    let rows = (pixels.len() / (4 * width as usize)) as u32;
+                    let mut set_pixel = |x: u32, y: u32, color: Vec3| {
+                        let offset = ((y - y0) * width + x) as usize * 4;
                         pixels[offset] = color.x as u8;
                         pixels[offset + 1] = color.y as u8;
                         pixels[offset + 2] = color.z as u8;
                         pixels[offset + 3] = 255;
+                    };
+
+                    for x0 in (0..width).step_by(2) {
+                        let tile = [(x0, y0), (x0 + 1, y0), (x0, y0 + 1), (x0 + 1, y0 + 1)]
+                            .into_iter()
+                            .filter(|(x, y)| *x < width && *y < y0 + rows)
+                            .collect::<Vec<_>>();
+
+                        // intersect the rays with the TLAS
+                        if ray_packets {
+                            let rays = tile
+                                .iter()
+                                .map(|(x, y)| pixel_ray(*x as f32, *y as f32))
+                                .collect::<Vec<_>>();
+                            let hits = tlas_cast.intersect_tlas_packet(
+                                &RayPacket::new(&rays),
+                                bvh_camera.tlas,
+                                &TlasCastSettings::default(),
+                            );
+                            for (lane, (x, y)) in tile.into_iter().enumerate() {
+                                set_pixel(x, y, color(&rays[lane], hits[lane]));
+                            }
+                        } else {
+                            for (x, y) in tile {
+                                set_pixel(x, y, single_ray_color(&pixel_ray(x as f32, y as f32)));
+                            }
+                        }
                     }
                 });
             }
@@ -132,6 +335,36 @@ pub fn render_camera(
     }
 }
 
+/// Writes the images of cameras with a [TlasCamera::save_path] once, clearing the path.
+pub fn save_camera_image(mut cameras: Query<&mut TlasCamera>, images: Res<Assets<Image>>) {
+    for mut camera in cameras.iter_mut() {
+        if camera.save_path.is_none()
+            || (matches!(camera.mode, TlasCameraMode::PathTrace { .. })
+                && camera.sample_count < camera.save_sample_count)
+        {
+            continue;
+        }
+        let Some(image) = camera.image.as_ref().and_then(|handle| images.get(handle)) else {
+            continue;
+        };
+        let path = camera.save_path.take().unwrap();
+        let result = image
+            .clone()
+            .try_into_dynamic()
+            .map_err(|e| e.to_string())
+            .and_then(|image| image.to_rgb8().save(&path).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("Failed to save camera image to {}: {e}", path.display());
+        }
+    }
+}
+
+/// Colour as bytes of the camera's sRGB image.
+fn srgb(color: Color) -> Vec3 {
+    let color = Srgba::from(color);
+    vec3(color.red, color.green, color.blue) * 255.0
+}
+
 pub fn camera_ui(mut commands: Commands, camera: Query<&TlasCamera, Added<TlasCamera>>) {
     for camera in camera.iter() {
         if let Some(image) = &camera.image {
@@ -104,7 +104,7 @@ pub fn debug_gimos(
                         config.tlas_branch
                     };
                     if let Some(color) = color {
-                        gizmos.cuboid(aabb3d_global(&node.aabb), color);
+                        gizmos.cuboid(aabb3d_global(&node.aabb()), color);
                     }
                 }
             }
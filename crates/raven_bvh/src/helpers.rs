@@ -1,25 +1,89 @@
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, platform::collections::HashMap, prelude::*};
 
+use crate::asset::{GltfBlas, SceneBlas};
 use crate::blas::*;
 
+use crate::refit::BlasRefit;
 use crate::tlas::*;
 
 /// Marker to convert mesh3d's mesh to a bvh
 #[derive(Component)]
 pub struct SpawnBvh;
 
-/// add MeshBvh component to Mesh3d entities that have SpawnMeshBvh
+/// Blases built by the spawn helpers, one per mesh so entities using the same mesh share it.
+///
+/// Only weak ids are kept, a [Blas] is dropped once no [MeshBlas] uses it.
+#[derive(Resource, Default)]
+pub struct MeshBlasCache {
+    blases: HashMap<(AssetId<Mesh>, bool), AssetId<Blas>>,
+}
+
+impl MeshBlasCache {
+    /// The shared [Blas] for a mesh, if one has been built.
+    pub fn get(&self, mesh: AssetId<Mesh>, keep_attributes: bool) -> Option<AssetId<Blas>> {
+        self.blases.get(&(mesh, keep_attributes)).copied()
+    }
+}
+
+/// Builds or reuses the [Blas] for a mesh, used by the spawn helpers.
+#[derive(SystemParam)]
+pub struct MeshBlasBuilder<'w> {
+    meshes: Res<'w, Assets<Mesh>>,
+    bvhs: ResMut<'w, Assets<Blas>>,
+    cache: ResMut<'w, MeshBlasCache>,
+}
+
+impl MeshBlasBuilder<'_> {
+    /// Returns the shared [Blas] for the mesh, building it on first use, ``None`` while the mesh is loading.
+    /// Errors on meshes a [Blas] can't be built from, like lines, see [BlasMeshError].
+    ///
+    /// ``unique`` builds one that isn't shared, for entities that refit theirs, see [BlasRefit].
+    pub fn get_or_build(
+        &mut self,
+        mesh: &Mesh3d,
+        keep_attributes: bool,
+        unique: bool,
+    ) -> Result<Option<Handle<Blas>>, BlasMeshError> {
+        let key = (mesh.id(), keep_attributes);
+        if !unique
+            && let Some(id) = self.cache.blases.get(&key)
+            && let Some(handle) = self.bvhs.get_strong_handle(*id)
+        {
+            return Ok(Some(handle));
+        }
+
+        let Some(mesh) = self.meshes.get(mesh) else {
+            return Ok(None);
+        };
+        let handle = self.bvhs.add(Blas::from_mesh(mesh, keep_attributes)?);
+        if !unique {
+            self.cache.blases.insert(key, handle.id());
+        }
+        Ok(Some(handle))
+    }
+}
 
+/// add MeshBvh component to Mesh3d entities that have SpawnMeshBvh, once the mesh has loaded
+#[allow(clippy::type_complexity)]
 pub(crate) fn spawn_bvh(
     mut commands: Commands,
-    meshes: Res<Assets<Mesh>>,
-    mut bvhs: ResMut<Assets<Blas>>,
-    query: Query<(Entity, &Mesh3d), With<SpawnBvh>>,
+    mut builder: MeshBlasBuilder,
+    query: Query<(Entity, &Mesh3d, Has<BlasKeepAttributes>, Has<BlasRefit>), With<SpawnBvh>>,
 ) {
-    for (e, handle) in query.iter() {
-        let mesh = meshes.get(handle).expect("Mesh not found");
-        let bvh = bvhs.add(Blas::from(mesh));
-        commands.entity(e).insert(MeshBlas(bvh)).remove::<SpawnBvh>();
+    for (e, handle, keep_attributes, unique) in query.iter() {
+        let bvh = match builder.get_or_build(handle, keep_attributes, unique) {
+            Ok(Some(bvh)) => bvh,
+            Ok(None) => continue,
+            Err(err) => {
+                warn!("Can't build a Blas for {e}: {err}");
+                commands.entity(e).remove::<SpawnBvh>();
+                continue;
+            }
+        };
+        commands
+            .entity(e)
+            .insert(MeshBlas(bvh))
+            .remove::<SpawnBvh>();
     }
 }
 
@@ -27,15 +91,28 @@ pub(crate) fn spawn_bvh(
 #[derive(Component)]
 pub struct SpawnBvhForTlas(pub Entity); // the tlas target
 
+#[allow(clippy::type_complexity)]
 pub(crate) fn spawn_bvh_for_tlas(
     mut commands: Commands,
-    meshes: Res<Assets<Mesh>>,
-    mut bvhs: ResMut<Assets<Blas>>,
-    query: Query<(Entity, &Mesh3d, &SpawnBvhForTlas)>,
+    mut builder: MeshBlasBuilder,
+    query: Query<(
+        Entity,
+        &Mesh3d,
+        &SpawnBvhForTlas,
+        Has<BlasKeepAttributes>,
+        Has<BlasRefit>,
+    )>,
 ) {
-    for (e, handle, spawn) in query.iter() {
-        let mesh = meshes.get(handle).expect("Mesh not found");
-        let bvh = bvhs.add(Blas::from(mesh));
+    for (e, handle, spawn, keep_attributes, unique) in query.iter() {
+        let bvh = match builder.get_or_build(handle, keep_attributes, unique) {
+            Ok(Some(bvh)) => bvh,
+            Ok(None) => continue,
+            Err(err) => {
+                warn!("Can't build a Blas for {e}: {err}");
+                commands.entity(e).remove::<SpawnBvhForTlas>();
+                continue;
+            }
+        };
         commands
             .entity(e)
             .insert((TlasTarget(spawn.0), MeshBlas(bvh)))
@@ -47,41 +124,139 @@ pub(crate) fn spawn_bvh_for_tlas(
 #[derive(Component)]
 pub struct SpawnSceneBvhForTlas(pub Entity);
 
-/// add MeshBvh components to all Mesh3d children of SceneRoot
-
+/// add MeshBvh components to all Mesh3d children of SceneRoot, the marker stays until every mesh has loaded
+///
+/// Uses the baked Blases of a [SceneBlas] when there is one.
+#[allow(clippy::type_complexity)]
 pub(crate) fn spawn_scene_bvh_for_tlas(
     mut commands: Commands,
-    meshes: Res<Assets<Mesh>>,
-    mut bvhs: ResMut<Assets<Blas>>,
-    query: Query<(Entity, &SceneRoot, &SpawnSceneBvhForTlas)>,
-    children: Query<(Entity, Option<&Children>, Option<&Mesh3d>)>,
+    mut builder: MeshBlasBuilder,
+    query: Query<(
+        Entity,
+        &SceneRoot,
+        &SpawnSceneBvhForTlas,
+        Has<BlasKeepAttributes>,
+        Option<&SceneBlas>,
+    )>,
+    children: Query<(
+        Entity,
+        Option<&Children>,
+        Option<&Mesh3d>,
+        Has<MeshBlas>,
+        Has<BlasRefit>,
+    )>,
+    gltf_blases: Res<Assets<GltfBlas>>,
     server: Res<AssetServer>,
     mut stack: Local<Vec<Entity>>,
 ) {
-    for (root, scene, spawn) in query.iter() {
-        if let Some(load_state) = server.get_load_state(scene.0.id()) {
-            if load_state.is_loading() {
-                continue;
-            }
+    for (root, scene, spawn, keep_attributes, scene_blas) in query.iter() {
+        if let Some(load_state) = server.get_load_state(scene.0.id())
+            && load_state.is_loading()
+        {
+            continue;
         }
+        // baked Blases don't keep attributes
+        let baked = match scene_blas {
+            Some(scene_blas) if !keep_attributes => {
+                if let Some(load_state) = server.get_load_state(scene_blas.0.id())
+                    && load_state.is_loading()
+                {
+                    continue;
+                }
+                gltf_blases.get(&scene_blas.0)
+            }
+            _ => None,
+        };
 
+        let mut loaded = true;
         stack.push(root);
         while let Some(e) = stack.pop() {
-            let (e, opt_children, opt_mesh) = children.get(e).unwrap();
+            let (e, opt_children, opt_mesh, has_blas, unique) = children.get(e).unwrap();
             if let Some(children) = opt_children {
                 for child in children.iter() {
                     stack.push(child);
                 }
             }
-            if let Some(h_mesh) = opt_mesh {
-                let mesh = meshes.get(h_mesh).expect("Mesh not found");
-                let bvh = bvhs.add(Blas::from(mesh));
-                commands
-                    .entity(e)
-                    .insert((MeshBlas(bvh), TlasTarget(spawn.0)));
+            if let Some(h_mesh) = opt_mesh
+                && !has_blas
+            {
+                // baked by primitive label, like Mesh0/Primitive0
+                let baked_blas = baked
+                    .filter(|_| !unique)
+                    .zip(h_mesh.0.path().and_then(|path| path.label()))
+                    .and_then(|(baked, label)| baked.primitives.get(label))
+                    .cloned();
+                let bvh = match baked_blas {
+                    Some(bvh) => Ok(Some(bvh)),
+                    None => builder.get_or_build(h_mesh, keep_attributes, unique),
+                };
+                match bvh {
+                    Ok(Some(bvh)) => {
+                        commands
+                            .entity(e)
+                            .insert((MeshBlas(bvh), TlasTarget(spawn.0)));
+                    }
+                    Ok(None) => loaded = false,
+                    // like lines and points, left out of the TLAS
+                    Err(err) => debug!("Skipping {e} in scene: {err}"),
+                }
             }
         }
 
-        commands.entity(root).remove::<SpawnSceneBvhForTlas>();
+        if loaded {
+            commands.entity(root).remove::<SpawnSceneBvhForTlas>();
+        }
+    }
+}
+
+/// Rebuilds shared Blases when their mesh is modified, and forgets them once unused.
+pub(crate) fn update_mesh_blas(
+    mut mesh_events: EventReader<AssetEvent<Mesh>>,
+    mut blas_events: EventReader<AssetEvent<Blas>>,
+    meshes: Res<Assets<Mesh>>,
+    mut bvhs: ResMut<Assets<Blas>>,
+    mut cache: ResMut<MeshBlasCache>,
+    mut query: Query<&mut MeshBlas>,
+) {
+    for event in blas_events.read() {
+        if let AssetEvent::Removed { id } = event {
+            cache.blases.retain(|_, blas| blas != id);
+        }
+    }
+
+    let mut rebuilt = Vec::new();
+    for event in mesh_events.read() {
+        match event {
+            AssetEvent::Modified { id } => {
+                let Some(mesh) = meshes.get(*id) else {
+                    continue;
+                };
+                for ((mesh_id, keep_attributes), blas_id) in cache.blases.iter() {
+                    if mesh_id == id
+                        && let Some(blas) = bvhs.get_mut(*blas_id)
+                    {
+                        match Blas::from_mesh(mesh, *keep_attributes) {
+                            Ok(new_blas) => {
+                                *blas = new_blas;
+                                rebuilt.push(*blas_id);
+                            }
+                            Err(err) => warn!("Can't rebuild the Blas for {id}: {err}"),
+                        }
+                    }
+                }
+            }
+            // entities still using the Blas keep it, but new ones won't find it
+            AssetEvent::Removed { id } => cache.blases.retain(|(mesh_id, _), _| mesh_id != id),
+            _ => {}
+        }
+    }
+
+    // so the TLASes update their bounds
+    if !rebuilt.is_empty() {
+        for mut mesh_blas in query.iter_mut() {
+            if rebuilt.contains(&mesh_blas.id()) {
+                mesh_blas.set_changed();
+            }
+        }
     }
 }
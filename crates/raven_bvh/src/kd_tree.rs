@@ -0,0 +1,198 @@
+use bevy::{
+    math::bounding::{Aabb3d, BoundingVolume},
+    prelude::*,
+};
+
+use crate::{aabb::Aabb3dExt, tlas::TlasNode};
+
+/// Max clusters in a kd-tree leaf before it is split
+const KD_LEAF_SIZE: usize = 4;
+
+/// kd-tree over the centroids of the TLAS clusters still to be merged, speeds up finding the
+/// best match during agglomerative clustering from a linear scan to a pruned search.
+///
+/// See "Fast Agglomerative Clustering for Rendering" by Walter et al. and Jacco Bikker's TLAS articles.
+/// Clusters are removed and inserted as they merge, node bounds only ever grow so they stay conservative.
+pub(crate) struct KdTree {
+    nodes: Vec<KdNode>,
+}
+
+struct KdNode {
+    /// Bounds of the centroids in this node
+    bounds: Aabb3d,
+    node_type: KdNodeType,
+}
+
+enum KdNodeType {
+    Leaf(Vec<u32>),
+    Split {
+        axis: usize,
+        position: f32,
+        left: u32,
+        right: u32,
+    },
+}
+
+impl KdTree {
+    /// Builds the tree from TLAS node indices.
+    pub fn new(clusters: &[u32], tlas_nodes: &[TlasNode]) -> Self {
+        let mut tree = KdTree { nodes: Vec::new() };
+        let mut items = clusters
+            .iter()
+            .map(|i| (*i, tlas_nodes[*i as usize].aabb().center()))
+            .collect::<Vec<_>>();
+        tree.nodes.push(KdNode {
+            bounds: Aabb3d::init(),
+            node_type: KdNodeType::Leaf(Vec::new()),
+        });
+        tree.subdivide(0, &mut items);
+        tree
+    }
+
+    fn subdivide(&mut self, node_idx: usize, items: &mut [(u32, Vec3A)]) {
+        let mut bounds = Aabb3d::init();
+        for (_, centroid) in items.iter() {
+            bounds.expand(*centroid);
+        }
+        self.nodes[node_idx].bounds = bounds;
+
+        if items.len() > KD_LEAF_SIZE {
+            // split at the median of the longest axis
+            let extent = bounds.max - bounds.min;
+            let axis = match extent.x >= extent.y && extent.x >= extent.z {
+                true => 0,
+                false if extent.y >= extent.z => 1,
+                false => 2,
+            };
+            let mid = items.len() / 2;
+            items.select_nth_unstable_by(mid, |a, b| a.1[axis].total_cmp(&b.1[axis]));
+            let position = items[mid].1[axis];
+
+            // everything left of the split has to be strictly less for lookups to find it again
+            let split = partition(items, |(_, c)| c[axis] < position);
+            if split > 0 {
+                let left = self.nodes.len() as u32;
+                self.nodes.push(KdNode {
+                    bounds: Aabb3d::init(),
+                    node_type: KdNodeType::Leaf(Vec::new()),
+                });
+                self.nodes.push(KdNode {
+                    bounds: Aabb3d::init(),
+                    node_type: KdNodeType::Leaf(Vec::new()),
+                });
+                self.nodes[node_idx].node_type = KdNodeType::Split {
+                    axis,
+                    position,
+                    left,
+                    right: left + 1,
+                };
+                let (left_items, right_items) = items.split_at_mut(split);
+                self.subdivide(left as usize, left_items);
+                self.subdivide(left as usize + 1, right_items);
+                return;
+            }
+        }
+        self.nodes[node_idx].node_type = KdNodeType::Leaf(items.iter().map(|(i, _)| *i).collect());
+    }
+
+    /// Adds a cluster.
+    pub fn insert(&mut self, cluster: u32, centroid: Vec3A) {
+        let mut node_idx = 0;
+        loop {
+            let node = &mut self.nodes[node_idx];
+            node.bounds.expand(centroid);
+            match &mut node.node_type {
+                KdNodeType::Leaf(clusters) => {
+                    clusters.push(cluster);
+                    return;
+                }
+                KdNodeType::Split {
+                    axis,
+                    position,
+                    left,
+                    right,
+                } => {
+                    node_idx = match centroid[*axis] < *position {
+                        true => *left as usize,
+                        false => *right as usize,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Removes a cluster, ``centroid`` must be the one it was added with.
+    pub fn remove(&mut self, cluster: u32, centroid: Vec3A) {
+        let mut node_idx = 0;
+        loop {
+            match &mut self.nodes[node_idx].node_type {
+                KdNodeType::Leaf(clusters) => {
+                    if let Some(i) = clusters.iter().position(|c| *c == cluster) {
+                        clusters.swap_remove(i);
+                    }
+                    return;
+                }
+                KdNodeType::Split {
+                    axis,
+                    position,
+                    left,
+                    right,
+                } => {
+                    node_idx = match centroid[*axis] < *position {
+                        true => *left as usize,
+                        false => *right as usize,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Finds the cluster that makes the smallest merged bounds with ``cluster``.
+    pub fn best_match(&self, cluster: u32, tlas_nodes: &[TlasNode]) -> Option<u32> {
+        let aabb = tlas_nodes[cluster as usize].aabb();
+        let mut best = None;
+        let mut best_area = f32::MAX;
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+        while let Some(node_idx) = stack.pop() {
+            let node = &self.nodes[node_idx];
+            // any cluster in the node has its centroid in bounds, so the merge is at least this big
+            let lower_bound = Aabb3d {
+                min: aabb.min.min(node.bounds.max),
+                max: aabb.max.max(node.bounds.min),
+            };
+            // ties go to the lowest index, so clustering can't cycle between equal matches
+            if lower_bound.area() > best_area {
+                continue;
+            }
+            match &node.node_type {
+                KdNodeType::Leaf(clusters) => {
+                    for other in clusters.iter().filter(|c| **c != cluster) {
+                        let area = aabb.merge(&tlas_nodes[*other as usize].aabb()).area();
+                        if area < best_area || (area == best_area && Some(*other) < best) {
+                            best_area = area;
+                            best = Some(*other);
+                        }
+                    }
+                }
+                KdNodeType::Split { left, right, .. } => {
+                    stack.push(*left as usize);
+                    stack.push(*right as usize);
+                }
+            }
+        }
+        best
+    }
+}
+
+/// Moves items matching ``pred`` to the front, returning how many there are.
+fn partition<T>(items: &mut [T], pred: impl Fn(&T) -> bool) -> usize {
+    let mut split = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(split, i);
+            split += 1;
+        }
+    }
+    split
+}
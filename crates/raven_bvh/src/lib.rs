@@ -5,17 +5,29 @@
 #[cfg(feature = "debug_draw")]
 use bevy::color::palettes::tailwind;
 use bevy::{
-    math::bounding::{Aabb3d, BoundingVolume},
-    prelude::*,
+    gltf::GltfLoader, image::CompressedImageFormats, prelude::*, render::renderer::RenderDevice,
 };
 
 mod aabb;
+mod asset;
 mod blas;
+mod build;
 mod helpers;
+mod kd_tree;
+mod packet;
+mod refit;
+mod shape;
 mod util;
+mod wide;
 use blas::*;
+#[cfg(feature = "bake")]
+mod bake;
 #[cfg(feature = "camera")]
 mod camera;
+#[cfg(feature = "camera")]
+mod path_trace;
+#[cfg(feature = "picking")]
+mod picking;
 mod tlas;
 #[cfg(feature = "debug_draw")]
 mod debug;
@@ -28,10 +40,22 @@ use crate::debug::*;
 
 pub mod prelude {
     
-    pub use crate::{BvhPlugin, BvhSystems, blas::*, helpers::*, tlas::*, util::*};
+    pub use crate::{
+        BvhPlugin, BvhSystems, asset::*, blas::*, build::*, helpers::*, packet::*, refit::*,
+        shape::*, tlas::*, util::*, wide::*,
+    };
 
     #[cfg(feature = "camera")]
-    pub use crate::camera::*;
+    pub use crate::{
+        camera::*,
+        path_trace::{PathTraceScene, Sun},
+    };
+
+    #[cfg(feature = "picking")]
+    pub use crate::picking::*;
+
+    #[cfg(feature = "bake")]
+    pub use crate::bake::*;
 
     #[cfg(feature = "debug_draw")]
     pub use crate::debug::*;
@@ -50,12 +74,18 @@ pub struct BvhPlugin;
 
 impl Plugin for BvhPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<BvhDebugMode>().init_asset::<Blas>();
+        app.init_resource::<BvhDebugMode>()
+            .init_resource::<helpers::MeshBlasCache>()
+            .init_asset::<Blas>()
+            .init_asset::<asset::GltfBlas>()
+            .init_asset_loader::<asset::BlasLoader>()
+            .register_asset_processor(asset::GltfBlasProcessor);
 
         app.add_systems(
             PostUpdate,
             (
                 // Helpers to spawn BVH from Mesh3d and SceneRoot
+                helpers::update_mesh_blas,
                 helpers::spawn_bvh,
                 helpers::spawn_bvh_for_tlas,
                 helpers::spawn_scene_bvh_for_tlas,
@@ -63,6 +93,16 @@ impl Plugin for BvhPlugin {
                 .before(BvhSystems::Update),                
         );
 
+        app.add_systems(
+            PostUpdate,
+            refit::refit_blas
+                .after(helpers::spawn_bvh)
+                .after(helpers::spawn_bvh_for_tlas)
+                .after(helpers::spawn_scene_bvh_for_tlas)
+                .after(TransformSystem::TransformPropagate)
+                .before(BvhSystems::Update),
+        );
+
         app.add_systems(
             PostUpdate,
             build_tlas
@@ -72,6 +112,8 @@ impl Plugin for BvhPlugin {
         .register_type::<Tlas>()
         .register_type::<TlasMembers>()
         .register_type::<TlasTarget>()
+        .register_type::<BvhLayers>()
+        .register_type::<refit::BlasRefit>()
         .register_type::<TlasRebuildStrategy>()
         .register_type::<TlasNodeType>();
 
@@ -79,114 +121,83 @@ impl Plugin for BvhPlugin {
         #[cfg(feature = "camera")]
         app.add_plugins(camera::TlasCameraPlugin);
     }
+
+    fn finish(&self, app: &mut App) {
+        // same image formats as the GltfPlugin's loader
+        let supported_compressed_formats = match app.world().get_resource::<RenderDevice>() {
+            Some(render_device) => CompressedImageFormats::from_features(render_device.features()),
+            None => CompressedImageFormats::NONE,
+        };
+        app.register_asset_loader(asset::GltfBlasLoader {
+            gltf: GltfLoader {
+                supported_compressed_formats,
+                custom_vertex_attributes: default(),
+            },
+        });
+    }
 }
 
-/// Builds the TLAS from the MeshBvh components in the scene
-/// Should not be called every frame, but for now it for debugging purposes
+/// Builds the TLAS from the MeshBvh components in the scene, see [TlasRebuildStrategy] for
+/// when it fully rebuilds and when it only refits.
+///
+/// Members whose [Blas] hasn't loaded yet, or is empty, are left out until it has triangles.
+#[allow(clippy::type_complexity)]
 pub fn build_tlas(
-    mut tlas: Query<(&mut Tlas, &TlasMembers, &mut TlasRebuildStrategy)>,
-    query: Query<(Entity, &MeshBlas, &GlobalTransform)>,
+    mut tlas: Query<(&mut Tlas, Ref<TlasMembers>, &mut TlasRebuildStrategy)>,
+    query: Query<(Entity, Ref<MeshBlas>, Ref<GlobalTransform>)>,
     bvhs: Res<Assets<Blas>>,
 ) {
     for (mut tlas, children, mut strat) in tlas.iter_mut() {
-        if let TlasRebuildStrategy::Mannual(false) = *strat {
-            continue;
-        }
-
-        // clear the tlas
-        tlas.tlas_nodes.clear();
-
-        let count = children.collection().len();
-        if count == 0 {
-            // if there are no children, we will not build the tlas
-            continue;
-        }
-
-        // reserve a root node as 0
-        tlas.tlas_nodes.push(TlasNode::default());
-
-        // fill the tlas all the leaf nodes
-        for e in children.iter() {
-            let (e, b, global_trans) = query.get(e).unwrap();
-            let bvh = bvhs.get(&b.0).expect("Bvh not found");
-
-            // convert the AABB to world space
-            let local_aabb = bvh.nodes[0].aabb.clone(); // root node AABB
-
-            // This would be ideal, but the scale only works if the aabb is centered local space, saidly not always the case
-            // let world_aabb = local_aabb
-            //     .scale_around_center(global_trans.scale())
-            //     .transformed_by(global_trans.translation(), global_trans.rotation());
-
-            // instead we will project the corners of the local AABB to world space
-            let mut world_aabb = Aabb3d::init();
-            for i in 0..8 {
-                let corner = Vec3A::new(
-                    if i & 1 == 0 {
-                        local_aabb.min.x
-                    } else {
-                        local_aabb.max.x
-                    },
-                    if i & 2 == 0 {
-                        local_aabb.min.y
-                    } else {
-                        local_aabb.max.y
-                    },
-                    if i & 4 == 0 {
-                        local_aabb.min.z
-                    } else {
-                        local_aabb.max.z
-                    },
-                );
-
-                let world_pos = global_trans.affine().transform_point3a(corner);
-                world_aabb.expand(world_pos);
+        // leaf bounds in world space
+        let members = children
+            .iter()
+            .filter_map(|e| {
+                let (e, b, global_trans) = query.get(e).ok()?;
+                let bvh = bvhs.get(&b.0).filter(|bvh| !bvh.is_empty())?;
+                // root node AABB, transform the corners of the local AABB to world space
+                let world_aabb = bvh.nodes[0].aabb.transform(&global_trans.affine());
+                Some((e, world_aabb, b.is_changed() || global_trans.is_changed()))
+            })
+            .collect::<Vec<_>>();
+
+        // a skipped member's Blas loading changes the count too
+        let membership_changed = children.is_changed() || tlas.leaf_count() != members.len();
+        let rebuild = match strat.as_mut() {
+            TlasRebuildStrategy::Every => true,
+            TlasRebuildStrategy::Mannual(false) => continue,
+            TlasRebuildStrategy::Mannual(rebuild) => {
+                // if in manual mode clear the flag
+                *rebuild = false;
+                true
             }
-            tlas.tlas_nodes.push(TlasNode {
-                aabb: world_aabb,
-                node_type: TlasNodeType::Leaf(e),
-            });
-        }
-
-        // use agglomerative clustering to build the TLAS
-
-        let mut node_index = (0..count as u32 + 1) // this builds a list of nodes to
-            .map(|i| i + 1)
-            .chain(std::iter::once(0))
-            .collect::<Vec<u32>>();
-
-        let mut node_indices = count as i32;
-
-        let mut a = 0i32;
-        let mut b = tlas.find_best_match(&node_index, node_indices, a);
-        while node_indices > 1 {
-            let c = tlas.find_best_match(&node_index, node_indices, b);
-            if a == c {
-                let node_index_a = node_index[a as usize];
-                let node_index_b = node_index[b as usize];
-                let node_a = tlas.tlas_nodes[node_index_a as usize];
-                let node_b = tlas.tlas_nodes[node_index_b as usize];
-                tlas.tlas_nodes.push(TlasNode {
-                    aabb: node_a.aabb.merge(&node_b.aabb),
-                    node_type: TlasNodeType::Branch {
-                        left: node_index_a as u16,
-                        right: node_index_b as u16,
-                    },
-                });
-                node_index[a as usize] = tlas.tlas_nodes.len() as u32 - 1;
-                node_index[b as usize] = node_index[node_indices as usize - 1];
-                node_indices -= 1;
-                b = tlas.find_best_match(&node_index, node_indices, a);
-            } else {
-                a = b;
-                b = c;
+            TlasRebuildStrategy::RefitOnChange => membership_changed,
+            TlasRebuildStrategy::EveryNFrames { frames, elapsed } => {
+                *elapsed += 1;
+                if membership_changed || *elapsed >= *frames {
+                    *elapsed = 0;
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+
+        // refit only the members that moved, one that isn't in the tree yet needs a rebuild
+        let mut moved = false;
+        let mut missing = false;
+        if !rebuild {
+            for (e, aabb, changed) in members.iter() {
+                if *changed {
+                    missing |= !tlas.set_leaf_aabb(*e, *aabb);
+                    moved = true;
+                }
             }
         }
-        tlas.tlas_nodes[0] = tlas.tlas_nodes[node_index[a as usize] as usize];
 
-        // if in manual mode clear the flag
-        if let TlasRebuildStrategy::Mannual(true) = *strat {
-            *strat = TlasRebuildStrategy::Mannual(false);
+        if rebuild || missing {
+            tlas.build(members.into_iter().map(|(e, aabb, _)| (e, aabb)));
+        } else if moved {
+            tlas.refit();
         }
     }
 }
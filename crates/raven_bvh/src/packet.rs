@@ -0,0 +1,322 @@
+use bevy::{
+    math::{
+        BVec4A,
+        bounding::{Aabb3d, RayCast3d},
+    },
+    prelude::*,
+};
+use std::mem::swap;
+
+use crate::{
+    blas::{Blas, Tri},
+    tlas::{BvhLayers, TlasCast, TlasCastSettings, TlasNodeType},
+    util::{BlasHit, RayCastExt},
+};
+
+/// Up to 4 coherent rays traced together, like a 2x2 tile of camera pixels.
+///
+/// Stored SoA so each box and triangle is tested against every ray at once with SIMD. The packet
+/// visits a node when any of its rays hit it, so it pays off when the rays take similar paths.
+#[derive(Debug, Clone, Copy)]
+pub struct RayPacket {
+    pub origin: [Vec4; 3],
+    pub direction: [Vec4; 3],
+    pub direction_recip: [Vec4; 3],
+    pub max: Vec4,
+    /// Lanes holding a ray, the rest are ignored.
+    pub active: BVec4A,
+}
+
+impl RayPacket {
+    pub const LANES: usize = 4;
+
+    /// Packs up to 4 rays, panics on more.
+    pub fn new(rays: &[RayCast3d]) -> Self {
+        assert!(
+            rays.len() <= Self::LANES,
+            "a RayPacket holds at most 4 rays"
+        );
+        let mut packet = RayPacket {
+            origin: [Vec4::ZERO; 3],
+            // any direction, inactive lanes are never used
+            direction: [Vec4::ONE; 3],
+            direction_recip: [Vec4::ONE; 3],
+            max: Vec4::ZERO,
+            active: BVec4A::new(
+                !rays.is_empty(),
+                rays.len() > 1,
+                rays.len() > 2,
+                rays.len() > 3,
+            ),
+        };
+        for (lane, ray) in rays.iter().enumerate() {
+            let recip = ray.direction_recip();
+            for axis in 0..3 {
+                packet.origin[axis][lane] = ray.origin[axis];
+                packet.direction[axis][lane] = ray.direction[axis];
+                packet.direction_recip[axis][lane] = recip[axis];
+            }
+            packet.max[lane] = ray.max;
+        }
+        packet
+    }
+
+    /// The ray in ``lane``, with its current max distance.
+    pub fn ray(&self, lane: usize) -> RayCast3d {
+        RayCast3d::new(
+            vec3a(
+                self.origin[0][lane],
+                self.origin[1][lane],
+                self.origin[2][lane],
+            ),
+            Dir3A::new_unchecked(vec3a(
+                self.direction[0][lane],
+                self.direction[1][lane],
+                self.direction[2][lane],
+            )),
+            self.max[lane],
+        )
+    }
+
+    /// Lanes whose ray hits ``aabb`` within its max distance, with each entry distance.
+    #[inline]
+    pub fn intersect_aabb(&self, aabb: &Aabb3d) -> (BVec4A, Vec4) {
+        let [ox, oy, oz] = self.origin;
+        let [ix, iy, iz] = self.direction_recip;
+        let tx1 = (Vec4::splat(aabb.min.x) - ox) * ix;
+        let tx2 = (Vec4::splat(aabb.max.x) - ox) * ix;
+        let ty1 = (Vec4::splat(aabb.min.y) - oy) * iy;
+        let ty2 = (Vec4::splat(aabb.max.y) - oy) * iy;
+        let tz1 = (Vec4::splat(aabb.min.z) - oz) * iz;
+        let tz2 = (Vec4::splat(aabb.max.z) - oz) * iz;
+        let tmin = tx1
+            .min(tx2)
+            .max(ty1.min(ty2))
+            .max(tz1.min(tz2))
+            .max(Vec4::ZERO);
+        let tmax = tx1
+            .max(tx2)
+            .min(ty1.max(ty2))
+            .min(tz1.max(tz2))
+            .min(self.max);
+        (tmin.cmple(tmax) & self.active, tmin)
+    }
+
+    /// Moller-Trumbore against every lane, like [RayCastExt::intersect_triangle], returns the
+    /// lanes hit within their max distance and the ``(distance, u, v)`` of each.
+    #[inline]
+    pub fn intersect_triangle(&self, tri: &Tri) -> (BVec4A, Vec4, Vec4, Vec4) {
+        let edge1 = tri.vertex1 - tri.vertex0;
+        let edge2 = tri.vertex2 - tri.vertex0;
+        let [dx, dy, dz] = self.direction;
+        let (e1x, e1y, e1z) = (
+            Vec4::splat(edge1.x),
+            Vec4::splat(edge1.y),
+            Vec4::splat(edge1.z),
+        );
+        let (e2x, e2y, e2z) = (
+            Vec4::splat(edge2.x),
+            Vec4::splat(edge2.y),
+            Vec4::splat(edge2.z),
+        );
+
+        // h = direction x edge2
+        let hx = dy * e2z - dz * e2y;
+        let hy = dz * e2x - dx * e2z;
+        let hz = dx * e2y - dy * e2x;
+        let a = e1x * hx + e1y * hy + e1z * hz;
+        let f = Vec4::ONE / a;
+
+        // s = origin - vertex0
+        let sx = self.origin[0] - Vec4::splat(tri.vertex0.x);
+        let sy = self.origin[1] - Vec4::splat(tri.vertex0.y);
+        let sz = self.origin[2] - Vec4::splat(tri.vertex0.z);
+        let u = f * (sx * hx + sy * hy + sz * hz);
+
+        // q = s x edge1
+        let qx = sy * e1z - sz * e1y;
+        let qy = sz * e1x - sx * e1z;
+        let qz = sx * e1y - sy * e1x;
+        let v = f * (dx * qx + dy * qy + dz * qz);
+        let t = f * (e2x * qx + e2y * qy + e2z * qz);
+
+        let hit = a.abs().cmpge(Vec4::splat(0.00001))
+            & u.cmpge(Vec4::ZERO)
+            & u.cmple(Vec4::ONE)
+            & v.cmpge(Vec4::ZERO)
+            & (u + v).cmple(Vec4::ONE)
+            & t.cmpgt(Vec4::splat(0.0001))
+            & t.cmple(self.max)
+            & self.active;
+        (hit, t, u, v)
+    }
+
+    /// Closest hit of each lane, traversing the binary tree of ``bvh``.
+    pub fn intersect_bvh(&self, bvh: &Blas) -> [Option<BlasHit>; 4] {
+        #[cfg(feature = "trace")]
+        let _span = info_span!("intersect_bvh_packet").entered();
+        let mut hits = [None; 4];
+        if bvh.is_empty() {
+            return hits;
+        }
+        let mut packet = *self;
+        let mut stack = Vec::with_capacity(64);
+        let mut node = &bvh.nodes[0];
+        loop {
+            if node.is_leaf() {
+                for i in 0..node.tri_count {
+                    let tri_index = bvh.triangle_indexs[(node.left_first + i) as usize];
+                    let (hit, t, u, v) = packet.intersect_triangle(&bvh.tris[tri_index]);
+                    // strictly closer, like the single ray search
+                    let closer = hit & t.cmplt(packet.max);
+                    let mask = closer.bitmask();
+                    if mask == 0 {
+                        continue;
+                    }
+                    packet.max = Vec4::select(closer, t, packet.max);
+                    for (lane, best) in hits.iter_mut().enumerate() {
+                        if mask & (1 << lane) != 0 {
+                            *best = Some(BlasHit {
+                                distance: t[lane],
+                                u: u[lane],
+                                v: v[lane],
+                                tri_index,
+                            });
+                        }
+                    }
+                }
+                match stack.pop() {
+                    Some(n) => node = n,
+                    None => break,
+                }
+                continue;
+            }
+
+            let mut child1 = &bvh.nodes[node.left_first as usize];
+            let mut child2 = &bvh.nodes[(node.left_first + 1) as usize];
+            let mut dist1 = packet.nearest_hit(&child1.aabb);
+            let mut dist2 = packet.nearest_hit(&child2.aabb);
+            if dist1.unwrap_or(f32::MAX) > dist2.unwrap_or(f32::MAX) {
+                swap(&mut dist1, &mut dist2);
+                swap(&mut child1, &mut child2);
+            }
+            if dist1.is_none() {
+                match stack.pop() {
+                    Some(n) => node = n,
+                    None => break,
+                }
+            } else {
+                node = child1;
+                if dist2.is_some() {
+                    stack.push(child2);
+                }
+            }
+        }
+        hits
+    }
+
+    /// Nearest entry distance of any lane hitting ``aabb``.
+    #[inline]
+    fn nearest_hit(&self, aabb: &Aabb3d) -> Option<f32> {
+        let (hit, tmin) = self.intersect_aabb(aabb);
+        hit.any()
+            .then(|| Vec4::select(hit, tmin, Vec4::splat(f32::MAX)).min_element())
+    }
+}
+
+impl TlasCast<'_, '_> {
+    /// Closest hit of each ray in the packet, see [RayPacket].
+    ///
+    /// A lane stops searching once it hits a member [TlasCastSettings::early_exit_test] accepts.
+    pub fn intersect_tlas_packet(
+        &self,
+        packet: &RayPacket,
+        tlas_e: Entity,
+        settings: &TlasCastSettings,
+    ) -> [Option<(Entity, BlasHit)>; 4] {
+        #[cfg(feature = "trace")]
+        let _span = info_span!("intersect_tlas_packet").entered();
+        let mut best: [Option<(Entity, BlasHit)>; 4] = [None; 4];
+        let Ok(tlas) = self.tlases.get(tlas_e) else {
+            return best;
+        };
+        if tlas.tlas_nodes.is_empty() {
+            return best;
+        }
+
+        let mut packet = *packet;
+        let mut stack = Vec::with_capacity(64);
+        let mut node = &tlas.tlas_nodes[0];
+        loop {
+            match tlas.node_type(node) {
+                TlasNodeType::Leaf(e) => {
+                    if let Ok((_e, mesh_bvh, global_trans, layers)) = self.query.get(e)
+                        && layers
+                            .unwrap_or(&BvhLayers::ALL)
+                            .intersects(&settings.layers)
+                        && (settings.filter)(e)
+                        && let Some(bvh) = self.bvhs.get(&mesh_bvh.0)
+                    {
+                        // convert each ray to the local space of the member
+                        let mut local_rays = Vec::with_capacity(RayPacket::LANES);
+                        let mut dir_scales = Vec4::ONE;
+                        let mut lanes = Vec::with_capacity(RayPacket::LANES);
+                        for lane in 0..RayPacket::LANES {
+                            if packet.active.test(lane) {
+                                let (local_ray, dir_scale) =
+                                    packet.ray(lane).to_local(global_trans);
+                                dir_scales[local_rays.len()] = dir_scale;
+                                local_rays.push(local_ray);
+                                lanes.push(lane);
+                            }
+                        }
+
+                        let local_hits = RayPacket::new(&local_rays).intersect_bvh(bvh);
+                        for (i, lane) in lanes.into_iter().enumerate() {
+                            let Some(mut hit) = local_hits[i] else {
+                                continue;
+                            };
+                            hit.distance /= dir_scales[i]; // Convert back to world-space distance
+                            if hit.distance < packet.max[lane] {
+                                packet.max[lane] = hit.distance;
+                                best[lane] = Some((e, hit));
+                                if (settings.early_exit_test)(e) {
+                                    packet.active.set(lane, false);
+                                }
+                            }
+                        }
+                        if !packet.active.any() {
+                            return best;
+                        }
+                    }
+                    match stack.pop() {
+                        Some(n) => node = n,
+                        None => break,
+                    }
+                }
+                TlasNodeType::Branch { left, right } => {
+                    let mut child1 = &tlas.tlas_nodes[right as usize];
+                    let mut child2 = &tlas.tlas_nodes[left as usize];
+                    let mut dist1 = packet.nearest_hit(&child1.aabb());
+                    let mut dist2 = packet.nearest_hit(&child2.aabb());
+                    if dist1.unwrap_or(f32::MAX) > dist2.unwrap_or(f32::MAX) {
+                        swap(&mut dist1, &mut dist2);
+                        swap(&mut child1, &mut child2);
+                    }
+                    if dist1.is_none() {
+                        match stack.pop() {
+                            Some(n) => node = n,
+                            None => break,
+                        }
+                    } else {
+                        node = child1;
+                        if dist2.is_some() {
+                            stack.push(child2);
+                        }
+                    }
+                }
+            }
+        }
+        best
+    }
+}
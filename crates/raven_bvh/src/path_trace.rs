@@ -0,0 +1,141 @@
+use bevy::{
+    ecs::system::{SystemParam, lifetimeless::Read},
+    math::bounding::RayCast3d,
+    prelude::*,
+};
+
+use crate::{
+    tlas::{TlasCast, TlasCastSettings},
+    util::{cosine_hemisphere, pcg, random},
+};
+
+/// Albedo of members without a [StandardMaterial].
+const DEFAULT_ALBEDO: Vec3 = Vec3::splat(0.8);
+/// Offset of bounce and shadow rays from the surface, so they don't hit it again.
+const SURFACE_OFFSET: f32 = 1e-3;
+
+/// What the [crate::camera::TlasCameraMode::PathTrace] mode reads from the world besides the TLAS.
+#[derive(SystemParam)]
+pub struct PathTraceScene<'w, 's> {
+    pub materials: Option<Res<'w, Assets<StandardMaterial>>>,
+    pub mesh_materials: Query<'w, 's, Read<MeshMaterial3d<StandardMaterial>>>,
+    pub suns: Query<
+        'w,
+        's,
+        (
+            Ref<'static, DirectionalLight>,
+            Ref<'static, GlobalTransform>,
+        ),
+    >,
+}
+
+/// The first [DirectionalLight], as the direction towards it and its radiance.
+#[derive(Debug, Clone, Copy)]
+pub struct Sun {
+    pub direction: Dir3A,
+    /// Light colour scaled so a [light_consts::lux::AMBIENT_DAYLIGHT] sun lights a white surface facing it to 1.
+    pub radiance: Vec3,
+}
+
+impl PathTraceScene<'_, '_> {
+    pub fn sun(&self) -> Option<Sun> {
+        let (light, trans) = self.suns.iter().next()?;
+        let color = light.color.to_linear();
+        Some(Sun {
+            direction: Dir3A::new_unchecked((-trans.forward().as_vec3()).into()),
+            radiance: vec3(color.red, color.green, color.blue) * light.illuminance
+                / light_consts::lux::AMBIENT_DAYLIGHT,
+        })
+    }
+
+    /// A light was added, moved or changed, so accumulated samples are stale.
+    pub fn lights_changed(&self) -> bool {
+        self.suns
+            .iter()
+            .any(|(light, trans)| light.is_changed() || trans.is_changed())
+    }
+
+    /// Linear ``(albedo, emissive)`` of ``e`` from its [StandardMaterial].
+    pub fn material(&self, e: Entity) -> (Vec3, Vec3) {
+        let Some(material) = self
+            .mesh_materials
+            .get(e)
+            .ok()
+            .zip(self.materials.as_ref())
+            .and_then(|(handle, materials)| materials.get(&handle.0))
+        else {
+            return (DEFAULT_ALBEDO, Vec3::ZERO);
+        };
+        let albedo = material.base_color.to_linear();
+        let emissive = material.emissive;
+        (
+            vec3(albedo.red, albedo.green, albedo.blue),
+            vec3(emissive.red, emissive.green, emissive.blue),
+        )
+    }
+
+    /// One sample of the radiance along ``ray``, diffuse surfaces lit by the sun, ``sky`` and each other.
+    ///
+    /// Follows up to ``bounces`` diffuse bounces after the first hit, each testing a shadow ray to the sun.
+    #[allow(clippy::too_many_arguments)]
+    pub fn trace_path(
+        &self,
+        tlas_cast: &TlasCast,
+        tlas_e: Entity,
+        ray: RayCast3d,
+        bounces: u32,
+        sky: Vec3,
+        sun: Option<Sun>,
+        seed: &mut u32,
+    ) -> Vec3 {
+        let settings = TlasCastSettings::default();
+        let mut ray = ray;
+        let mut radiance = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        for _ in 0..=bounces {
+            let Some(hit) = tlas_cast.cast_ray(&ray, tlas_e, &settings) else {
+                radiance += throughput * sky;
+                break;
+            };
+            let (albedo, emissive) = self.material(hit.entity);
+            radiance += throughput * emissive;
+
+            // face the ray we came in on
+            let flip = |n: Vec3| match n.dot(Vec3::from(*ray.direction)) > 0.0 {
+                true => -n,
+                false => n,
+            };
+            let normal = flip(hit.normal);
+            let shading_normal = flip(hit.vertex_normal.unwrap_or(hit.normal));
+            let origin = hit.point + normal * SURFACE_OFFSET;
+
+            if let Some(sun) = sun {
+                let cos = shading_normal.dot(Vec3::from(*sun.direction));
+                let shadow_ray = RayCast3d::new(origin, sun.direction, 1e30f32);
+                if cos > 0.0
+                    && tlas_cast
+                        .intersect_tlas_any(&shadow_ray, tlas_e, &settings)
+                        .is_none()
+                {
+                    radiance += throughput * albedo * sun.radiance * cos;
+                }
+            }
+
+            // cosine weighted, so the lambert term and pdf cancel out
+            throughput *= albedo;
+            let direction = cosine_hemisphere(shading_normal, random(seed), random(seed));
+            let Ok(direction) = Dir3A::new(direction.into()) else {
+                break;
+            };
+            ray = RayCast3d::new(origin, direction, 1e30f32);
+        }
+        radiance
+    }
+}
+
+/// Seed for ``sample`` of the pixel at ``index``, the same every run.
+pub(crate) fn pixel_seed(index: u32, sample: u32) -> u32 {
+    let mut seed = index ^ sample.wrapping_mul(0x9E37_79B9);
+    pcg(&mut seed);
+    seed
+}
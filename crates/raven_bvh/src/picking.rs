@@ -0,0 +1,131 @@
+use bevy::{
+    math::bounding::RayCast3d,
+    picking::{
+        PickSet,
+        backend::{HitData, PointerHits, ray::RayMap},
+    },
+    platform::collections::HashSet,
+    prelude::*,
+    render::view::RenderLayers,
+};
+
+use crate::{
+    tlas::{TlasCast, TlasCastSettings},
+    util::BlasHit,
+};
+
+/// A [bevy::picking] backend casting the pointer rays into a [crate::tlas::Tlas], instead of through
+/// every mesh like the ``MeshPickingPlugin``.
+///
+/// Add [BvhPickingCamera] to the cameras that should pick. Entities with [Pickable::IGNORE] are
+/// skipped and [Pickable::should_block_lower] hides the hits behind them.
+pub struct BvhPickingPlugin;
+
+impl Plugin for BvhPickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BvhPickingSettings>()
+            .register_type::<BvhPickingSettings>()
+            .register_type::<BvhPickingCamera>()
+            .add_systems(PreUpdate, update_hits.in_set(PickSet::Backend));
+    }
+}
+
+/// Picks against the TLAS on this entity.
+#[derive(Component, Debug, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct BvhPickingCamera(pub Entity);
+
+/// Runtime settings for the [BvhPickingPlugin].
+#[derive(Resource, Debug, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct BvhPickingSettings {
+    /// Only pick entities with a [Pickable], false by default.
+    pub require_markers: bool,
+    /// Skip entities hidden by their [Visibility], true by default.
+    pub require_visible: bool,
+}
+
+impl Default for BvhPickingSettings {
+    fn default() -> Self {
+        Self {
+            require_markers: false,
+            require_visible: true,
+        }
+    }
+}
+
+/// Casts the [RayMap] rays into the picking cameras' TLASes and sends [PointerHits].
+#[allow(clippy::too_many_arguments)]
+pub fn update_hits(
+    settings: Res<BvhPickingSettings>,
+    ray_map: Res<RayMap>,
+    cameras: Query<(&Camera, &BvhPickingCamera, Option<&RenderLayers>)>,
+    pickables: Query<&Pickable>,
+    layers: Query<&RenderLayers>,
+    visibility: Query<&InheritedVisibility>,
+    tlas_cast: TlasCast,
+    mut output: EventWriter<PointerHits>,
+    mut seen: Local<HashSet<Entity>>,
+) {
+    for (&ray_id, &ray) in ray_map.iter() {
+        let Ok((camera, picking_camera, cam_layers)) = cameras.get(ray_id.camera) else {
+            continue;
+        };
+        let cam_layers = cam_layers.cloned().unwrap_or_default();
+
+        let filter = |e: Entity| {
+            let pickable = pickables.get(e).ok();
+            (!settings.require_markers || pickable.is_some())
+                && pickable.is_none_or(|p| p.is_hoverable)
+                // entities without render layers are on the default layer 0
+                && cam_layers.intersects(&layers.get(e).cloned().unwrap_or_default())
+                && (!settings.require_visible || visibility.get(e).is_ok_and(|v| v.get()))
+        };
+        let blocks = |e: Entity| pickables.get(e).is_ok_and(|p| p.should_block_lower);
+
+        // everything up to and including the first hit that blocks
+        let ray = RayCast3d::from_ray(ray, f32::MAX);
+        let mut hits: Vec<(Entity, BlasHit)> = Vec::new();
+        tlas_cast.traverse_tlas(
+            &ray,
+            picking_camera.0,
+            &TlasCastSettings::default().with_filter(&filter),
+            |ray, e, hit| {
+                if blocks(e) {
+                    ray.max = hit.distance;
+                }
+                hits.push((e, hit));
+                false
+            },
+        );
+        hits.sort_unstable_by(|(_, a), (_, b)| a.distance.total_cmp(&b.distance));
+
+        // nearest hit per entity
+        seen.clear();
+        let mut picks = Vec::new();
+        for (e, hit) in hits {
+            if !seen.insert(e) {
+                continue;
+            }
+            if let Some(tlas_hit) = tlas_cast.tlas_hit(&ray, e, hit) {
+                let normal = tlas_hit.vertex_normal.unwrap_or(tlas_hit.normal);
+                picks.push((
+                    e,
+                    HitData::new(
+                        ray_id.camera,
+                        tlas_hit.distance,
+                        Some(tlas_hit.point),
+                        Some(normal),
+                    ),
+                ));
+            }
+            if blocks(e) {
+                break;
+            }
+        }
+
+        if !picks.is_empty() {
+            output.write(PointerHits::new(ray_id.pointer, picks, camera.order as f32));
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use bevy::{
+    platform::collections::HashSet,
+    prelude::*,
+    render::mesh::skinning::{SkinnedMesh, SkinnedMeshInverseBindposes},
+};
+
+use crate::blas::*;
+
+/// Opt-in to keep a [MeshBlas] following its [Mesh3d], for deforming and skinned meshes.
+///
+/// Refits the [Blas] when the triangle count is unchanged, otherwise fully rebuilds it. A [SkinnedMesh]
+/// is skinned on the CPU to its current pose. The [Blas] is written in place, so it shouldn't be shared
+/// with other entities.
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub enum BlasRefit {
+    /// Refit every update, for skinned meshes.
+    #[default]
+    EveryFrame,
+    /// Refit on [AssetEvent::Modified] of the source mesh, for meshes whose positions are edited.
+    OnMeshModified,
+}
+
+/// Joint matrices taking a [SkinnedMesh]'s vertices to its pose, in the space of the mesh entity
+/// since that is the space the [Blas] is in.
+pub fn skin_joint_matrices(
+    skinned_mesh: &SkinnedMesh,
+    mesh_transform: &GlobalTransform,
+    inverse_bindposes: &SkinnedMeshInverseBindposes,
+    joints: &Query<&GlobalTransform>,
+) -> Option<Vec<Mat4>> {
+    let to_mesh = Mat4::from(mesh_transform.affine().inverse());
+    skinned_mesh
+        .joints
+        .iter()
+        .zip(inverse_bindposes.iter())
+        .map(|(joint, inverse_bindpose)| {
+            let joint = joints.get(*joint).ok()?;
+            Some(to_mesh * joint.compute_matrix() * *inverse_bindpose)
+        })
+        .collect()
+}
+
+/// Refits the [Blas] of entities with [BlasRefit], marks their [MeshBlas] changed so the TLAS refits too.
+#[allow(clippy::type_complexity)]
+pub(crate) fn refit_blas(
+    mut mesh_events: EventReader<AssetEvent<Mesh>>,
+    meshes: Res<Assets<Mesh>>,
+    mut bvhs: ResMut<Assets<Blas>>,
+    inverse_bindposes: Option<Res<Assets<SkinnedMeshInverseBindposes>>>,
+    mut query: Query<(
+        &BlasRefit,
+        &Mesh3d,
+        &mut MeshBlas,
+        &GlobalTransform,
+        Option<&SkinnedMesh>,
+    )>,
+    joints: Query<&GlobalTransform>,
+    mut modified: Local<HashSet<AssetId<Mesh>>>,
+) {
+    modified.clear();
+    for event in mesh_events.read() {
+        if let AssetEvent::Modified { id } = event {
+            modified.insert(*id);
+        }
+    }
+
+    for (refit, mesh_handle, mut mesh_blas, global_trans, skinned_mesh) in query.iter_mut() {
+        if *refit == BlasRefit::OnMeshModified && !modified.contains(&mesh_handle.id()) {
+            continue;
+        }
+        let Some(mesh) = meshes.get(mesh_handle) else {
+            continue;
+        };
+
+        // skip the pose if the joints aren't ready yet, rather than refit to the bind pose
+        let joint_matrices = match skinned_mesh {
+            Some(skinned_mesh) => {
+                let Some(matrices) = inverse_bindposes
+                    .as_ref()
+                    .and_then(|bindposes| bindposes.get(&skinned_mesh.inverse_bindposes))
+                    .and_then(|bindposes| {
+                        skin_joint_matrices(skinned_mesh, global_trans, bindposes, &joints)
+                    })
+                else {
+                    continue;
+                };
+                Some(matrices)
+            }
+            None => None,
+        };
+
+        let Some(blas) = bvhs.get_mut(&mesh_blas.0) else {
+            continue;
+        };
+        if let Err(err) = blas.update_from_mesh(mesh, joint_matrices.as_deref()) {
+            warn!("Can't refit the Blas of {}: {err}", mesh_handle.id());
+            continue;
+        }
+        mesh_blas.set_changed();
+    }
+}
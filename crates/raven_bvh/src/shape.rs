@@ -0,0 +1,734 @@
+use bevy::{
+    math::{
+        Affine3A,
+        bounding::{Aabb3d, BoundingVolume, IntersectsVolume, RayCast3d},
+    },
+    prelude::*,
+    render::primitives::Frustum,
+};
+
+use crate::{
+    aabb::Aabb3dExt,
+    blas::{Blas, Tri},
+    tlas::{BvhLayers, TlasCast, TlasCastSettings, TlasNodeType},
+};
+
+/// Result of a shape cast or closest point query.
+///
+/// From [Blas] queries it is in mesh space, from [TlasCast] queries in world space.
+#[derive(Debug, Clone, Copy)]
+pub struct ShapeHit {
+    /// Distance traveled by the shape for casts, distance from the query point for closest point queries.
+    pub distance: f32,
+    /// Point on the triangle.
+    pub point: Vec3,
+    /// Direction from ``point`` back to the shape center or query point,
+    /// the triangle normal facing it when they touch.
+    pub normal: Vec3,
+    pub tri_index: usize,
+}
+
+impl Blas {
+    /// Sweeps a sphere along ``direction``, returning the first triangle it touches within ``max_distance``.
+    ///
+    /// A sphere already touching a triangle hits it at distance 0.
+    pub fn sphere_cast(
+        &self,
+        origin: Vec3,
+        direction: Dir3,
+        radius: f32,
+        max_distance: f32,
+    ) -> Option<ShapeHit> {
+        #[cfg(feature = "trace")]
+        let _span = info_span!("sphere_cast").entered();
+        let mut cast = SphereCast::new(origin, direction, radius, max_distance);
+        traverse_blas(self, Entity::PLACEHOLDER, None, &mut cast, &|_| false);
+        cast.best.map(|(_, hit)| hit)
+    }
+
+    /// Returns the index of every triangle overlapping ``aabb``.
+    pub fn overlap_aabb(&self, aabb: &Aabb3d) -> Vec<usize> {
+        #[cfg(feature = "trace")]
+        let _span = info_span!("overlap_aabb").entered();
+        let mut overlap = AabbOverlap::new(*aabb);
+        traverse_blas(self, Entity::PLACEHOLDER, None, &mut overlap, &|_| false);
+        let mut tris = overlap
+            .hits
+            .into_iter()
+            .map(|(_, tri_index)| tri_index)
+            .collect::<Vec<_>>();
+        // spatial splits can put a triangle in more than one leaf
+        tris.sort_unstable();
+        tris.dedup();
+        tris
+    }
+
+    /// Returns the closest point on the mesh to ``point`` within ``max_distance``.
+    pub fn closest_point(&self, point: Vec3, max_distance: f32) -> Option<ShapeHit> {
+        #[cfg(feature = "trace")]
+        let _span = info_span!("closest_point").entered();
+        let mut closest = ClosestPoint::new(point, max_distance);
+        traverse_blas(self, Entity::PLACEHOLDER, None, &mut closest, &|_| false);
+        closest.best.map(|(_, hit)| hit)
+    }
+}
+
+impl TlasCast<'_, '_> {
+    /// Sweeps a sphere through the TLAS, returning the first member triangle it touches within ``max_distance``.
+    ///
+    /// See [Blas::sphere_cast].
+    pub fn sphere_cast(
+        &self,
+        origin: Vec3,
+        direction: Dir3,
+        radius: f32,
+        max_distance: f32,
+        tlas_e: Entity,
+        settings: &TlasCastSettings,
+    ) -> Option<(Entity, ShapeHit)> {
+        let mut cast = SphereCast::new(origin, direction, radius, max_distance);
+        self.traverse_shape(tlas_e, settings, &mut cast);
+        cast.best
+    }
+
+    /// Returns every member overlapping ``aabb`` with the indices of its overlapping triangles.
+    pub fn overlap_aabb(
+        &self,
+        aabb: &Aabb3d,
+        tlas_e: Entity,
+        settings: &TlasCastSettings,
+    ) -> Vec<(Entity, Vec<usize>)> {
+        let mut overlap = AabbOverlap::new(*aabb);
+        self.traverse_shape(tlas_e, settings, &mut overlap);
+
+        // hits come grouped by member
+        let mut members: Vec<(Entity, Vec<usize>)> = Vec::new();
+        for (e, tri_index) in overlap.hits {
+            match members.last_mut() {
+                Some((last, tris)) if *last == e => tris.push(tri_index),
+                _ => members.push((e, vec![tri_index])),
+            }
+        }
+        // spatial splits can put a triangle in more than one leaf
+        for (_, tris) in members.iter_mut() {
+            tris.sort_unstable();
+            tris.dedup();
+        }
+        members
+    }
+
+    /// Returns the closest point on any member to ``point`` within ``max_distance``.
+    pub fn closest_point(
+        &self,
+        point: Vec3,
+        max_distance: f32,
+        tlas_e: Entity,
+        settings: &TlasCastSettings,
+    ) -> Option<(Entity, ShapeHit)> {
+        let mut closest = ClosestPoint::new(point, max_distance);
+        self.traverse_shape(tlas_e, settings, &mut closest);
+        closest.best
+    }
+
+    /// Returns the members whose bounds overlap ``aabb``, when ``exact`` only members with a triangle overlapping it.
+    pub fn query_aabb(
+        &self,
+        aabb: &Aabb3d,
+        tlas_e: Entity,
+        exact: bool,
+        settings: &TlasCastSettings,
+    ) -> Vec<Entity> {
+        let mut query = RegionQuery::new(Region::Aabb(*aabb), exact);
+        self.traverse_shape(tlas_e, settings, &mut query);
+        query.hits
+    }
+
+    /// Returns the members whose bounds are inside or crossing ``frustum``, when ``exact`` only members with a triangle in it.
+    ///
+    /// Half spaces that aren't finite, like the far plane of an infinite projection, are ignored.
+    pub fn query_frustum(
+        &self,
+        frustum: &Frustum,
+        tlas_e: Entity,
+        exact: bool,
+        settings: &TlasCastSettings,
+    ) -> Vec<Entity> {
+        let mut query = RegionQuery::new(Region::Frustum(*frustum), exact);
+        self.traverse_shape(tlas_e, settings, &mut query);
+        query.hits
+    }
+
+    /// Walks the TLAS and member BLASes in world space with a [BvhVisitor].
+    ///
+    /// Members are skipped by [TlasCastSettings::filter] and [TlasCastSettings::layers].
+    pub(crate) fn traverse_shape(
+        &self,
+        tlas_e: Entity,
+        settings: &TlasCastSettings,
+        visitor: &mut impl BvhVisitor,
+    ) {
+        let Ok(tlas) = self.tlases.get(tlas_e) else {
+            return;
+        };
+        if tlas.tlas_nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = Vec::with_capacity(64);
+        stack.push(&tlas.tlas_nodes[0]);
+        while let Some(node) = stack.pop() {
+            if !visitor.visit_aabb(&node.aabb()) {
+                continue;
+            }
+            match tlas.node_type(node) {
+                TlasNodeType::Leaf(e) => {
+                    let Ok((_e, mesh_bvh, global_trans, layers)) = self.query.get(e) else {
+                        continue;
+                    };
+                    if !layers
+                        .unwrap_or(&BvhLayers::ALL)
+                        .intersects(&settings.layers)
+                        || !(settings.filter)(e)
+                    {
+                        continue;
+                    }
+                    if !visitor.visit_member(e) {
+                        if visitor.member_done() && (settings.early_exit_test)(e) {
+                            return;
+                        }
+                        continue;
+                    }
+                    if let Some(bvh) = self.bvhs.get(&mesh_bvh.0)
+                        && traverse_blas(
+                            bvh,
+                            e,
+                            Some(&global_trans.affine()),
+                            visitor,
+                            settings.early_exit_test,
+                        )
+                    {
+                        return;
+                    }
+                }
+                TlasNodeType::Branch { left, right } => {
+                    stack.push(&tlas.tlas_nodes[right as usize]);
+                    stack.push(&tlas.tlas_nodes[left as usize]);
+                }
+            }
+        }
+    }
+}
+
+/// Callbacks for walking a BVH with something other than a ray.
+pub(crate) trait BvhVisitor {
+    /// Returns ``false`` to skip the node and everything under it.
+    fn visit_aabb(&mut self, aabb: &Aabb3d) -> bool;
+
+    /// Called for each triangle in a visited leaf, returns ``true`` if it was a hit.
+    fn visit_tri(&mut self, entity: Entity, tri: &Tri, tri_index: usize) -> bool;
+
+    /// Called on reaching a TLAS member, returns ``false`` to skip its BLAS.
+    fn visit_member(&mut self, _entity: Entity) -> bool {
+        true
+    }
+
+    /// Returns ``true`` once the current member needs no more triangles visited.
+    fn member_done(&self) -> bool {
+        false
+    }
+}
+
+/// Walks the [BlasNode](crate::blas::BlasNode)s of ``bvh``, transforming nodes and triangles by ``to_world`` when given.
+///
+/// Returns ``true`` if the traversal was stopped by ``early_exit_test`` after a hit,
+/// it also ends early once [BvhVisitor::member_done].
+pub(crate) fn traverse_blas(
+    bvh: &Blas,
+    entity: Entity,
+    to_world: Option<&Affine3A>,
+    visitor: &mut impl BvhVisitor,
+    early_exit_test: &dyn Fn(Entity) -> bool,
+) -> bool {
+    if bvh.is_empty() {
+        return false;
+    }
+
+    let mut stack = Vec::with_capacity(64);
+    stack.push(&bvh.nodes[0]);
+    while let Some(node) = stack.pop() {
+        let aabb = match to_world {
+            Some(affine) => node.aabb.transform(affine),
+            None => node.aabb,
+        };
+        if !visitor.visit_aabb(&aabb) {
+            continue;
+        }
+        if !node.is_leaf() {
+            stack.push(&bvh.nodes[(node.left_first + 1) as usize]);
+            stack.push(&bvh.nodes[node.left_first as usize]);
+            continue;
+        }
+        for i in 0..node.tri_count {
+            let tri_index = bvh.triangle_indexs[(node.left_first + i) as usize];
+            let tri = &bvh.tris[tri_index];
+            let hit = match to_world {
+                Some(affine) => {
+                    let tri = Tri::new(
+                        affine.transform_point3a(tri.vertex0),
+                        affine.transform_point3a(tri.vertex1),
+                        affine.transform_point3a(tri.vertex2),
+                    );
+                    visitor.visit_tri(entity, &tri, tri_index)
+                }
+                None => visitor.visit_tri(entity, tri, tri_index),
+            };
+            if hit && early_exit_test(entity) {
+                return true;
+            }
+            if hit && visitor.member_done() {
+                return false;
+            }
+        }
+    }
+    false
+}
+
+/// Keeps the closest triangle a sphere swept along a ray touches.
+struct SphereCast {
+    ray: RayCast3d,
+    radius: f32,
+    best: Option<(Entity, ShapeHit)>,
+}
+
+impl SphereCast {
+    fn new(origin: Vec3, direction: Dir3, radius: f32, max_distance: f32) -> Self {
+        Self {
+            ray: RayCast3d::new(origin, direction, max_distance),
+            radius,
+            best: None,
+        }
+    }
+}
+
+impl BvhVisitor for SphereCast {
+    fn visit_aabb(&mut self, aabb: &Aabb3d) -> bool {
+        self.ray
+            .aabb_intersection_at(&aabb.grow(Vec3A::splat(self.radius)))
+            .is_some()
+    }
+
+    fn visit_tri(&mut self, entity: Entity, tri: &Tri, tri_index: usize) -> bool {
+        let origin = self.ray.origin;
+        let direction = self.ray.direction.as_vec3a();
+        let Some((distance, point)) = sphere_cast_triangle(origin, direction, self.radius, tri)
+        else {
+            return false;
+        };
+        if distance > self.ray.max {
+            return false;
+        }
+        let center = origin + direction * distance;
+        self.ray.max = distance;
+        self.best = Some((
+            entity,
+            ShapeHit {
+                distance,
+                point: point.into(),
+                normal: (center - point)
+                    .try_normalize()
+                    .unwrap_or_else(|| facing_normal(tri, origin))
+                    .into(),
+                tri_index,
+            },
+        ));
+        true
+    }
+}
+
+/// Collects every triangle overlapping an aabb.
+struct AabbOverlap {
+    aabb: Aabb3d,
+    hits: Vec<(Entity, usize)>,
+}
+
+impl AabbOverlap {
+    fn new(aabb: Aabb3d) -> Self {
+        Self {
+            aabb,
+            hits: Vec::new(),
+        }
+    }
+}
+
+impl BvhVisitor for AabbOverlap {
+    fn visit_aabb(&mut self, aabb: &Aabb3d) -> bool {
+        self.aabb.intersects(aabb)
+    }
+
+    fn visit_tri(&mut self, entity: Entity, tri: &Tri, tri_index: usize) -> bool {
+        if !triangle_aabb_overlap(tri, &self.aabb) {
+            return false;
+        }
+        self.hits.push((entity, tri_index));
+        true
+    }
+}
+
+/// Keeps the closest point on any triangle, nodes further than the best so far are skipped.
+struct ClosestPoint {
+    point: Vec3A,
+    max_distance: f32,
+    best: Option<(Entity, ShapeHit)>,
+}
+
+impl ClosestPoint {
+    fn new(point: Vec3, max_distance: f32) -> Self {
+        Self {
+            point: point.into(),
+            max_distance,
+            best: None,
+        }
+    }
+}
+
+impl BvhVisitor for ClosestPoint {
+    fn visit_aabb(&mut self, aabb: &Aabb3d) -> bool {
+        aabb.closest_point(self.point).distance_squared(self.point)
+            <= self.max_distance * self.max_distance
+    }
+
+    fn visit_tri(&mut self, entity: Entity, tri: &Tri, tri_index: usize) -> bool {
+        let point = closest_point_on_triangle(self.point, tri);
+        let distance = point.distance(self.point);
+        if distance > self.max_distance {
+            return false;
+        }
+        self.max_distance = distance;
+        self.best = Some((
+            entity,
+            ShapeHit {
+                distance,
+                point: point.into(),
+                normal: (self.point - point)
+                    .try_normalize()
+                    .unwrap_or_else(|| facing_normal(tri, self.point))
+                    .into(),
+                tri_index,
+            },
+        ));
+        true
+    }
+}
+
+/// Region of a [RegionQuery]
+enum Region {
+    Aabb(Aabb3d),
+    Frustum(Frustum),
+}
+
+impl Region {
+    fn intersects_aabb(&self, aabb: &Aabb3d) -> bool {
+        match self {
+            Region::Aabb(region) => region.intersects(aabb),
+            Region::Frustum(frustum) => frustum_intersects_aabb(frustum, aabb),
+        }
+    }
+
+    fn intersects_tri(&self, tri: &Tri) -> bool {
+        match self {
+            Region::Aabb(region) => triangle_aabb_overlap(tri, region),
+            Region::Frustum(frustum) => frustum_intersects_triangle(frustum, tri),
+        }
+    }
+}
+
+/// Collects each member inside a region once, by its bounds or, when ``exact``, by its triangles.
+struct RegionQuery {
+    region: Region,
+    exact: bool,
+    current: Entity,
+    hits: Vec<Entity>,
+}
+
+impl RegionQuery {
+    fn new(region: Region, exact: bool) -> Self {
+        Self {
+            region,
+            exact,
+            current: Entity::PLACEHOLDER,
+            hits: Vec::new(),
+        }
+    }
+}
+
+impl BvhVisitor for RegionQuery {
+    fn visit_aabb(&mut self, aabb: &Aabb3d) -> bool {
+        self.region.intersects_aabb(aabb)
+    }
+
+    fn visit_tri(&mut self, entity: Entity, tri: &Tri, _tri_index: usize) -> bool {
+        if !self.region.intersects_tri(tri) {
+            return false;
+        }
+        self.hits.push(entity);
+        true
+    }
+
+    fn visit_member(&mut self, entity: Entity) -> bool {
+        self.current = entity;
+        if !self.exact {
+            // the TLAS leaf bounds already overlap
+            self.hits.push(entity);
+        }
+        self.exact
+    }
+
+    fn member_done(&self) -> bool {
+        self.hits.last() == Some(&self.current)
+    }
+}
+
+/// Half spaces of ``frustum`` that can be tested, skipping non-finite ones like an infinite far plane.
+fn finite_half_spaces(frustum: &Frustum) -> impl Iterator<Item = (Vec3A, f32)> + '_ {
+    frustum
+        .half_spaces
+        .iter()
+        .filter(|half_space| half_space.normal_d().is_finite())
+        .map(|half_space| (half_space.normal(), half_space.d()))
+}
+
+/// Conservative, an aabb outside the frustum near its corners can still pass.
+pub(crate) fn frustum_intersects_aabb(frustum: &Frustum, aabb: &Aabb3d) -> bool {
+    let center = aabb.center();
+    let half = aabb.half_size();
+    finite_half_spaces(frustum)
+        .all(|(normal, d)| normal.dot(center) + d + half.dot(normal.abs()) >= 0.0)
+}
+
+/// Clips the triangle by each half space, anything left over is inside the frustum.
+pub(crate) fn frustum_intersects_triangle(frustum: &Frustum, tri: &Tri) -> bool {
+    let mut polygon = vec![tri.vertex0, tri.vertex1, tri.vertex2];
+    let mut clipped = Vec::with_capacity(9);
+    for (normal, d) in finite_half_spaces(frustum) {
+        let distance = |p: Vec3A| normal.dot(p) + d;
+        clipped.clear();
+        for (i, a) in polygon.iter().enumerate() {
+            let b = polygon[(i + 1) % polygon.len()];
+            let (da, db) = (distance(*a), distance(b));
+            if da >= 0.0 {
+                clipped.push(*a);
+            }
+            if (da >= 0.0) != (db >= 0.0) {
+                clipped.push(*a + (b - *a) * (da / (da - db)));
+            }
+        }
+        if clipped.is_empty() {
+            return false;
+        }
+        std::mem::swap(&mut polygon, &mut clipped);
+    }
+    true
+}
+
+/// Triangle normal flipped to face ``point``.
+fn facing_normal(tri: &Tri, point: Vec3A) -> Vec3A {
+    let normal = (tri.vertex1 - tri.vertex0)
+        .cross(tri.vertex2 - tri.vertex0)
+        .normalize_or_zero();
+    match normal.dot(point - tri.vertex0) < 0.0 {
+        true => -normal,
+        false => normal,
+    }
+}
+
+/// Closest point on a triangle, see Real-Time Collision Detection 5.1.5.
+pub(crate) fn closest_point_on_triangle(p: Vec3A, tri: &Tri) -> Vec3A {
+    let (a, b, c) = (tri.vertex0, tri.vertex1, tri.vertex2);
+    let ab = b - a;
+    let ac = c - a;
+
+    // vertex regions, then edge regions, then the face
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let sum = va + vb + vc;
+    if sum <= f32::EPSILON {
+        // degenerate triangle
+        return a;
+    }
+    a + ab * (vb / sum) + ac * (vc / sum)
+}
+
+/// Sweeps a sphere along ``direction`` (normalized) against a triangle, returning the distance traveled
+/// and the contact point on the triangle.
+pub(crate) fn sphere_cast_triangle(
+    origin: Vec3A,
+    direction: Vec3A,
+    radius: f32,
+    tri: &Tri,
+) -> Option<(f32, Vec3A)> {
+    let (a, b, c) = (tri.vertex0, tri.vertex1, tri.vertex2);
+
+    // already touching
+    let closest = closest_point_on_triangle(origin, tri);
+    if closest.distance_squared(origin) <= radius * radius {
+        return Some((0.0, closest));
+    }
+
+    // face, if the sphere lands inside the triangle nothing else can be closer
+    let normal = facing_normal(tri, origin);
+    let height = normal.dot(origin - a);
+    let speed = -normal.dot(direction);
+    if speed > f32::EPSILON && height >= radius {
+        let t = (height - radius) / speed;
+        let contact = origin + direction * t - normal * radius;
+        let sides = [
+            (b - a).cross(contact - a).dot(normal),
+            (c - b).cross(contact - b).dot(normal),
+            (a - c).cross(contact - c).dot(normal),
+        ];
+        if sides.iter().all(|s| *s >= 0.0) || sides.iter().all(|s| *s <= 0.0) {
+            return Some((t, contact));
+        }
+    }
+
+    // otherwise the earliest edge or vertex
+    let mut best: Option<(f32, Vec3A)> = None;
+    for (p, q) in [(a, b), (b, c), (c, a)] {
+        if let Some(t) = ray_cylinder(origin, direction, radius, p, q)
+            && best.is_none_or(|(best, _)| t < best)
+        {
+            let edge = q - p;
+            let center = origin + direction * t;
+            let s = ((center - p).dot(edge) / edge.length_squared()).clamp(0.0, 1.0);
+            best = Some((t, p + edge * s));
+        }
+    }
+    for vertex in [a, b, c] {
+        if let Some(t) = ray_sphere(origin, direction, radius, vertex)
+            && best.is_none_or(|(best, _)| t < best)
+        {
+            best = Some((t, vertex));
+        }
+    }
+    best
+}
+
+/// Ray against the side of a capsule around segment ``p`` ``q``, the caps are left to [ray_sphere].
+fn ray_cylinder(origin: Vec3A, direction: Vec3A, radius: f32, p: Vec3A, q: Vec3A) -> Option<f32> {
+    let edge = q - p;
+    let m = origin - p;
+    let ee = edge.dot(edge);
+    if ee <= f32::EPSILON {
+        return None;
+    }
+    let ed = edge.dot(direction);
+    let em = edge.dot(m);
+
+    let a = ee - ed * ed;
+    if a.abs() <= f32::EPSILON {
+        // parallel to the edge
+        return None;
+    }
+    let b = ee * m.dot(direction) - em * ed;
+    let c = ee * (m.dot(m) - radius * radius) - em * em;
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let t = (-b - discriminant.sqrt()) / a;
+    if t < 0.0 {
+        return None;
+    }
+    // within the segment
+    let s = (em + t * ed) / ee;
+    (0.0..=1.0).contains(&s).then_some(t)
+}
+
+/// Ray against a sphere, ``None`` if it starts inside.
+fn ray_sphere(origin: Vec3A, direction: Vec3A, radius: f32, center: Vec3A) -> Option<f32> {
+    let m = origin - center;
+    let b = m.dot(direction);
+    let c = m.dot(m) - radius * radius;
+    if c > 0.0 && b > 0.0 {
+        return None;
+    }
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let t = -b - discriminant.sqrt();
+    (t >= 0.0).then_some(t)
+}
+
+/// Separating axis test between a triangle and an aabb, see Akenine-Möller's triangle box overlap.
+pub(crate) fn triangle_aabb_overlap(tri: &Tri, aabb: &Aabb3d) -> bool {
+    let center = aabb.center();
+    let half = aabb.half_size();
+    let v = [tri.vertex0, tri.vertex1, tri.vertex2].map(|v| v - center);
+
+    // aabb face normals
+    for axis in 0..3 {
+        let min = v[0][axis].min(v[1][axis]).min(v[2][axis]);
+        let max = v[0][axis].max(v[1][axis]).max(v[2][axis]);
+        if min > half[axis] || max < -half[axis] {
+            return false;
+        }
+    }
+
+    let separated = |axis: Vec3A| {
+        let p = v.map(|v| v.dot(axis));
+        let r = half.dot(axis.abs());
+        p[0].min(p[1]).min(p[2]) > r || p[0].max(p[1]).max(p[2]) < -r
+    };
+
+    // triangle normal
+    let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+    if separated(edges[0].cross(edges[1])) {
+        return false;
+    }
+
+    // edge cross products
+    for edge in edges {
+        for axis in [Vec3A::X, Vec3A::Y, Vec3A::Z] {
+            let axis = axis.cross(edge);
+            if axis.length_squared() > f32::EPSILON && separated(axis) {
+                return false;
+            }
+        }
+    }
+    true
+}
@@ -1,54 +1,87 @@
 use std::mem::swap;
 
 use bevy::{
+    ecs::entity::EntityHashMap,
     ecs::system::{SystemParam, lifetimeless::Read},
     math::bounding::{Aabb3d, BoundingVolume, RayCast3d},
     prelude::*,
+    tasks::{ComputeTaskPool, ParallelSliceMut, TaskPool},
 };
 
 use crate::{
     Blas,
     aabb::Aabb3dExt,
     blas::MeshBlas,
-    util::{BlasHit, RayCastExt},
+    kd_tree::KdTree,
+    util::{BlasHit, RayCastExt, insert_nearest, traverse_bvh_counted},
 };
 
-/// Note: we really want this to be 32 bytes, so things layout in on nice 64 bytes cache lines, but using Vec3A instead of Vec3 in
-/// aabb, puts us at 48 instead of 32, need to test this impact more
-// pub struct Aabb {
-//     pub min: Vec3,
-//     pub max: Vec3,
-// }
-
 /// A TLAS node, which is a node in the top-level acceleration structure (TLAS).
+///
+/// Decoded view of a [TlasNode], see [Tlas::node_type].
 #[derive(Debug, Copy, Clone, Reflect)]
 pub enum TlasNodeType {
     Leaf(Entity),
     Branch {
-        left: u16,  // index of left child in TLAS nodes
-        right: u16, // index of right child in TLAS nodes
+        left: u32,  // index of left child in TLAS nodes
+        right: u32, // index of right child in TLAS nodes
     },
 }
 
+/// Packed to 32 bytes so two nodes fit in a 64 byte cache line.
 #[derive(Debug, Copy, Clone, Reflect)]
+#[repr(C)]
 pub struct TlasNode {
-    pub aabb: Aabb3d,
-    pub node_type: TlasNodeType,
+    pub min: Vec3,
+    /// Index of left child in TLAS nodes, or of the member entity for a leaf
+    pub left: u32,
+    pub max: Vec3,
+    /// Index of right child in TLAS nodes, [TlasNode::LEAF] for a leaf
+    pub right: u32,
 }
 
+const _: () = assert!(size_of::<TlasNode>() == 32);
+
 // TODO: This is left in a invade state,
 impl Default for TlasNode {
     fn default() -> Self {
-        TlasNode {
-            aabb: Aabb3d::init(),
-            node_type: TlasNodeType::Branch { left: 0, right: 0 },
-        }
+        TlasNode::branch(&Aabb3d::init(), 0, 0)
     }
 }
 
 impl TlasNode {
+    /// Marks a leaf in [TlasNode::right].
+    pub const LEAF: u32 = u32::MAX;
+
+    pub fn branch(aabb: &Aabb3d, left: u32, right: u32) -> Self {
+        TlasNode {
+            min: aabb.min.into(),
+            left,
+            max: aabb.max.into(),
+            right,
+        }
+    }
+
+    pub fn leaf(aabb: &Aabb3d, member: u32) -> Self {
+        TlasNode::branch(aabb, member, TlasNode::LEAF)
+    }
+
+    #[inline]
+    pub fn aabb(&self) -> Aabb3d {
+        Aabb3d {
+            min: self.min.into(),
+            max: self.max.into(),
+        }
+    }
+
+    #[inline]
+    pub fn set_aabb(&mut self, aabb: &Aabb3d) {
+        self.min = aabb.min.into();
+        self.max = aabb.max.into();
+    }
+
     pub fn is_leaf(&self) -> bool {
-        matches!(self.node_type, TlasNodeType::Leaf { .. })
+        self.right == TlasNode::LEAF
     }
 }
 
@@ -57,8 +90,20 @@ impl TlasNode {
 #[require(TlasMembers, TlasRebuildStrategy)]
 pub struct Tlas {
     pub tlas_nodes: Vec<TlasNode>,
+    /// Member entities, indexed by leaf nodes.
+    pub leaf_entities: Vec<Entity>,
+    /// Surface area of each node when it was last built, refit subtrees that grow past
+    /// [PARTIAL_REBUILD_RATIO] of it are rebuilt.
+    #[reflect(ignore)]
+    build_areas: Vec<f32>,
+    /// Leaf node index of each member, for refitting.
+    #[reflect(ignore)]
+    leaves: EntityHashMap<u32>,
 }
 
+/// How much a subtree's surface area can grow from refits before it is rebuilt.
+pub const PARTIAL_REBUILD_RATIO: f32 = 2.0;
+
 // Used to add entity with Bvh to a Tlas
 #[derive(Component, Default, Debug, Reflect)]
 #[relationship_target(relationship=TlasTarget)]
@@ -71,6 +116,21 @@ pub enum TlasRebuildStrategy {
     #[default]
     Every, // rebuild every update
     Mannual(bool), // will rebuild when set to true once
+    /// Refit the leaves of members whose transform or [MeshBlas] changed, rebuilding only subtrees
+    /// that have grown too much. Membership changes still do a full rebuild.
+    RefitOnChange,
+    /// Full rebuild every ``frames`` updates, refitting changed members in between.
+    EveryNFrames {
+        frames: u32,
+        elapsed: u32,
+    },
+}
+
+impl TlasRebuildStrategy {
+    /// Full rebuild every ``frames`` updates, refitting changed members in between.
+    pub fn every_n_frames(frames: u32) -> Self {
+        TlasRebuildStrategy::EveryNFrames { frames, elapsed: 0 }
+    }
 }
 
 // TODO: maybe make this generic so entity could be part of many TLASes?
@@ -82,95 +142,516 @@ pub struct TlasTarget(pub Entity);
 
 /// A TLAS is a top-level acceleration structure that contains instances of bottom-level acceleration structures (BLAS).
 impl Tlas {
-    pub fn find_best_match(&self, list: &[u32], n: i32, a: i32) -> i32 {
-        let mut smallest = 1e30f32;
-        let mut best_b = -1i32;
-        for b in 0..n {
-            if b != a {
-                let node_a = &self.tlas_nodes[list[a as usize] as usize];
-                let node_b = &self.tlas_nodes[list[b as usize] as usize];
-                let surface_area = node_a.aabb.merge(&node_b.aabb).area();
-                if surface_area < smallest {
-                    smallest = surface_area;
-                    best_b = b;
+    /// Number of members in the built tree.
+    pub fn leaf_count(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Decodes a node from [Tlas::tlas_nodes].
+    #[inline]
+    pub fn node_type(&self, node: &TlasNode) -> TlasNodeType {
+        match node.is_leaf() {
+            true => TlasNodeType::Leaf(self.leaf_entities[node.left as usize]),
+            false => TlasNodeType::Branch {
+                left: node.left,
+                right: node.right,
+            },
+        }
+    }
+
+    /// Rebuilds the whole tree from world space member bounds.
+    ///
+    /// Uses agglomerative clustering, with a kd-tree to find each cluster's best match.
+    pub fn build(&mut self, members: impl IntoIterator<Item = (Entity, Aabb3d)>) {
+        self.tlas_nodes.clear();
+        self.leaf_entities.clear();
+        self.build_areas.clear();
+        self.leaves.clear();
+
+        // reserve a root node as 0
+        self.tlas_nodes.push(TlasNode::default());
+
+        // fill the tlas all the leaf nodes
+        for (e, aabb) in members {
+            self.leaves.insert(e, self.tlas_nodes.len() as u32);
+            self.tlas_nodes
+                .push(TlasNode::leaf(&aabb, self.leaf_entities.len() as u32));
+            self.leaf_entities.push(e);
+        }
+
+        let count = self.leaves.len();
+        match count {
+            0 => self.tlas_nodes.clear(),
+            1 => {
+                // the only leaf is the root
+                self.tlas_nodes.swap_remove(0);
+                self.leaves.values_mut().for_each(|i| *i = 0);
+            }
+            _ => {
+                // root goes in 0 and is merged last, so it is at the bottom of the slots
+                let mut slots = vec![0];
+                slots.extend((count as u32 + 1)..(2 * count as u32 - 1));
+                self.tlas_nodes.resize(2 * count - 1, TlasNode::default());
+                let leaves = (1..=count as u32).collect::<Vec<_>>();
+                self.cluster(&leaves, slots);
+            }
+        }
+        self.build_areas = self.tlas_nodes.iter().map(|n| n.aabb().area()).collect();
+    }
+
+    /// Sets the bounds of a member's leaf, call [Tlas::refit] once all are set.
+    ///
+    /// Returns ``false`` if the entity isn't in the built tree.
+    pub fn set_leaf_aabb(&mut self, e: Entity, aabb: Aabb3d) -> bool {
+        let Some(i) = self.leaves.get(&e) else {
+            return false;
+        };
+        self.tlas_nodes[*i as usize].set_aabb(&aabb);
+        true
+    }
+
+    /// Refits branch bounds to their children, then rebuilds any subtree whose surface area
+    /// has grown more than [PARTIAL_REBUILD_RATIO] since it was built.
+    pub fn refit(&mut self) {
+        if self.tlas_nodes.is_empty() {
+            return;
+        }
+
+        // children before parents
+        let mut stack = vec![(0u32, false)];
+        while let Some((i, children_done)) = stack.pop() {
+            let node = self.tlas_nodes[i as usize];
+            if !node.is_leaf() {
+                if children_done {
+                    let aabb = self.tlas_nodes[node.left as usize]
+                        .aabb()
+                        .merge(&self.tlas_nodes[node.right as usize].aabb());
+                    self.tlas_nodes[i as usize].set_aabb(&aabb);
+                } else {
+                    stack.push((i, true));
+                    stack.push((node.left, false));
+                    stack.push((node.right, false));
+                }
+            }
+        }
+
+        // top most subtrees that have degraded
+        let mut stack = vec![0u32];
+        while let Some(i) = stack.pop() {
+            let node = self.tlas_nodes[i as usize];
+            if node.is_leaf() {
+                continue;
+            }
+            if node.aabb().area() > self.build_areas[i as usize] * PARTIAL_REBUILD_RATIO {
+                self.rebuild_subtree(i);
+            } else {
+                stack.push(node.left);
+                stack.push(node.right);
+            }
+        }
+    }
+
+    /// Reclusters the leaves under ``root``, reusing the subtree's branch nodes.
+    fn rebuild_subtree(&mut self, root: u32) {
+        let mut leaves = Vec::new();
+        let mut slots = Vec::new();
+        let mut stack = vec![root];
+        while let Some(i) = stack.pop() {
+            let node = self.tlas_nodes[i as usize];
+            if node.is_leaf() {
+                leaves.push(i);
+            } else {
+                slots.push(i);
+                stack.push(node.left);
+                stack.push(node.right);
+            }
+        }
+        // root was pushed first, so it is filled by the last merge
+        self.cluster(&leaves, slots.clone());
+        for i in slots {
+            self.build_areas[i as usize] = self.tlas_nodes[i as usize].aabb().area();
+        }
+    }
+
+    /// Agglomerative clustering of ``clusters`` into branch nodes written to ``slots``,
+    /// the last merge is written to ``slots[0]``. Needs one less slot than clusters.
+    fn cluster(&mut self, clusters: &[u32], mut slots: Vec<u32>) {
+        debug_assert_eq!(clusters.len(), slots.len() + 1);
+        let mut kd_tree = KdTree::new(clusters, &self.tlas_nodes);
+        let mut remaining = clusters.len();
+
+        let Some(mut a) = clusters.first().copied() else {
+            return;
+        };
+        let Some(mut b) = kd_tree.best_match(a, &self.tlas_nodes) else {
+            return;
+        };
+        while remaining > 1 {
+            let c = kd_tree
+                .best_match(b, &self.tlas_nodes)
+                .expect("more than one cluster left");
+            if a == c {
+                // a and b are each others best match, merge them
+                let aabb_a = self.tlas_nodes[a as usize].aabb();
+                let aabb_b = self.tlas_nodes[b as usize].aabb();
+                kd_tree.remove(a, aabb_a.center());
+                kd_tree.remove(b, aabb_b.center());
+
+                let slot = slots.pop().expect("one less slot than clusters");
+                let aabb = aabb_a.merge(&aabb_b);
+                self.tlas_nodes[slot as usize] = TlasNode::branch(&aabb, a, b);
+                kd_tree.insert(slot, aabb.center());
+                remaining -= 1;
+
+                a = slot;
+                if remaining > 1 {
+                    b = kd_tree
+                        .best_match(a, &self.tlas_nodes)
+                        .expect("more than one cluster left");
                 }
+            } else {
+                a = b;
+                b = c;
             }
         }
-        best_b
     }
 }
 
+/// Layer bitmask for a TLAS member, checked against [TlasCastSettings::layers] at the leaves.
+///
+/// Members without it are on every layer.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub struct BvhLayers(pub u32);
+
+impl BvhLayers {
+    pub const ALL: Self = BvhLayers(u32::MAX);
+    pub const NONE: Self = BvhLayers(0);
+
+    /// Creates layers from a list of layer indices.
+    pub fn from_layers(layers: &[u8]) -> Self {
+        BvhLayers(layers.iter().fold(0, |mask, layer| mask | 1 << layer))
+    }
+
+    pub fn intersects(&self, other: &BvhLayers) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl Default for BvhLayers {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// Settings for [TlasCast] queries, similar to Bevy's ``MeshRayCastSettings``.
+#[derive(Clone, Copy)]
+pub struct TlasCastSettings<'a> {
+    /// Only entities this returns ``true`` for are tested.
+    pub filter: &'a (dyn Fn(Entity) -> bool + Sync),
+    /// Called after each member that was hit, returning ``true`` stops the search.
+    ///
+    /// The TLAS is walked front to back, so stopping early can still miss a closer hit on an overlapping member.
+    pub early_exit_test: &'a (dyn Fn(Entity) -> bool + Sync),
+    /// Only members whose [BvhLayers] intersect these are tested.
+    pub layers: BvhLayers,
+}
+
+impl<'a> TlasCastSettings<'a> {
+    /// Setter for [`TlasCastSettings::filter`]
+    pub fn with_filter(mut self, filter: &'a (impl Fn(Entity) -> bool + Sync)) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Setter for [`TlasCastSettings::early_exit_test`]
+    pub fn with_early_exit_test(
+        mut self,
+        early_exit_test: &'a (impl Fn(Entity) -> bool + Sync),
+    ) -> Self {
+        self.early_exit_test = early_exit_test;
+        self
+    }
+
+    /// Setter for [`TlasCastSettings::layers`]
+    pub fn with_layers(mut self, layers: BvhLayers) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// Stop at the first entity hit.
+    pub fn always_early_exit(self) -> Self {
+        self.with_early_exit_test(&|_| true)
+    }
+
+    /// Search the whole TLAS, the default.
+    pub fn never_early_exit(self) -> Self {
+        self.with_early_exit_test(&|_| false)
+    }
+}
+
+impl Default for TlasCastSettings<'_> {
+    fn default() -> Self {
+        Self {
+            filter: &|_| true,
+            early_exit_test: &|_| false,
+            layers: BvhLayers::ALL,
+        }
+    }
+}
+
+/// A ray hit on a TLAS member, in world space.
+#[derive(Debug, Clone, Copy)]
+pub struct TlasHit {
+    pub entity: Entity,
+    /// Distance along the ray.
+    pub distance: f32,
+    pub point: Vec3,
+    /// Normal of the triangle from its winding, not flipped to face the ray.
+    pub normal: Vec3,
+    /// Interpolated vertex normal, when the [Blas] kept its attributes and the mesh had normals.
+    pub vertex_normal: Option<Vec3>,
+    /// Interpolated UV0, when the [Blas] kept its attributes and the mesh had UVs.
+    pub uv: Option<Vec2>,
+    /// Triangle vertices in world space.
+    pub triangle: [Vec3; 3],
+    pub blas_hit: BlasHit,
+}
+
 #[derive(SystemParam)]
+#[allow(clippy::type_complexity)]
 pub struct TlasCast<'w, 's> {
     pub bvhs: Res<'w, Assets<Blas>>,
     pub tlases: Query<'w, 's, Read<Tlas>>,
-    pub query: Query<'w, 's, (Entity, Read<MeshBlas>, Read<GlobalTransform>)>,
+    pub query: Query<
+        'w,
+        's,
+        (
+            Entity,
+            Read<MeshBlas>,
+            Read<GlobalTransform>,
+            Option<Read<BvhLayers>>,
+        ),
+    >,
 }
 
-impl<'w, 's> TlasCast<'w, 's> {    
-    pub fn intersect_tlas(&self, ray: &RayCast3d, tlas_e: Entity) -> Option<(Entity, BlasHit)> {
-        
-        
+impl<'w, 's> TlasCast<'w, 's> {
+    /// Returns the closest hit along the ray.
+    pub fn intersect_tlas(
+        &self,
+        ray: &RayCast3d,
+        tlas_e: Entity,
+        settings: &TlasCastSettings,
+    ) -> Option<(Entity, BlasHit)> {
+        let mut best: Option<(Entity, BlasHit)> = None;
+        self.traverse_tlas(ray, tlas_e, settings, |ray, e, hit| {
+            if hit.distance < best.map_or(f32::MAX, |(_, best)| best.distance) {
+                best = Some((e, hit));
+                // PERF: tighten our search as we find hits, more complex the scene the bigger the performance win
+                ray.max = hit.distance;
+            }
+            false
+        });
+        best
+    }
+
+    /// Number of TLAS and BLAS nodes visited finding the closest hit, like [TlasCast::intersect_tlas].
+    ///
+    /// A measure of how well the trees fit the scene, the ``TlasCamera`` heatmap shows it per pixel.
+    pub fn traversal_cost(
+        &self,
+        ray: &RayCast3d,
+        tlas_e: Entity,
+        settings: &TlasCastSettings,
+    ) -> u32 {
+        let mut visits = 0;
+        let mut best = f32::MAX;
+        self.traverse_tlas_counted(ray, tlas_e, settings, &mut visits, |ray, _e, hit| {
+            if hit.distance < best {
+                best = hit.distance;
+                ray.max = hit.distance;
+            }
+            false
+        });
+        visits
+    }
+
+    /// Returns the closest hit along the ray as a [TlasHit].
+    pub fn cast_ray(
+        &self,
+        ray: &RayCast3d,
+        tlas_e: Entity,
+        settings: &TlasCastSettings,
+    ) -> Option<TlasHit> {
+        self.intersect_tlas(ray, tlas_e, settings)
+            .and_then(|(e, hit)| self.tlas_hit(ray, e, hit))
+    }
+
+    /// Closest hit of each ray as a [TlasHit], cast in parallel on the [ComputeTaskPool].
+    ///
+    /// For systems casting many rays a frame, like vision cones or audio occlusion. The pool is
+    /// created with default settings if nothing, like Bevy's ``TaskPoolPlugin``, has yet.
+    pub fn intersect_batch(
+        &self,
+        rays: &[RayCast3d],
+        tlas_e: Entity,
+        settings: &TlasCastSettings,
+    ) -> Vec<Option<TlasHit>> {
+        const CHUNK_SIZE: usize = 64;
+        let mut hits = vec![None; rays.len()];
+        let pool = ComputeTaskPool::get_or_init(TaskPool::default);
+        hits.par_chunk_map_mut(pool, CHUNK_SIZE, |i, chunk| {
+            for (hit, ray) in chunk.iter_mut().zip(&rays[i * CHUNK_SIZE..]) {
+                *hit = self.cast_ray(ray, tlas_e, settings);
+            }
+        });
+        hits
+    }
+
+    /// Expands a [BlasHit] on ``entity`` from one of the ``intersect_tlas`` queries into a [TlasHit].
+    pub fn tlas_hit(&self, ray: &RayCast3d, entity: Entity, hit: BlasHit) -> Option<TlasHit> {
+        let (_e, mesh_bvh, global_trans, _layers) = self.query.get(entity).ok()?;
+        let bvh = self.bvhs.get(&mesh_bvh.0)?;
+        let tri = bvh.tris.get(hit.tri_index)?;
+
+        let affine = global_trans.affine();
+        let normal_matrix = affine.matrix3.inverse().transpose();
+        let to_world_normal = |n: Vec3A| Vec3::from(normal_matrix * n).normalize_or_zero();
+
+        let local_normal = (tri.vertex1 - tri.vertex0).cross(tri.vertex2 - tri.vertex0);
+        let attributes = bvh.attributes.as_ref();
+        Some(TlasHit {
+            entity,
+            distance: hit.distance,
+            point: ray.get_point(hit.distance).into(),
+            normal: to_world_normal(local_normal),
+            vertex_normal: attributes
+                .and_then(|a| a.normal(hit.tri_index, hit.u, hit.v))
+                .map(to_world_normal),
+            uv: attributes.and_then(|a| a.uv0(hit.tri_index, hit.u, hit.v)),
+            triangle: [tri.vertex0, tri.vertex1, tri.vertex2]
+                .map(|v| Vec3::from(affine.transform_point3a(v))),
+            blas_hit: hit,
+        })
+    }
+
+    /// Returns the first hit found, not the closest, stopping as soon as anything is hit.
+    ///
+    /// Use for visibility checks, is anything between A and B.
+    pub fn intersect_tlas_any(
+        &self,
+        ray: &RayCast3d,
+        tlas_e: Entity,
+        settings: &TlasCastSettings,
+    ) -> Option<(Entity, BlasHit)> {
+        let mut any = None;
+        self.traverse_tlas(ray, tlas_e, settings, |_ray, e, hit| {
+            any = Some((e, hit));
+            true
+        });
+        any
+    }
+
+    /// Returns up to ``max_count`` of the closest hits along the ray, sorted by distance.
+    ///
+    /// An entity can show up more than once if the ray passes through several of its triangles.
+    pub fn intersect_tlas_all(
+        &self,
+        ray: &RayCast3d,
+        tlas_e: Entity,
+        max_count: usize,
+        settings: &TlasCastSettings,
+    ) -> Vec<(Entity, BlasHit)> {
+        let mut hits = Vec::new();
+        if max_count == 0 {
+            return hits;
+        }
+        self.traverse_tlas(ray, tlas_e, settings, |ray, e, hit| {
+            insert_nearest(&mut hits, (e, hit), max_count, ray);
+            false
+        });
+        hits
+    }
+
+    /// Walks the TLAS front to back and each hit member's BLAS, calling ``on_hit`` with world space hits.
+    ///
+    /// ``on_hit`` can shorten ``ray.max`` to cull the rest of the search, returning ``true`` stops the traversal.
+    /// Members are skipped by [TlasCastSettings::filter] and [TlasCastSettings::layers].
+    pub(crate) fn traverse_tlas(
+        &self,
+        ray: &RayCast3d,
+        tlas_e: Entity,
+        settings: &TlasCastSettings,
+        on_hit: impl FnMut(&mut RayCast3d, Entity, BlasHit) -> bool,
+    ) {
+        self.traverse_tlas_counted(ray, tlas_e, settings, &mut 0, on_hit);
+    }
+
+    /// [TlasCast::traverse_tlas] adding the number of TLAS and BLAS nodes visited to ``visits``.
+    pub(crate) fn traverse_tlas_counted(
+        &self,
+        ray: &RayCast3d,
+        tlas_e: Entity,
+        settings: &TlasCastSettings,
+        visits: &mut u32,
+        mut on_hit: impl FnMut(&mut RayCast3d, Entity, BlasHit) -> bool,
+    ) {
         let Ok(tlas) = self.tlases.get(tlas_e) else {
-            return None;
+            return;
         };
 
         if tlas.tlas_nodes.is_empty() {
-            return None;
+            return;
         }
 
         // Search the Tlas by traversing the tree
         let mut stack = Vec::<&TlasNode>::with_capacity(64);
         let mut node = &tlas.tlas_nodes[0];
-        let mut best_hit: Option<BlasHit> = None;
-        let mut best_entity: Option<Entity> = None;
-
-        // PERF: clone the ray so we can update max distance as we find hits to tighten our search,
-        // more complex the scene the bigger the performance win
         let mut ray = ray.clone();
         loop {
-            match node.node_type {
+            *visits += 1;
+            match tlas.node_type(node) {
                 TlasNodeType::Leaf(e) => {
                     // test vs entity bvh if it has one
-                    if let Ok((_e, mesh_bvh, global_trans)) = self.query.get(e) {
+                    if let Ok((_e, mesh_bvh, global_trans, layers)) = self.query.get(e)
+                        && layers.unwrap_or(&BvhLayers::ALL).intersects(&settings.layers)
+                        && (settings.filter)(e)
+                        && let Some(bvh) = self.bvhs.get(&mesh_bvh.0)
+                    {
                         // convert the ray to local space of the e
                         let (local_ray, dir_scale) = ray.to_local(global_trans);
-                        let bvh = self.bvhs.get(&mesh_bvh.0).unwrap();
-                        if let Some(mut hit) = local_ray.intersect_bvh(bvh) {
-                            hit.distance /= dir_scale; // Convert back to world-space distance                        
-                            if let Some(best) = best_hit {
-                                if hit.distance < best.distance {
-                                    best_hit = Some(hit);
-                                    best_entity = Some(e);
-                                    ray.max = hit.distance; // tighten the ray
-                                }
-                            } else {
-                                best_hit = Some(hit);
-                                best_entity = Some(e);
-                                ray.max = hit.distance; // tighten the ray
-                            }
+                        let mut stop = false;
+                        let mut hit_member = false;
+                        traverse_bvh_counted(&local_ray, bvh, visits, &mut |local_ray, mut hit| {
+                            hit.distance /= dir_scale; // Convert back to world-space distance
+                            hit_member = true;
+                            stop = on_hit(&mut ray, e, hit);
+                            local_ray.max = ray.max * dir_scale;
+                            stop
+                        });
+                        // once per member, after its closest hit is known, like MeshRayCast
+                        if stop || (hit_member && (settings.early_exit_test)(e)) {
+                            return;
                         }
                     }
-                    if let Some(n) = stack.pop() {
-                        node = n;
-                    } else {
-                        break;
+                    match stack.pop() {
+                        Some(n) => node = n,
+                        None => break,
                     }
                 }
                 TlasNodeType::Branch { left, right } => {
                     let mut child1 = &tlas.tlas_nodes[right as usize];
                     let mut child2 = &tlas.tlas_nodes[left as usize];
-                    let mut dist1 = ray.aabb_intersection_at(&child1.aabb);
-                    let mut dist2 = ray.aabb_intersection_at(&child2.aabb);
+                    let mut dist1 = ray.aabb_intersection_at(&child1.aabb());
+                    let mut dist2 = ray.aabb_intersection_at(&child2.aabb());
                     if dist1.unwrap_or(f32::MAX) > dist2.unwrap_or(f32::MAX) {
                         swap(&mut dist1, &mut dist2);
                         swap(&mut child1, &mut child2);
                     }
                     if dist1.is_none() {
-                        if let Some(n) = stack.pop() {
-                            node = n;
-                        } else {
-                            break;
+                        match stack.pop() {
+                            Some(n) => node = n,
+                            None => break,
                         }
                     } else {
                         node = child1;
@@ -181,10 +662,5 @@ impl<'w, 's> TlasCast<'w, 's> {
                 }
             }
         }
-        if let Some(hit) = best_hit {
-            Some((best_entity.unwrap(), hit))
-        } else {
-            None
-        }
     }
 }
@@ -1,4 +1,7 @@
-use crate::blas::{Blas, Tri};
+use crate::{
+    blas::{Blas, Tri},
+    wide::{BlasWide, traverse_wide},
+};
 use bevy::{math::bounding::RayCast3d, prelude::*};
 use std::mem::swap;
 
@@ -33,6 +36,13 @@ pub trait RayCastExt {
 
     /// Intersect the ray with a BVH, returning the closest hit if any
     fn intersect_bvh(&self, bvh: &Blas) -> Option<BlasHit>;
+
+    /// Intersect the ray with a BVH, returning the first hit found, not the closest.
+    /// Use for visibility checks where only knowing something is in the way matters.
+    fn intersect_bvh_any(&self, bvh: &Blas) -> Option<BlasHit>;
+
+    /// Intersect the ray with a BVH, returning up to ``max_count`` of the closest hits sorted by distance.
+    fn intersect_bvh_all(&self, bvh: &Blas, max_count: usize) -> Vec<BlasHit>;
 }
 
 impl RayCastExt for RayCast3d {
@@ -99,60 +109,194 @@ impl RayCastExt for RayCast3d {
     fn intersect_bvh(&self, bvh: &Blas) -> Option<BlasHit> {
         #[cfg(feature = "trace")]
         let _span = info_span!("intersect_bvh").entered();
-        let mut node = &bvh.nodes[0];
-        let mut stack = Vec::with_capacity(64);
         let mut best_hit: Option<BlasHit> = None;
+        traverse_bvh(self, bvh, &mut |ray, hit| {
+            if hit.distance < best_hit.map_or(f32::MAX, |best| best.distance) {
+                best_hit = Some(hit);
+                // PERF: tighten the ray as we find hits, more complex the scene the big the performance win
+                ray.max = hit.distance;
+            }
+            false
+        });
+        best_hit
+    }
 
-        // PERF: clone the ray so we can update max distance as we find hits to tighten our search,
-        // more complex the scene the big the performance win
-        let mut ray = self.clone();
-
-        loop {
-            if node.is_leaf() {
-                for i in 0..node.tri_count {
-                    let tri_index = bvh.triangle_indexs[(node.left_first + i) as usize];
-                    if let Some(hit) = ray.intersect_triangle(&bvh.tris[tri_index], tri_index) {
-                        if let Some(best) = best_hit {
-                            if hit.distance < best.distance {
-                                best_hit = Some(hit);
-                                ray.max = hit.distance; // tighten the ray
-                            }
-                        } else {
-                            best_hit = Some(hit);
-                            ray.max = hit.distance; // tighten the ray
-                        }
-                    }
-                }
-                if stack.is_empty() {
-                    break;
+    fn intersect_bvh_any(&self, bvh: &Blas) -> Option<BlasHit> {
+        #[cfg(feature = "trace")]
+        let _span = info_span!("intersect_bvh_any").entered();
+        let mut any_hit = None;
+        traverse_bvh(self, bvh, &mut |_ray, hit| {
+            any_hit = Some(hit);
+            true
+        });
+        any_hit
+    }
+
+    fn intersect_bvh_all(&self, bvh: &Blas, max_count: usize) -> Vec<BlasHit> {
+        #[cfg(feature = "trace")]
+        let _span = info_span!("intersect_bvh_all").entered();
+        let mut hits = Vec::new();
+        if max_count == 0 {
+            return hits;
+        }
+        traverse_bvh(self, bvh, &mut |ray, hit| {
+            insert_nearest(&mut hits, hit, max_count, ray);
+            false
+        });
+        hits
+    }
+}
+
+/// Walks the BVH front to back, calling ``on_hit`` for every triangle the ray hits.
+///
+/// ``on_hit`` can shorten ``ray.max`` to cull the rest of the search, returning ``true`` stops the traversal.
+/// Uses the wide layout when the BVH has one, see [Blas::collapse].
+pub(crate) fn traverse_bvh(
+    ray: &RayCast3d,
+    bvh: &Blas,
+    on_hit: &mut impl FnMut(&mut RayCast3d, BlasHit) -> bool,
+) {
+    traverse_bvh_counted(ray, bvh, &mut 0, on_hit);
+}
+
+/// [traverse_bvh] adding the number of nodes visited to ``visits``.
+pub(crate) fn traverse_bvh_counted(
+    ray: &RayCast3d,
+    bvh: &Blas,
+    visits: &mut u32,
+    on_hit: &mut impl FnMut(&mut RayCast3d, BlasHit) -> bool,
+) {
+    match &bvh.wide {
+        Some(BlasWide::Four(wide)) => return traverse_wide(ray, bvh, wide, visits, on_hit),
+        Some(BlasWide::Eight(wide)) => return traverse_wide(ray, bvh, wide, visits, on_hit),
+        None => {}
+    }
+    if bvh.is_empty() {
+        return;
+    }
+    let mut node = &bvh.nodes[0];
+    let mut stack = Vec::with_capacity(64);
+    let mut ray = ray.clone();
+
+    loop {
+        *visits += 1;
+        if node.is_leaf() {
+            for i in 0..node.tri_count {
+                let tri_index = bvh.triangle_indexs[(node.left_first + i) as usize];
+                if let Some(hit) = ray.intersect_triangle(&bvh.tris[tri_index], tri_index)
+                    && hit.distance <= ray.max
+                    && on_hit(&mut ray, hit)
+                {
+                    return;
                 }
-                node = stack.pop().unwrap();
-                continue;
             }
-            let mut child1 = &bvh.nodes[node.left_first as usize];
-            let mut child2 = &bvh.nodes[(node.left_first + 1) as usize];
+            match stack.pop() {
+                Some(n) => node = n,
+                None => break,
+            }
+            continue;
+        }
+        let mut child1 = &bvh.nodes[node.left_first as usize];
+        let mut child2 = &bvh.nodes[(node.left_first + 1) as usize];
 
-            let mut dist1 = ray.aabb_intersection_at(&child1.aabb);
-            let mut dist2 = ray.aabb_intersection_at(&child2.aabb);
+        let mut dist1 = ray.aabb_intersection_at(&child1.aabb);
+        let mut dist2 = ray.aabb_intersection_at(&child2.aabb);
 
-            // Sort the children by distance
-            if dist1.unwrap_or(f32::MAX) > dist2.unwrap_or(f32::MAX) {
-                swap(&mut dist1, &mut dist2);
-                swap(&mut child1, &mut child2);
-            }
+        // Sort the children by distance
+        if dist1.unwrap_or(f32::MAX) > dist2.unwrap_or(f32::MAX) {
+            swap(&mut dist1, &mut dist2);
+            swap(&mut child1, &mut child2);
+        }
 
-            if dist1.is_none() {
-                if stack.is_empty() {
-                    break;
-                }
-                node = stack.pop().unwrap();
-            } else {
-                node = child1;
-                if dist2.is_some() {
-                    stack.push(child2);
-                }
+        if dist1.is_none() {
+            match stack.pop() {
+                Some(n) => node = n,
+                None => break,
+            }
+        } else {
+            node = child1;
+            if dist2.is_some() {
+                stack.push(child2);
             }
         }
-        best_hit
     }
 }
+
+/// Keeps ``hits`` sorted by distance holding at most ``max_count``, once full the ray is tightened to the furthest kept hit.
+pub(crate) fn insert_nearest<T: HitDistance>(
+    hits: &mut Vec<T>,
+    hit: T,
+    max_count: usize,
+    ray: &mut RayCast3d,
+) {
+    let distance = hit.hit_distance();
+    let index = hits.partition_point(|h| h.hit_distance() <= distance);
+    if index >= max_count {
+        return;
+    }
+    // a triangle in more than one leaf is hit again at the same distance
+    if hits[..index]
+        .iter()
+        .rev()
+        .take_while(|h| h.hit_distance() == distance)
+        .any(|h| h.same_hit(&hit))
+    {
+        return;
+    }
+    hits.insert(index, hit);
+    hits.truncate(max_count);
+    if hits.len() == max_count {
+        ray.max = hits[max_count - 1].hit_distance();
+    }
+}
+
+/// Distance along the ray of a hit, used to keep hit lists sorted.
+pub(crate) trait HitDistance {
+    fn hit_distance(&self) -> f32;
+
+    /// Same triangle, so only one is kept.
+    fn same_hit(&self, other: &Self) -> bool;
+}
+
+impl HitDistance for BlasHit {
+    fn hit_distance(&self) -> f32 {
+        self.distance
+    }
+
+    fn same_hit(&self, other: &Self) -> bool {
+        self.tri_index == other.tri_index
+    }
+}
+
+impl HitDistance for (Entity, BlasHit) {
+    fn hit_distance(&self) -> f32 {
+        self.1.distance
+    }
+
+    fn same_hit(&self, other: &Self) -> bool {
+        self.0 == other.0 && self.1.tri_index == other.1.tri_index
+    }
+}
+
+/// Direction in the hemisphere around ``normal`` from two uniform samples, cosine weighted.
+#[cfg(any(feature = "bake", feature = "camera"))]
+pub(crate) fn cosine_hemisphere(normal: Vec3, r1: f32, r2: f32) -> Vec3 {
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    let (sin, cos) = (std::f32::consts::TAU * r1).sin_cos();
+    let r = r2.sqrt();
+    tangent * (r * cos) + bitangent * (r * sin) + normal * (1.0 - r2).max(0.0).sqrt()
+}
+
+/// Uniform in ``0..1``.
+#[cfg(any(feature = "bake", feature = "camera"))]
+pub(crate) fn random(seed: &mut u32) -> f32 {
+    (pcg(seed) >> 8) as f32 / (1 << 24) as f32
+}
+
+/// PCG hash, steps ``seed`` and returns the next value.
+#[cfg(any(feature = "bake", feature = "camera"))]
+pub(crate) fn pcg(seed: &mut u32) -> u32 {
+    *seed = seed.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+    let word = ((*seed >> ((*seed >> 28) + 4)) ^ *seed).wrapping_mul(277_803_737);
+    (word >> 22) ^ word
+}
@@ -0,0 +1,253 @@
+use bevy::{
+    math::bounding::{Aabb3d, RayCast3d},
+    prelude::*,
+};
+
+use crate::{
+    aabb::Aabb3dExt,
+    blas::Blas,
+    util::{BlasHit, RayCastExt},
+};
+
+/// Marks an empty slot in a [WideNode].
+const EMPTY: u32 = u32::MAX;
+
+/// Layout the rays traverse a [Blas] in, see [Blas::collapse].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum BlasWidth {
+    /// The binary [crate::blas::BlasNode] tree, one box test per node.
+    #[default]
+    Two,
+    /// 4 children per node, tested at once with SIMD.
+    Four,
+    /// 8 children per node, tested at once with SIMD, fewer levels but more boxes tested per node.
+    Eight,
+}
+
+/// A node of a [WideBlas] with its children's bounds stored SoA, in ``GROUPS`` groups of 4 lanes.
+///
+/// A slot with ``tri_count > 0`` is a leaf starting at ``child`` in [Blas::triangle_indexs],
+/// otherwise ``child`` is the index of a [WideNode] or [EMPTY].
+#[derive(Debug, Clone, Copy)]
+pub struct WideNode<const GROUPS: usize> {
+    pub min_x: [Vec4; GROUPS],
+    pub min_y: [Vec4; GROUPS],
+    pub min_z: [Vec4; GROUPS],
+    pub max_x: [Vec4; GROUPS],
+    pub max_y: [Vec4; GROUPS],
+    pub max_z: [Vec4; GROUPS],
+    pub child: [[u32; 4]; GROUPS],
+    pub tri_count: [[u32; 4]; GROUPS],
+}
+
+impl<const GROUPS: usize> Default for WideNode<GROUPS> {
+    fn default() -> Self {
+        let min = [Vec4::splat(1e30f32); GROUPS];
+        let max = [Vec4::splat(-1e30f32); GROUPS];
+        WideNode {
+            min_x: min,
+            min_y: min,
+            min_z: min,
+            max_x: max,
+            max_y: max,
+            max_z: max,
+            child: [[EMPTY; 4]; GROUPS],
+            tri_count: [[0; 4]; GROUPS],
+        }
+    }
+}
+
+impl<const GROUPS: usize> WideNode<GROUPS> {
+    fn set_slot(&mut self, slot: usize, aabb: &Aabb3d, child: u32, tri_count: u32) {
+        let (group, lane) = (slot / 4, slot % 4);
+        self.min_x[group][lane] = aabb.min.x;
+        self.min_y[group][lane] = aabb.min.y;
+        self.min_z[group][lane] = aabb.min.z;
+        self.max_x[group][lane] = aabb.max.x;
+        self.max_y[group][lane] = aabb.max.y;
+        self.max_z[group][lane] = aabb.max.z;
+        self.child[group][lane] = child;
+        self.tri_count[group][lane] = tri_count;
+    }
+}
+
+/// A [Blas] tree collapsed to ``4 * GROUPS`` children per node, see [Blas::collapse].
+///
+/// Only the nodes are stored, leaves index the [Blas] triangles.
+#[derive(Debug, Default, Clone)]
+pub struct WideBlas<const GROUPS: usize> {
+    pub nodes: Vec<WideNode<GROUPS>>,
+}
+
+/// BVH4, see [BlasWidth::Four].
+pub type Blas4 = WideBlas<1>;
+/// BVH8, see [BlasWidth::Eight].
+pub type Blas8 = WideBlas<2>;
+
+/// The wide layout kept in a [Blas].
+#[derive(Debug, Clone)]
+pub enum BlasWide {
+    Four(Blas4),
+    Eight(Blas8),
+}
+
+impl<const GROUPS: usize> WideBlas<GROUPS> {
+    pub const WIDTH: usize = 4 * GROUPS;
+
+    /// Collapses the binary tree, each wide node takes the largest nodes of the binary subtree below it.
+    pub fn collapse(blas: &Blas) -> Self {
+        let mut wide = WideBlas { nodes: Vec::new() };
+        // an empty root has nothing to collapse
+        if blas
+            .nodes
+            .first()
+            .is_some_and(|root| root.tri_count > 0 || root.left_first != 0)
+        {
+            wide.collapse_node(blas, 0);
+        }
+        wide
+    }
+
+    fn collapse_node(&mut self, blas: &Blas, node_idx: usize) -> u32 {
+        let wide_idx = self.nodes.len();
+        self.nodes.push(WideNode::default());
+
+        let node = &blas.nodes[node_idx];
+        let mut children = match node.is_leaf() {
+            true => vec![node_idx],
+            false => vec![node.left_first as usize, node.left_first as usize + 1],
+        };
+        // open the largest interior child until the node is full
+        while children.len() < Self::WIDTH {
+            let Some((i, _)) = children
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| !blas.nodes[**c].is_leaf())
+                .max_by(|(_, a), (_, b)| {
+                    blas.nodes[**a]
+                        .aabb
+                        .area()
+                        .total_cmp(&blas.nodes[**b].aabb.area())
+                })
+            else {
+                break;
+            };
+            let left = blas.nodes[children[i]].left_first as usize;
+            children[i] = left;
+            children.push(left + 1);
+        }
+
+        for (slot, c) in children.into_iter().enumerate() {
+            let child = &blas.nodes[c];
+            let (index, tri_count) = match child.is_leaf() {
+                true => (child.left_first, child.tri_count),
+                false => (self.collapse_node(blas, c), 0),
+            };
+            self.nodes[wide_idx].set_slot(slot, &child.aabb, index, tri_count);
+        }
+        wide_idx as u32
+    }
+}
+
+impl Blas {
+    /// Collapses the tree into a wide layout rays traverse with SIMD, [BlasWidth::Two] drops it.
+    ///
+    /// Kept through refits and rebuilds, but isn't serialized by [Blas::to_bytes].
+    pub fn collapse(&mut self, width: BlasWidth) {
+        self.wide = match width {
+            BlasWidth::Two => None,
+            BlasWidth::Four => Some(BlasWide::Four(Blas4::collapse(self))),
+            BlasWidth::Eight => Some(BlasWide::Eight(Blas8::collapse(self))),
+        };
+    }
+
+    /// Layout rays traverse, see [Blas::collapse].
+    pub fn width(&self) -> BlasWidth {
+        match self.wide {
+            None => BlasWidth::Two,
+            Some(BlasWide::Four(_)) => BlasWidth::Four,
+            Some(BlasWide::Eight(_)) => BlasWidth::Eight,
+        }
+    }
+}
+
+/// Walks a wide tree front to back, like [crate::util::traverse_bvh_counted].
+pub(crate) fn traverse_wide<const GROUPS: usize>(
+    ray: &RayCast3d,
+    bvh: &Blas,
+    wide: &WideBlas<GROUPS>,
+    visits: &mut u32,
+    on_hit: &mut impl FnMut(&mut RayCast3d, BlasHit) -> bool,
+) {
+    if wide.nodes.is_empty() {
+        return;
+    }
+    let mut ray = ray.clone();
+    let origin = ray.origin;
+    let inv = ray.direction_recip();
+    let (ox, oy, oz) = (
+        Vec4::splat(origin.x),
+        Vec4::splat(origin.y),
+        Vec4::splat(origin.z),
+    );
+    let (ix, iy, iz) = (Vec4::splat(inv.x), Vec4::splat(inv.y), Vec4::splat(inv.z));
+
+    // (entry distance, child, tri_count)
+    let mut stack: Vec<(f32, u32, u32)> = Vec::with_capacity(64);
+    stack.push((0.0, 0, 0));
+    let mut hits: Vec<(f32, u32, u32)> = Vec::with_capacity(8);
+    while let Some((distance, child, tri_count)) = stack.pop() {
+        // a closer hit since this was pushed
+        if distance > ray.max {
+            continue;
+        }
+        *visits += 1;
+
+        if tri_count > 0 {
+            for i in child..child + tri_count {
+                let tri_index = bvh.triangle_indexs[i as usize];
+                if let Some(hit) = ray.intersect_triangle(&bvh.tris[tri_index], tri_index)
+                    && hit.distance <= ray.max
+                    && on_hit(&mut ray, hit)
+                {
+                    return;
+                }
+            }
+            continue;
+        }
+
+        let node = &wide.nodes[child as usize];
+        let ray_max = Vec4::splat(ray.max);
+        hits.clear();
+        for group in 0..GROUPS {
+            // slab test 4 boxes at once
+            let tx1 = (node.min_x[group] - ox) * ix;
+            let tx2 = (node.max_x[group] - ox) * ix;
+            let ty1 = (node.min_y[group] - oy) * iy;
+            let ty2 = (node.max_y[group] - oy) * iy;
+            let tz1 = (node.min_z[group] - oz) * iz;
+            let tz2 = (node.max_z[group] - oz) * iz;
+            let tmin = tx1
+                .min(tx2)
+                .max(ty1.min(ty2))
+                .max(tz1.min(tz2))
+                .max(Vec4::ZERO);
+            let tmax = tx1
+                .max(tx2)
+                .min(ty1.max(ty2))
+                .min(tz1.max(tz2))
+                .min(ray_max);
+            let mask = tmin.cmple(tmax).bitmask();
+            for lane in 0..4 {
+                let child = node.child[group][lane];
+                if mask & (1 << lane) != 0 && child != EMPTY {
+                    hits.push((tmin[lane], child, node.tri_count[group][lane]));
+                }
+            }
+        }
+
+        // far to near, so the nearest is popped first
+        hits.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+        stack.extend_from_slice(&hits);
+    }
+}
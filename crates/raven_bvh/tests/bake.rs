@@ -0,0 +1,134 @@
+//! Bakes a ground plane under a low box, headlessly.
+use bevy::{prelude::*, render::mesh::MeshPlugin};
+use raven_bvh::prelude::*;
+
+const GROUND_SIZE: f32 = 10.0;
+
+#[test]
+fn bake_vertex_colors() {
+    let settings = BakeSettings {
+        sun_direction: Some(Dir3::Y),
+        points_per_frame: 16,
+        ..default()
+    };
+    let (mut app, ground) = setup(BakeOutput::VertexColor, settings);
+    let progress = run_bake(&mut app, ground);
+    assert!(progress.len() > 1, "baked in one frame");
+    assert!(progress.windows(2).all(|p| p[0].done < p[1].done));
+    assert!(progress.last().unwrap().is_finished());
+
+    let colors = vertex_colors(&app, ground);
+    let positions = ground_positions(&app, ground);
+    for (position, color) in positions.iter().zip(&colors) {
+        let distance = position.xz().abs().max_element();
+        if distance < 1.0 {
+            // under the box
+            assert!(color[0] < 0.15 && color[3] == 0.0, "{position} {color:?}");
+        } else if distance > 3.5 {
+            assert!(color[0] == 1.0 && color[3] == 1.0, "{position} {color:?}");
+        }
+    }
+
+    // the same bake again
+    let (mut app, ground) = setup(BakeOutput::VertexColor, settings);
+    run_bake(&mut app, ground);
+    assert_eq!(vertex_colors(&app, ground), colors);
+}
+
+#[test]
+fn bake_lightmap() {
+    let size = 32;
+    let (mut app, ground) = setup(
+        BakeOutput::Lightmap {
+            width: size,
+            height: size,
+        },
+        BakeSettings::default(),
+    );
+    run_bake(&mut app, ground);
+
+    let lightmap = app.world().get::<BakedLightmap>(ground).unwrap();
+    let image = app
+        .world()
+        .resource::<Assets<Image>>()
+        .get(&lightmap.0)
+        .unwrap();
+    let data = image.data.as_ref().unwrap();
+    let texel = |x: u32, y: u32| data[((y * size + x) * 4) as usize];
+    assert!(texel(size / 2, size / 2) < 40, "centre isn't occluded");
+    assert_eq!(texel(0, 0), 255);
+}
+
+fn setup(output: BakeOutput, settings: BakeSettings) -> (App, Entity) {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        TransformPlugin,
+        AssetPlugin::default(),
+        ImagePlugin::default(),
+        MeshPlugin,
+        BvhPlugin,
+        BvhBakePlugin,
+    ));
+
+    let tlas = app.world_mut().spawn(Tlas::default()).id();
+    let mut meshes = app.world_mut().resource_mut::<Assets<Mesh>>();
+    let mut ground_mesh = Plane3d::new(Vec3::Y, Vec2::splat(GROUND_SIZE / 2.0))
+        .mesh()
+        .subdivisions(19)
+        .build();
+    let uvs = ground_mesh.attribute(Mesh::ATTRIBUTE_UV_0).unwrap().clone();
+    ground_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_1, uvs);
+    let ground_mesh = meshes.add(ground_mesh);
+    let box_mesh = meshes.add(Cuboid::new(4.0, 1.0, 4.0));
+
+    let ground = app
+        .world_mut()
+        .spawn((
+            Mesh3d(ground_mesh),
+            Transform::default(),
+            SpawnBvhForTlas(tlas),
+            BakeLighting::new(tlas, output).with_settings(settings),
+        ))
+        .id();
+    app.world_mut().spawn((
+        Mesh3d(box_mesh),
+        Transform::from_xyz(0.0, 0.75, 0.0),
+        SpawnBvhForTlas(tlas),
+    ));
+    (app, ground)
+}
+
+/// Updates until the bake is done, returning the progress events sent
+fn run_bake(app: &mut App, ground: Entity) -> Vec<BakeProgress> {
+    let mut progress = Vec::new();
+    for _ in 0..100 {
+        app.update();
+        let mut events = app.world_mut().resource_mut::<Events<BakeProgress>>();
+        progress.extend(events.drain().filter(|p| p.entity == ground));
+        if !app.world().entity(ground).contains::<BakeLighting>() {
+            return progress;
+        }
+    }
+    panic!("bake didn't finish");
+}
+
+fn ground_mesh(app: &App, ground: Entity) -> &Mesh {
+    let mesh = app.world().get::<Mesh3d>(ground).unwrap();
+    app.world().resource::<Assets<Mesh>>().get(mesh).unwrap()
+}
+
+fn vertex_colors(app: &App, ground: Entity) -> Vec<[f32; 4]> {
+    match ground_mesh(app, ground).attribute(Mesh::ATTRIBUTE_COLOR) {
+        Some(bevy::render::mesh::VertexAttributeValues::Float32x4(colors)) => colors.clone(),
+        _ => panic!("no vertex colors"),
+    }
+}
+
+fn ground_positions(app: &App, ground: Entity) -> Vec<Vec3> {
+    let positions = ground_mesh(app, ground)
+        .attribute(Mesh::ATTRIBUTE_POSITION)
+        .and_then(|p| p.as_float3())
+        .unwrap();
+    positions.iter().map(|p| Vec3::from(*p)).collect()
+}
@@ -0,0 +1,100 @@
+//! Round trips Blases through [Blas::to_bytes] and checks [Blas::from_bytes] rejects corrupt data.
+use bevy::{math::bounding::RayCast3d, prelude::*};
+use raven_bvh::prelude::*;
+
+/// magic and 5 u32s
+const HEADER_SIZE: usize = 24;
+/// min, max, left_first and tri_count
+const NODE_SIZE: usize = 32;
+
+#[test]
+fn round_trip() {
+    let mesh = Sphere::new(1.0).mesh().ico(2).unwrap();
+    for keep_attributes in [false, true] {
+        let blas = Blas::from_mesh(&mesh, keep_attributes).unwrap();
+        let loaded = Blas::from_bytes(&blas.to_bytes()).unwrap();
+
+        assert_eq!(loaded.nodes.len(), blas.nodes.len());
+        for (a, b) in loaded.nodes.iter().zip(blas.nodes.iter()) {
+            assert_eq!(a.aabb.min, b.aabb.min);
+            assert_eq!(a.aabb.max, b.aabb.max);
+            assert_eq!(a.left_first, b.left_first);
+            assert_eq!(a.tri_count, b.tri_count);
+        }
+        assert_eq!(loaded.triangle_indexs, blas.triangle_indexs);
+        assert_eq!(loaded.attributes.is_some(), keep_attributes);
+
+        let ray = RayCast3d::new(vec3(0.2, 0.1, -5.0), Dir3A::Z, 100.0);
+        assert_eq!(
+            ray.intersect_bvh(&loaded).map(|hit| hit.distance),
+            ray.intersect_bvh(&blas).map(|hit| hit.distance)
+        );
+    }
+
+    let empty = Blas::build(Vec::new(), BlasBuildQuality::default());
+    assert!(Blas::from_bytes(&empty.to_bytes()).unwrap().is_empty());
+}
+
+#[test]
+fn corrupt_bytes() {
+    let blas = Blas::from_mesh(&Cuboid::new(1.0, 1.0, 1.0).mesh().build(), false).unwrap();
+    assert!(!blas.nodes[0].is_leaf());
+    let bytes = blas.to_bytes();
+
+    for len in [0, 3, HEADER_SIZE, bytes.len() - 1] {
+        assert_eq!(
+            Blas::from_bytes(&bytes[..len]).unwrap_err(),
+            BlasFormatError::UnexpectedEnd
+        );
+    }
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] = b'X';
+    assert_eq!(
+        Blas::from_bytes(&bad_magic).unwrap_err(),
+        BlasFormatError::BadMagic
+    );
+
+    let mut bad_version = bytes.clone();
+    bad_version[4..8].copy_from_slice(&99u32.to_le_bytes());
+    assert_eq!(
+        Blas::from_bytes(&bad_version).unwrap_err(),
+        BlasFormatError::UnsupportedVersion(99)
+    );
+
+    // the root's children out of range, pointing back at the root and at the last node alone
+    let last = blas.nodes.len() as u32 - 1;
+    for left_first in [0, last, u32::MAX] {
+        let mut bad_branch = bytes.clone();
+        set_node_field(&mut bad_branch, 0, 24, left_first);
+        assert_eq!(
+            Blas::from_bytes(&bad_branch).unwrap_err(),
+            BlasFormatError::InvalidIndex,
+            "left_first {left_first}"
+        );
+    }
+
+    // a leaf reading past the triangle indices
+    let leaf = blas.nodes.iter().position(|node| node.is_leaf()).unwrap();
+    let mut bad_leaf = bytes.clone();
+    set_node_field(&mut bad_leaf, leaf, 24, blas.triangle_indexs.len() as u32);
+    assert_eq!(
+        Blas::from_bytes(&bad_leaf).unwrap_err(),
+        BlasFormatError::InvalidIndex
+    );
+
+    // a triangle index past the triangles
+    let indices_start = HEADER_SIZE + blas.nodes.len() * NODE_SIZE + blas.tris.len() * 36;
+    let mut bad_index = bytes.clone();
+    bad_index[indices_start..indices_start + 4]
+        .copy_from_slice(&(blas.tris.len() as u32).to_le_bytes());
+    assert_eq!(
+        Blas::from_bytes(&bad_index).unwrap_err(),
+        BlasFormatError::InvalidIndex
+    );
+}
+
+fn set_node_field(bytes: &mut [u8], node: usize, offset: usize, value: u32) {
+    let start = HEADER_SIZE + node * NODE_SIZE + offset;
+    bytes[start..start + 4].copy_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,56 @@
+//! [TlasCastSettings::early_exit_test] runs once per member hit, after that member is searched.
+use std::sync::Mutex;
+
+use bevy::{
+    ecs::system::{RunSystemOnce, SystemState},
+    math::bounding::RayCast3d,
+    prelude::*,
+};
+use raven_bvh::{build_tlas, prelude::*};
+
+#[test]
+fn early_exit_once_per_member() {
+    let mut world = World::new();
+    let mut blases = Assets::<Blas>::default();
+    let cube = blases.add(Blas::try_from(&Cuboid::new(1.0, 1.0, 1.0).mesh().build()).unwrap());
+    world.insert_resource(blases);
+
+    let tlas_e = world.spawn(Tlas::default()).id();
+    let members = [0.0, 3.0].map(|x| {
+        world
+            .spawn((
+                MeshBlas(cube.clone()),
+                GlobalTransform::from_xyz(x, 0.0, 0.0),
+                TlasTarget(tlas_e),
+            ))
+            .id()
+    });
+    world.run_system_once(build_tlas).unwrap();
+
+    let mut state = SystemState::<TlasCast>::new(&mut world);
+    let tlas_cast = state.get(&world);
+    // enters and leaves both cubes, two triangle hits each
+    let ray = RayCast3d::new(vec3(-5.0, 0.1, 0.2), Dir3A::X, 100.0);
+
+    let tested = Mutex::new(Vec::new());
+    let never = |e| {
+        tested.lock().unwrap().push(e);
+        false
+    };
+    let settings = TlasCastSettings::default().with_early_exit_test(&never);
+    let hits = tlas_cast.intersect_tlas_all(&ray, tlas_e, 8, &settings);
+    assert_eq!(hits.len(), 4);
+    assert_eq!(*tested.lock().unwrap(), members);
+
+    // stopping after the first member still keeps both of its hits
+    tested.lock().unwrap().clear();
+    let first = |e| {
+        tested.lock().unwrap().push(e);
+        true
+    };
+    let settings = TlasCastSettings::default().with_early_exit_test(&first);
+    let hits = tlas_cast.intersect_tlas_all(&ray, tlas_e, 8, &settings);
+    assert_eq!(hits.len(), 2);
+    assert!(hits.iter().all(|(e, _)| *e == members[0]));
+    assert_eq!(*tested.lock().unwrap(), [members[0]]);
+}
@@ -0,0 +1,97 @@
+//! Builds a TLAS past the old u16 node index limit and checks ray hits against testing every member.
+use bevy::{
+    ecs::system::{RunSystemOnce, SystemState},
+    math::bounding::RayCast3d,
+    prelude::*,
+};
+use raven_bvh::{build_tlas, prelude::*};
+
+/// 35^3 = 42875 members, 85749 nodes
+const GRID_SIZE: i32 = 35;
+const SPACING: f32 = 3.0;
+const RAY_COUNT: usize = 100;
+
+#[test]
+fn large_tlas_ray_hits() {
+    let mut world = World::new();
+    let mut blases = Assets::<Blas>::default();
+    let cube = blases.add(Blas::try_from(&Cuboid::new(1.0, 1.0, 1.0).mesh().build()).unwrap());
+    world.insert_resource(blases);
+
+    let tlas_e = world.spawn(Tlas::default()).id();
+    for x in 0..GRID_SIZE {
+        for y in 0..GRID_SIZE {
+            for z in 0..GRID_SIZE {
+                let transform =
+                    Transform::from_translation(vec3(x as f32, y as f32, z as f32) * SPACING)
+                        .with_rotation(Quat::from_rotation_y((x + y + z) as f32))
+                        .with_scale(Vec3::splat(1.0 + (x * y * z % 3) as f32 * 0.5));
+                world.spawn((
+                    MeshBlas(cube.clone()),
+                    GlobalTransform::from(transform),
+                    TlasTarget(tlas_e),
+                ));
+            }
+        }
+    }
+    world.run_system_once(build_tlas).unwrap();
+
+    let tlas = world.get::<Tlas>(tlas_e).unwrap();
+    let member_count = (GRID_SIZE * GRID_SIZE * GRID_SIZE) as usize;
+    assert_eq!(tlas.leaf_count(), member_count);
+    assert_eq!(tlas.tlas_nodes.len(), 2 * member_count - 1);
+    assert!(tlas.tlas_nodes.len() > u16::MAX as usize);
+
+    let mut state = SystemState::<(
+        TlasCast,
+        Query<(Entity, &MeshBlas, &GlobalTransform)>,
+        Res<Assets<Blas>>,
+    )>::new(&mut world);
+    let (tlas_cast, members, blases) = state.get(&world);
+
+    let extent = GRID_SIZE as f32 * SPACING;
+    let mut seed = 1u32;
+    let mut hits = 0;
+    for _ in 0..RAY_COUNT {
+        let origin = vec3(random(&mut seed), random(&mut seed), -1.0) * extent;
+        let target = vec3(random(&mut seed), random(&mut seed), 2.0) * extent;
+        let ray = RayCast3d::new(origin, Dir3A::new((target - origin).into()).unwrap(), 1e30);
+
+        let hit = tlas_cast.intersect_tlas(&ray, tlas_e, &TlasCastSettings::default());
+
+        // closest hit testing every member
+        let mut expected: Option<(Entity, f32)> = None;
+        for (e, mesh_blas, global_trans) in members.iter() {
+            let bvh = blases.get(&mesh_blas.0).unwrap();
+            let (local_ray, dir_scale) = ray.to_local(global_trans);
+            if let Some(hit) = local_ray.intersect_bvh(bvh) {
+                let distance = hit.distance / dir_scale;
+                if expected.is_none_or(|(_, best)| distance < best) {
+                    expected = Some((e, distance));
+                }
+            }
+        }
+
+        match (hit, expected) {
+            (Some((_, hit)), Some((_, distance))) => {
+                assert!(
+                    (hit.distance - distance).abs() < 1e-3,
+                    "hit at {} expected {distance}",
+                    hit.distance
+                );
+                hits += 1;
+            }
+            (None, None) => {}
+            (hit, expected) => panic!("hit {hit:?} expected {expected:?}"),
+        }
+    }
+    assert!(hits > RAY_COUNT / 2, "only {hits} rays hit");
+}
+
+/// Xorshift in 0..1, keeps the test deterministic without an rng dependency.
+fn random(seed: &mut u32) -> f32 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 17;
+    *seed ^= *seed << 5;
+    *seed as f32 / u32::MAX as f32
+}
@@ -0,0 +1,163 @@
+//! Builds Blases from meshes with each supported topology and position format.
+use bevy::{
+    asset::RenderAssetUsages,
+    math::bounding::{Aabb3d, RayCast3d},
+    prelude::*,
+    render::mesh::{
+        Indices, MeshVertexAttribute, PrimitiveTopology, VertexAttributeValues, VertexFormat,
+    },
+};
+use raven_bvh::prelude::*;
+
+/// A 2x1 grid of quads in the XZ plane, as a strip
+const STRIP_POSITIONS: [[f32; 3]; 6] = [
+    [0.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [1.0, 0.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [2.0, 0.0, 0.0],
+    [2.0, 0.0, 1.0],
+];
+
+#[test]
+fn triangle_strip_matches_list() {
+    let strip = mesh(PrimitiveTopology::TriangleStrip, STRIP_POSITIONS.to_vec());
+    let list = mesh(PrimitiveTopology::TriangleList, STRIP_POSITIONS.to_vec())
+        .with_inserted_indices(Indices::U16(vec![0, 1, 2, 2, 1, 3, 2, 3, 4, 4, 3, 5]));
+    assert_eq!(
+        vertices(&Blas::try_from(&strip).unwrap()),
+        vertices(&Blas::try_from(&list).unwrap())
+    );
+}
+
+#[test]
+fn triangle_strip_skips_degenerates() {
+    // two strips joined by repeating the last and first index
+    let strip = mesh(PrimitiveTopology::TriangleStrip, STRIP_POSITIONS.to_vec())
+        .with_inserted_indices(Indices::U32(vec![0, 1, 2, 3, 3, 2, 2, 4, 3, 5]));
+    let blas = Blas::try_from(&strip).unwrap();
+    assert_eq!(blas.tris.len(), 4);
+    for tri in &blas.tris {
+        let edge0 = tri.vertex1 - tri.vertex0;
+        let edge1 = tri.vertex2 - tri.vertex0;
+        assert!(edge0.cross(edge1).length() > 0.0);
+    }
+}
+
+#[test]
+fn quantized_positions() {
+    let mut quantized = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    );
+    quantized.insert_attribute(
+        position_attribute(VertexFormat::Snorm16x4),
+        VertexAttributeValues::Snorm16x4(vec![
+            [0, 0, 0, 0],
+            [i16::MAX, 0, 0, 0],
+            [0, i16::MIN, i16::MAX, 0],
+        ]),
+    );
+    let blas = Blas::try_from(&quantized).unwrap();
+    assert_eq!(
+        vertices(&blas),
+        vec![[Vec3A::ZERO, Vec3A::X, vec3a(0.0, -1.0, 1.0)]]
+    );
+}
+
+#[test]
+fn mesh_errors() {
+    let lines = mesh(PrimitiveTopology::LineList, STRIP_POSITIONS.to_vec());
+    assert_eq!(
+        Blas::try_from(&lines).unwrap_err(),
+        BlasMeshError::UnsupportedTopology(PrimitiveTopology::LineList)
+    );
+
+    let empty = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    );
+    assert_eq!(
+        Blas::try_from(&empty).unwrap_err(),
+        BlasMeshError::MissingPositions
+    );
+
+    let out_of_range = mesh(PrimitiveTopology::TriangleList, STRIP_POSITIONS.to_vec())
+        .with_inserted_indices(Indices::U16(vec![0, 1, 6]));
+    assert_eq!(
+        Blas::try_from(&out_of_range).unwrap_err(),
+        BlasMeshError::IndexOutOfRange(6)
+    );
+
+    let mut flat = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    );
+    flat.insert_attribute(
+        position_attribute(VertexFormat::Float32x2),
+        vec![[0.0f32, 0.0], [1.0, 0.0], [0.0, 1.0]],
+    );
+    assert_eq!(
+        Blas::try_from(&flat).unwrap_err(),
+        BlasMeshError::UnsupportedPositionFormat(VertexFormat::Float32x2)
+    );
+
+    let degenerate_strip = mesh(PrimitiveTopology::TriangleStrip, STRIP_POSITIONS.to_vec())
+        .with_inserted_indices(Indices::U16(vec![0, 0, 1, 1]));
+    assert_eq!(
+        Blas::try_from(&degenerate_strip).unwrap_err(),
+        BlasMeshError::NoTriangles
+    );
+
+    let short_list = mesh(PrimitiveTopology::TriangleList, STRIP_POSITIONS.to_vec())
+        .with_inserted_indices(Indices::U32(vec![0, 1]));
+    assert_eq!(
+        Blas::try_from(&short_list).unwrap_err(),
+        BlasMeshError::NoTriangles
+    );
+}
+
+#[test]
+fn empty_blas_queries() {
+    let ray = RayCast3d::new(vec3(0.0, 1.0, 0.0), Dir3A::NEG_Y, 10.0);
+    for quality in [
+        BlasBuildQuality::default(),
+        BlasBuildQuality::FullSweep,
+        BlasBuildQuality::spatial(),
+    ] {
+        for width in [BlasWidth::Two, BlasWidth::Four, BlasWidth::Eight] {
+            let mut blas = Blas::build(Vec::new(), quality);
+            blas.collapse(width);
+            assert!(blas.is_empty());
+            assert!(
+                RayPacket::new(&[ray.clone(), ray.clone(), ray.clone(), ray.clone()])
+                    .intersect_bvh(&blas)
+                    .iter()
+                    .all(Option::is_none)
+            );
+            assert!(blas.sphere_cast(Vec3::Y, Dir3::NEG_Y, 0.5, 10.0).is_none());
+            assert!(blas.closest_point(Vec3::ZERO, 10.0).is_none());
+            assert!(
+                blas.overlap_aabb(&Aabb3d::new(Vec3::ZERO, Vec3::ONE))
+                    .is_empty()
+            );
+        }
+    }
+}
+
+fn mesh(topology: PrimitiveTopology, positions: Vec<[f32; 3]>) -> Mesh {
+    Mesh::new(topology, RenderAssetUsages::default())
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+}
+
+/// [Mesh::ATTRIBUTE_POSITION] in another format, the way quantized positions get in
+fn position_attribute(format: VertexFormat) -> MeshVertexAttribute {
+    MeshVertexAttribute::new("Vertex_Position", 0, format)
+}
+
+fn vertices(blas: &Blas) -> Vec<[Vec3A; 3]> {
+    blas.tris
+        .iter()
+        .map(|tri| [tri.vertex0, tri.vertex1, tri.vertex2])
+        .collect()
+}
@@ -0,0 +1,176 @@
+//! Sphere casts, closest points, aabb overlaps and frustum queries against single triangles.
+use bevy::{
+    ecs::system::{RunSystemOnce, SystemState},
+    math::bounding::Aabb3d,
+    prelude::*,
+    render::primitives::{Frustum, HalfSpace},
+};
+use raven_bvh::{build_tlas, prelude::*};
+
+const EPSILON: f32 = 1e-4;
+
+/// Right triangle in the XZ plane, its hypotenuse runs from (2, 0, 0) to (0, 0, 2)
+fn triangle() -> Blas {
+    Blas::build(
+        vec![Tri::new(
+            Vec3A::ZERO,
+            vec3a(2.0, 0.0, 0.0),
+            vec3a(0.0, 0.0, 2.0),
+        )],
+        BlasBuildQuality::default(),
+    )
+}
+
+fn assert_hit(hit: Option<ShapeHit>, distance: f32, point: Vec3) {
+    let hit = hit.expect("expected a hit");
+    assert!((hit.distance - distance).abs() < EPSILON, "{hit:?}");
+    assert!(hit.point.distance(point) < EPSILON, "{hit:?}");
+}
+
+#[test]
+fn sphere_cast_contacts() {
+    let blas = triangle();
+
+    // face, lands inside the triangle
+    let face = blas.sphere_cast(vec3(0.5, 3.0, 0.5), Dir3::NEG_Y, 0.5, 10.0);
+    assert_hit(face, 2.5, vec3(0.5, 0.0, 0.5));
+    assert!(face.unwrap().normal.distance(Vec3::Y) < EPSILON);
+
+    // edge, passes beside the hypotenuse 0.5.sqrt() away
+    let edge = blas.sphere_cast(vec3(1.5, 3.0, 1.5), Dir3::NEG_Y, 1.0, 10.0);
+    assert_hit(edge, 3.0 - 0.5f32.sqrt(), vec3(1.0, 0.0, 1.0));
+    let normal = edge.unwrap().normal;
+    assert!(
+        normal.distance(vec3(0.5, 0.5f32.sqrt(), 0.5)) < EPSILON,
+        "{normal}"
+    );
+
+    // vertex, passes beside the corner at the origin 2.0.sqrt() away
+    let vertex = blas.sphere_cast(vec3(-1.0, 3.0, -1.0), Dir3::NEG_Y, 2.0, 10.0);
+    assert_hit(vertex, 3.0 - 2.0f32.sqrt(), Vec3::ZERO);
+
+    // already touching, whichever way it moves
+    for direction in [Dir3::NEG_Y, Dir3::Y, Dir3::X] {
+        let touching = blas.sphere_cast(vec3(0.5, 0.3, 0.5), direction, 0.5, 10.0);
+        assert_hit(touching, 0.0, vec3(0.5, 0.0, 0.5));
+    }
+
+    // moving away, out of range and passing by
+    let misses = |origin: Vec3, direction: Dir3, max_distance: f32| {
+        blas.sphere_cast(origin, direction, 0.5, max_distance)
+            .is_none()
+    };
+    assert!(misses(vec3(0.5, 3.0, 0.5), Dir3::Y, 10.0));
+    assert!(misses(vec3(0.5, 3.0, 0.5), Dir3::NEG_Y, 2.0));
+    assert!(misses(vec3(2.5, 3.0, 2.5), Dir3::NEG_Y, 10.0));
+}
+
+#[test]
+fn closest_point_regions() {
+    let blas = triangle();
+    for (point, closest) in [
+        // face
+        (vec3(0.5, 1.0, 0.5), vec3(0.5, 0.0, 0.5)),
+        (vec3(0.5, -1.0, 0.5), vec3(0.5, 0.0, 0.5)),
+        // edges
+        (vec3(1.0, 1.0, -1.0), vec3(1.0, 0.0, 0.0)),
+        (vec3(-1.0, 0.0, 1.0), vec3(0.0, 0.0, 1.0)),
+        (vec3(1.5, 1.0, 1.5), vec3(1.0, 0.0, 1.0)),
+        // vertices
+        (vec3(-1.0, 0.0, -1.0), Vec3::ZERO),
+        (vec3(3.0, 0.0, -1.0), vec3(2.0, 0.0, 0.0)),
+        (vec3(-1.0, 1.0, 3.0), vec3(0.0, 0.0, 2.0)),
+    ] {
+        let hit = blas.closest_point(point, 10.0);
+        assert_hit(hit, point.distance(closest), closest);
+    }
+    assert!(blas.closest_point(vec3(0.5, 1.0, 0.5), 0.9).is_none());
+}
+
+#[test]
+fn aabb_overlap_axes() {
+    let blas = triangle();
+    let overlaps = |center: Vec3, half_size: f32| {
+        !blas
+            .overlap_aabb(&Aabb3d::new(center, Vec3::splat(half_size)))
+            .is_empty()
+    };
+
+    assert!(overlaps(vec3(0.5, 0.0, 0.5), 0.1));
+    // crossing an edge and a corner
+    assert!(overlaps(vec3(1.0, 0.0, 1.0), 0.1));
+    assert!(overlaps(vec3(2.0, 0.0, 0.0), 0.1));
+    // separated along an aabb face normal and the triangle normal
+    assert!(!overlaps(vec3(3.0, 0.0, 0.5), 0.5));
+    assert!(!overlaps(vec3(0.5, 0.5, 0.5), 0.4));
+    // only Y cross the hypotenuse separates these, both boxes overlap the triangle's bounds and plane
+    assert!(!overlaps(vec3(1.5, 0.0, 1.5), 0.4));
+    assert!(!overlaps(vec3(1.8, 0.0, 1.8), 0.7));
+}
+
+#[test]
+fn frustum_clips_triangles() {
+    let mut world = World::new();
+    let mut blases = Assets::<Blas>::default();
+    let mut triangle = |a: Vec3, b: Vec3, c: Vec3| {
+        blases.add(Blas::build(
+            vec![Tri::new(a.into(), b.into(), c.into())],
+            BlasBuildQuality::default(),
+        ))
+    };
+    let inside = triangle(
+        vec3(-0.5, 0.0, -0.5),
+        vec3(0.5, 0.0, -0.5),
+        vec3(0.0, 0.0, 0.5),
+    );
+    // its bounds overlap the frustum's corner, but the whole triangle is past x + z = 3.5
+    let corner = triangle(
+        vec3(0.5, 0.0, 3.0),
+        vec3(3.0, 0.0, 0.5),
+        vec3(3.0, 0.0, 3.0),
+    );
+    let far = triangle(
+        vec3(5.0, 0.0, 5.0),
+        vec3(6.0, 0.0, 5.0),
+        vec3(5.0, 0.0, 6.0),
+    );
+    world.insert_resource(blases);
+
+    let tlas_e = world.spawn(Tlas::default()).id();
+    let [inside_e, corner_e, _far_e] = [inside, corner, far].map(|blas| {
+        world
+            .spawn((
+                MeshBlas(blas),
+                GlobalTransform::IDENTITY,
+                TlasTarget(tlas_e),
+            ))
+            .id()
+    });
+    world.run_system_once(build_tlas).unwrap();
+
+    // the [-1, 1] cube
+    let frustum = Frustum {
+        half_spaces: [
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::Z,
+            Vec3::NEG_Z,
+        ]
+        .map(|normal| HalfSpace::new(normal.extend(1.0))),
+    };
+
+    let mut state = SystemState::<TlasCast>::new(&mut world);
+    let tlas_cast = state.get(&world);
+    let query = |exact| {
+        let mut hits =
+            tlas_cast.query_frustum(&frustum, tlas_e, exact, &TlasCastSettings::default());
+        hits.sort();
+        hits
+    };
+    let mut bounds = vec![inside_e, corner_e];
+    bounds.sort();
+    assert_eq!(query(false), bounds);
+    assert_eq!(query(true), [inside_e]);
+}
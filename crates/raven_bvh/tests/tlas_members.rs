@@ -0,0 +1,59 @@
+//! [build_tlas] leaves out members whose Blas is missing or empty, and adds them once it loads.
+use bevy::{ecs::system::SystemState, math::bounding::RayCast3d, prelude::*};
+use raven_bvh::{build_tlas, prelude::*};
+
+#[test]
+fn skips_missing_and_empty_blases() {
+    let mut world = World::new();
+    let mut blases = Assets::<Blas>::default();
+    let cube = blases.add(Blas::try_from(&Cuboid::new(1.0, 1.0, 1.0).mesh().build()).unwrap());
+    let empty = blases.add(Blas::build(Vec::new(), BlasBuildQuality::default()));
+    // reserved but not added yet, like one still loading
+    let loading = blases.reserve_handle();
+    world.insert_resource(blases);
+
+    let tlas_e = world
+        .spawn((Tlas::default(), TlasRebuildStrategy::RefitOnChange))
+        .id();
+    let mut member = |blas: &Handle<Blas>, x: f32| {
+        world
+            .spawn((
+                MeshBlas(blas.clone()),
+                GlobalTransform::from_xyz(x, 0.0, 0.0),
+                TlasTarget(tlas_e),
+            ))
+            .id()
+    };
+    let cube_e = member(&cube, 0.0);
+    member(&empty, 3.0);
+    let loading_e = member(&loading, 6.0);
+
+    // a schedule keeps the system's change ticks, so only the Blas loading can trigger the rebuild
+    let mut schedule = Schedule::default();
+    schedule.add_systems(build_tlas);
+    schedule.run(&mut world);
+    assert_eq!(world.get::<Tlas>(tlas_e).unwrap().leaf_count(), 1);
+    schedule.run(&mut world);
+    assert_eq!(world.get::<Tlas>(tlas_e).unwrap().leaf_count(), 1);
+
+    // a ray through every member only hits the cube
+    let ray = RayCast3d::new(vec3(10.0, 0.0, 0.0), Dir3A::NEG_X, 100.0);
+    assert_eq!(cast(&mut world, tlas_e, &ray), Some(cube_e));
+
+    // nothing moved, but the Blas loading changes the membership
+    world.resource_mut::<Assets<Blas>>().insert(
+        &loading,
+        Blas::try_from(&Cuboid::new(1.0, 1.0, 1.0).mesh().build()).unwrap(),
+    );
+    schedule.run(&mut world);
+    assert_eq!(world.get::<Tlas>(tlas_e).unwrap().leaf_count(), 2);
+    assert_eq!(cast(&mut world, tlas_e, &ray), Some(loading_e));
+}
+
+fn cast(world: &mut World, tlas_e: Entity, ray: &RayCast3d) -> Option<Entity> {
+    let mut state = SystemState::<TlasCast>::new(world);
+    let tlas_cast = state.get(world);
+    tlas_cast
+        .intersect_tlas(ray, tlas_e, &TlasCastSettings::default())
+        .map(|(e, _)| e)
+}
@@ -0,0 +1,245 @@
+//! Refitting the TLAS with each [TlasRebuildStrategy] finds the same hits as testing every member.
+use bevy::{
+    ecs::system::SystemState,
+    math::bounding::{Aabb3d, RayCast3d},
+    prelude::*,
+};
+use rand::prelude::*;
+use rand_chacha::{ChaChaRng, rand_core::SeedableRng};
+use raven_bvh::{build_tlas, prelude::*};
+
+const GRID_SIZE: i32 = 6;
+const SPACING: f32 = 3.0;
+const RAY_COUNT: usize = 256;
+
+#[test]
+fn refit_on_change() {
+    let (mut world, mut schedule, tlas_e, members) = setup(TlasRebuildStrategy::RefitOnChange);
+    let mut rng = ChaChaRng::seed_from_u64(0);
+    let before = branches(&world, tlas_e);
+
+    // small moves only refit, the branches keep their children
+    for e in members.iter().step_by(3) {
+        nudge(&mut world, *e, Vec3::splat(0.3));
+    }
+    schedule.run(&mut world);
+    assert_eq!(branches(&world, tlas_e), before);
+    assert_matches_members(&mut world, tlas_e, &mut rng);
+
+    // nothing moved, nothing changes
+    schedule.run(&mut world);
+    assert_eq!(branches(&world, tlas_e), before);
+}
+
+#[test]
+fn rebuild_subtree_matches_full_rebuild() {
+    let (mut world, mut schedule, tlas_e, members) = setup(TlasRebuildStrategy::RefitOnChange);
+    let mut rng = ChaChaRng::seed_from_u64(1);
+    let extent = GRID_SIZE as f32 * SPACING;
+
+    let before = branches(&world, tlas_e);
+
+    // swapping corners grows their subtrees far past PARTIAL_REBUILD_RATIO
+    let first = members[0];
+    let last = *members.last().unwrap();
+    nudge(&mut world, first, Vec3::splat(extent));
+    nudge(&mut world, last, Vec3::splat(-extent));
+    schedule.run(&mut world);
+    assert_ne!(branches(&world, tlas_e), before);
+    assert_matches_members(&mut world, tlas_e, &mut rng);
+
+    // every member is still a leaf exactly once
+    let tlas = world.get::<Tlas>(tlas_e).unwrap();
+    assert_eq!(tlas.tlas_nodes.len(), 2 * members.len() - 1);
+    let mut leaves = tlas
+        .tlas_nodes
+        .iter()
+        .filter_map(|node| match tlas.node_type(node) {
+            TlasNodeType::Leaf(e) => Some(e),
+            TlasNodeType::Branch { .. } => None,
+        })
+        .collect::<Vec<_>>();
+    leaves.sort();
+    let mut expected = members.clone();
+    expected.sort();
+    assert_eq!(leaves, expected);
+
+    // the partially rebuilt tree is no worse than building it again from scratch
+    let rebuilt_cost = sah_cost(world.get::<Tlas>(tlas_e).unwrap());
+    let mut full = Tlas::default();
+    full.build(
+        tlas.leaf_entities
+            .iter()
+            .map(|e| (*e, leaf_aabb(tlas, *e)))
+            .collect::<Vec<_>>(),
+    );
+    assert!(
+        rebuilt_cost <= sah_cost(&full) * 1.5,
+        "partial {rebuilt_cost} full {}",
+        sah_cost(&full)
+    );
+}
+
+#[test]
+fn every_n_frames() {
+    let (mut world, mut schedule, tlas_e, members) = setup(TlasRebuildStrategy::every_n_frames(3));
+    let mut rng = ChaChaRng::seed_from_u64(2);
+    let elapsed = |world: &World| match world.get::<TlasRebuildStrategy>(tlas_e).unwrap() {
+        TlasRebuildStrategy::EveryNFrames { elapsed, .. } => *elapsed,
+        _ => unreachable!(),
+    };
+    // the first update built it
+    assert_eq!(elapsed(&world), 0);
+
+    // refits in between, rebuilds on the third frame
+    for expected in [1, 2, 0, 1] {
+        nudge(&mut world, members[expected as usize], Vec3::X);
+        schedule.run(&mut world);
+        assert_eq!(elapsed(&world), expected);
+        assert_matches_members(&mut world, tlas_e, &mut rng);
+    }
+
+    // a new member rebuilds straight away
+    let cube = world.get::<MeshBlas>(members[0]).unwrap().0.clone();
+    world.spawn((
+        MeshBlas(cube),
+        GlobalTransform::from_xyz(-SPACING, 0.0, 0.0),
+        TlasTarget(tlas_e),
+    ));
+    schedule.run(&mut world);
+    assert_eq!(elapsed(&world), 0);
+    assert_eq!(
+        world.get::<Tlas>(tlas_e).unwrap().leaf_count(),
+        members.len() + 1
+    );
+    assert_matches_members(&mut world, tlas_e, &mut rng);
+}
+
+/// A grid of cubes, built once by the returned schedule
+fn setup(strategy: TlasRebuildStrategy) -> (World, Schedule, Entity, Vec<Entity>) {
+    let mut world = World::new();
+    let mut blases = Assets::<Blas>::default();
+    let cube = blases.add(Blas::try_from(&Cuboid::new(1.0, 1.0, 1.0).mesh().build()).unwrap());
+    world.insert_resource(blases);
+
+    let tlas_e = world.spawn((Tlas::default(), strategy)).id();
+    let mut members = Vec::new();
+    for x in 0..GRID_SIZE {
+        for y in 0..GRID_SIZE {
+            for z in 0..GRID_SIZE {
+                let transform =
+                    Transform::from_translation(vec3(x as f32, y as f32, z as f32) * SPACING)
+                        .with_rotation(Quat::from_rotation_y((x + y + z) as f32));
+                members.push(
+                    world
+                        .spawn((
+                            MeshBlas(cube.clone()),
+                            GlobalTransform::from(transform),
+                            TlasTarget(tlas_e),
+                        ))
+                        .id(),
+                );
+            }
+        }
+    }
+
+    // a schedule keeps the system's change ticks between runs, so only moved members count as changed
+    let mut schedule = Schedule::default();
+    schedule.add_systems(build_tlas);
+    schedule.run(&mut world);
+    (world, schedule, tlas_e, members)
+}
+
+fn nudge(world: &mut World, e: Entity, offset: Vec3) {
+    let mut global_trans = world.get_mut::<GlobalTransform>(e).unwrap();
+    let transform = global_trans.compute_transform();
+    *global_trans =
+        GlobalTransform::from(transform.with_translation(transform.translation + offset));
+}
+
+/// Children of each branch node
+fn branches(world: &World, tlas_e: Entity) -> Vec<(u32, u32)> {
+    let tlas = world.get::<Tlas>(tlas_e).unwrap();
+    tlas.tlas_nodes
+        .iter()
+        .filter_map(|node| match tlas.node_type(node) {
+            TlasNodeType::Branch { left, right } => Some((left, right)),
+            TlasNodeType::Leaf(_) => None,
+        })
+        .collect()
+}
+
+fn leaf_aabb(tlas: &Tlas, e: Entity) -> Aabb3d {
+    tlas.tlas_nodes
+        .iter()
+        .find(|node| matches!(tlas.node_type(node), TlasNodeType::Leaf(leaf) if leaf == e))
+        .unwrap()
+        .aabb()
+}
+
+/// Surface area heuristic cost, the summed area of the branches relative to the root
+fn sah_cost(tlas: &Tlas) -> f32 {
+    let area = |aabb: Aabb3d| {
+        let size = aabb.max - aabb.min;
+        size.x * size.y + size.y * size.z + size.z * size.x
+    };
+    let root = area(tlas.tlas_nodes[0].aabb());
+    tlas.tlas_nodes
+        .iter()
+        .filter(|node| !node.is_leaf())
+        .map(|node| area(node.aabb()) / root)
+        .sum()
+}
+
+/// Closest hit of random rays through the grid against testing every member.
+fn assert_matches_members(world: &mut World, tlas_e: Entity, rng: &mut ChaChaRng) {
+    let mut state = SystemState::<(
+        TlasCast,
+        Query<(Entity, &MeshBlas, &GlobalTransform)>,
+        Res<Assets<Blas>>,
+    )>::new(world);
+    let (tlas_cast, members, blases) = state.get(world);
+
+    let extent = GRID_SIZE as f32 * SPACING;
+    let mut hits = 0;
+    for _ in 0..RAY_COUNT {
+        let origin = vec3(
+            rng.random_range(-extent..extent * 2.0),
+            rng.random_range(-extent..extent * 2.0),
+            -extent,
+        );
+        let target = vec3(
+            rng.random_range(0.0..extent),
+            rng.random_range(0.0..extent),
+            extent,
+        );
+        let ray = RayCast3d::new(origin, Dir3A::new((target - origin).into()).unwrap(), 1e30);
+        let hit = tlas_cast.intersect_tlas(&ray, tlas_e, &TlasCastSettings::default());
+
+        let mut expected: Option<(Entity, f32)> = None;
+        for (e, mesh_blas, global_trans) in members.iter() {
+            let blas = blases.get(&mesh_blas.0).unwrap();
+            let (local_ray, dir_scale) = ray.to_local(global_trans);
+            if let Some(hit) = local_ray.intersect_bvh(blas) {
+                let distance = hit.distance / dir_scale;
+                if expected.is_none_or(|(_, best)| distance < best) {
+                    expected = Some((e, distance));
+                }
+            }
+        }
+
+        match (hit, expected) {
+            (Some((_, hit)), Some((_, distance))) => {
+                assert!(
+                    (hit.distance - distance).abs() < 1e-3,
+                    "hit at {} expected {distance}",
+                    hit.distance
+                );
+                hits += 1;
+            }
+            (None, None) => {}
+            (hit, expected) => panic!("hit {hit:?} expected {expected:?}"),
+        }
+    }
+    assert!(hits > 0);
+}
@@ -0,0 +1,156 @@
+//! BVH4 and BVH8 layouts and 2x2 ray packets return the same hits as scalar rays on the binary tree.
+use bevy::{
+    ecs::system::{RunSystemOnce, SystemState},
+    math::bounding::RayCast3d,
+    prelude::*,
+};
+use rand::prelude::*;
+use rand_chacha::{ChaChaRng, rand_core::SeedableRng};
+use raven_bvh::{build_tlas, prelude::*};
+
+const RAY_COUNT: usize = 1024;
+const EXTENT: f32 = 10.0;
+
+#[test]
+fn wide_and_packets_match_scalar() {
+    let mut rng = ChaChaRng::seed_from_u64(0);
+    // a leaf root, a branch root with two leaves and a tree deep enough to fill BVH8 nodes
+    for tri_count in [1, 2, 5, 2000] {
+        let tris = random_tris(&mut rng, tri_count);
+        for quality in [
+            BlasBuildQuality::default(),
+            BlasBuildQuality::FullSweep,
+            BlasBuildQuality::spatial(),
+        ] {
+            let scalar = Blas::build(tris.clone(), quality);
+            let wide = [BlasWidth::Four, BlasWidth::Eight].map(|width| {
+                let mut blas = Blas::build(tris.clone(), quality);
+                blas.collapse(width);
+                assert_eq!(blas.width(), width);
+                blas
+            });
+
+            let rays = random_rays(&mut rng, RAY_COUNT);
+            let mut hits = 0;
+            for rays in rays.chunks(RayPacket::LANES) {
+                let packet_hits = RayPacket::new(rays).intersect_bvh(&scalar);
+                for (lane, ray) in rays.iter().enumerate() {
+                    let expected = ray.intersect_bvh(&scalar);
+                    hits += expected.is_some() as usize;
+                    for blas in wide.iter() {
+                        let hit = ray.intersect_bvh(blas);
+                        assert_eq!(
+                            hit.map(|h| (h.tri_index, h.distance)),
+                            expected.map(|h| (h.tri_index, h.distance)),
+                            "{tri_count} triangles {quality:?} {:?}",
+                            blas.width()
+                        );
+                        assert_eq!(ray.intersect_bvh_any(blas).is_some(), expected.is_some());
+                    }
+                    assert_same_hit(packet_hits[lane], expected);
+                }
+            }
+            assert!(hits > 0, "{tri_count} triangles {quality:?} never hit");
+        }
+    }
+}
+
+#[test]
+fn tlas_packets_match_scalar() {
+    let mut rng = ChaChaRng::seed_from_u64(1);
+    let mut world = World::new();
+    let mut blases = Assets::<Blas>::default();
+    let shapes = [BlasWidth::Two, BlasWidth::Four, BlasWidth::Eight].map(|width| {
+        let mut blas = Blas::try_from(&Sphere::new(0.6).mesh().build()).unwrap();
+        blas.collapse(width);
+        blases.add(blas)
+    });
+    world.insert_resource(blases);
+
+    let tlas_e = world.spawn(Tlas::default()).id();
+    for i in 0..200 {
+        let transform = Transform::from_translation(random_point(&mut rng))
+            .with_rotation(Quat::from_rotation_y(rng.random_range(0.0..6.0)))
+            .with_scale(vec3(1.0 + rng.random::<f32>(), 1.0, 0.7));
+        world.spawn((
+            MeshBlas(shapes[i % shapes.len()].clone()),
+            GlobalTransform::from(transform),
+            TlasTarget(tlas_e),
+        ));
+    }
+    world.run_system_once(build_tlas).unwrap();
+
+    let mut state = SystemState::<TlasCast>::new(&mut world);
+    let tlas_cast = state.get(&world);
+    let settings = TlasCastSettings::default();
+    let mut hits = 0;
+    for rays in random_rays(&mut rng, RAY_COUNT).chunks(RayPacket::LANES) {
+        let packet = RayPacket::new(rays);
+        let packet_hits = tlas_cast.intersect_tlas_packet(&packet, tlas_e, &settings);
+        for (lane, ray) in rays.iter().enumerate() {
+            let expected = tlas_cast.intersect_tlas(ray, tlas_e, &settings);
+            hits += expected.is_some() as usize;
+            assert_eq!(packet_hits[lane].map(|(e, _)| e), expected.map(|(e, _)| e));
+            assert_same_hit(
+                packet_hits[lane].map(|(_, hit)| hit),
+                expected.map(|(_, hit)| hit),
+            );
+        }
+    }
+    assert!(hits > RAY_COUNT / 4, "{hits}");
+}
+
+/// Packets test 4 rays at once with a slightly different triangle test, so distances can differ a little
+fn assert_same_hit(hit: Option<BlasHit>, expected: Option<BlasHit>) {
+    match (hit, expected) {
+        (Some(hit), Some(expected)) => {
+            assert_eq!(hit.tri_index, expected.tri_index);
+            assert!(
+                (hit.distance - expected.distance).abs() < 1e-4,
+                "hit at {} expected {}",
+                hit.distance,
+                expected.distance
+            );
+        }
+        (None, None) => {}
+        _ => panic!("hit {hit:?} expected {expected:?}"),
+    }
+}
+
+fn random_point(rng: &mut ChaChaRng) -> Vec3 {
+    vec3(
+        rng.random_range(-EXTENT..EXTENT),
+        rng.random_range(-EXTENT..EXTENT),
+        rng.random_range(-EXTENT..EXTENT),
+    )
+}
+
+/// Triangles scattered through the extent, every tenth one large enough to overlap its neighbours
+fn random_tris(rng: &mut ChaChaRng, count: usize) -> Vec<Tri> {
+    (0..count)
+        .map(|i| {
+            let size = if i % 10 == 0 { 8.0 } else { 1.0 };
+            let v0 = random_point(rng);
+            let v1 = v0 + random_point(rng) / EXTENT * size;
+            let v2 = v0 + random_point(rng) / EXTENT * size;
+            Tri::new(v0.into(), v1.into(), v2.into())
+        })
+        .collect()
+}
+
+/// Rays from outside the extent towards random points inside it, a packet's rays start together
+fn random_rays(rng: &mut ChaChaRng, count: usize) -> Vec<RayCast3d> {
+    let mut rays = Vec::with_capacity(count);
+    while rays.len() < count {
+        let origin = random_point(rng).normalize() * EXTENT * 2.0;
+        for _ in 0..RayPacket::LANES {
+            let target = random_point(rng) * 0.5;
+            rays.push(RayCast3d::new(
+                origin,
+                Dir3A::new((target - origin).into()).unwrap(),
+                1e30,
+            ));
+        }
+    }
+    rays
+}
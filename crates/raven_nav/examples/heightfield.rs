@@ -147,8 +147,8 @@ fn ray_cast(
         };
 
         let ray_cast = RayCast3d::from_ray(ray, f32::MAX);
-        if let Some((_e, hit)) = tlas.intersect_tlas(&ray_cast, tlas_entity) {        
-            *start_pos = ray.get_point(hit.distance);
+        if let Some(hit) = tlas.cast_ray(&ray_cast, tlas_entity, &TlasCastSettings::default()) {        
+            *start_pos = hit.point;
         }
     }
 
@@ -161,8 +161,8 @@ fn ray_cast(
             return;
         };
         let ray_cast = RayCast3d::from_ray(ray, f32::MAX);
-        if let Some((_e, hit)) = tlas.intersect_tlas(&ray_cast, tlas_entity) {        
-            *end_pos = ray.get_point(hit.distance);
+        if let Some(hit) = tlas.cast_ray(&ray_cast, tlas_entity, &TlasCastSettings::default()) {        
+            *end_pos = hit.point;
         }
     }
     
@@ -172,7 +172,7 @@ fn ray_cast(
 
     // Run pathfinding to get a polygon path.
     match nav_path.find_path(tlas_entity, *start_pos, *end_pos, None, Some(&[1.0, 0.5])) {
-        Ok(path) => gizmos.linestrip(path, tailwind::BLUE_300),
+        Ok(path) => gizmos.linestrip(path.points, tailwind::BLUE_300),
         Err(error) => error!("Error with pathfinding: {:?}", error),
     }    
 }
\ No newline at end of file
@@ -1,6 +1,9 @@
 use bevy::prelude::*;
 
-use crate::nav::NavAgents;
+use crate::{
+    nav::NavAgents,
+    path::{NavPath, PathError},
+};
 
 #[derive(Component, Reflect)]
 #[require(
@@ -76,5 +79,158 @@ pub enum AgentState {
     NoPath,
 }
 
+/// Velocity the agent should move at this frame, its [Velocity] with avoidance applied.
+///
+/// Agents are only moved by it with [AgentMover], otherwise apply it to your own movement, like a physics velocity.
 #[derive(Component, Default)]
 pub struct AgentDesiredVelocity(pub Vec3);
+
+/// Opt-in for the [crate::NavPlugin] to move the agent by writing its [Transform].
+///
+/// Leave it off for agents moved by physics or your own controller.
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct AgentMover;
+
+/// Path an agent follows to its [AgentTarget], planned by the [crate::NavPlugin].
+///
+/// A new path is planned once the target moves further than the agent's radius from ``target``.
+#[derive(Component, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct AgentPath {
+    pub points: Vec<Vec3>,
+    /// Index of the point the agent is heading to.
+    pub next: usize,
+    /// Where the target was when the path was planned.
+    pub target: Vec3,
+}
+
+/// Agents closer than this to the end of their path have reached their target.
+const ARRIVAL_DISTANCE: f32 = 0.05;
+
+/// Plans an [AgentPath] when an agent's [AgentTarget] is set or has moved.
+#[allow(clippy::type_complexity)]
+pub(crate) fn update_agent_paths(
+    mut commands: Commands,
+    mut agent_query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &AgentTarget,
+            &AgentSettings,
+            Option<&AgentNav>,
+            Option<&AgentPath>,
+            &mut AgentState,
+        ),
+        With<Agent>,
+    >,
+    target_query: Query<&GlobalTransform>,
+    mut nav_path: NavPath,
+) {
+    for (e, trans, target, settings, agent_nav, path, mut state) in agent_query.iter_mut() {
+        let target = match target {
+            AgentTarget::None => None,
+            AgentTarget::Point(point) => Some(*point),
+            AgentTarget::Entity(target_e) => {
+                target_query.get(*target_e).ok().map(|t| t.translation())
+            }
+        };
+        let Some(target) = target else {
+            if path.is_some() {
+                commands.entity(e).remove::<AgentPath>();
+            }
+            state.set_if_neq(AgentState::Idle);
+            continue;
+        };
+        if path.is_some_and(|path| path.target.distance(target) <= settings.radius) {
+            continue;
+        }
+        let Some(agent_nav) = agent_nav else {
+            state.set_if_neq(AgentState::AgentNotOnNavMesh);
+            continue;
+        };
+
+        // failed paths are kept empty, so they are only retried once the target moves
+        let (points, new_state) =
+            match nav_path.find_path(agent_nav.0, trans.translation(), target, None, None) {
+                Ok(result) => (result.points, AgentState::Moving),
+                Err(PathError::NoValidStartPolygon { .. }) => {
+                    (Vec::new(), AgentState::AgentNotOnNavMesh)
+                }
+                Err(PathError::NoValidEndPolygon { .. }) => {
+                    (Vec::new(), AgentState::TargetNotOnNavMesh)
+                }
+                Err(_) => (Vec::new(), AgentState::NoPath),
+            };
+        state.set_if_neq(new_state);
+        commands.entity(e).insert(AgentPath {
+            points,
+            next: 1,
+            target,
+        });
+    }
+}
+
+/// Sets each agent's preferred [Velocity] towards the next point of its [AgentPath], slowing down for the last one.
+#[allow(clippy::type_complexity)]
+pub(crate) fn steer_agents(
+    mut agent_query: Query<
+        (
+            &GlobalTransform,
+            &AgentSettings,
+            &mut Velocity,
+            Option<&mut AgentPath>,
+            &mut AgentState,
+        ),
+        With<Agent>,
+    >,
+) {
+    for (trans, settings, mut velocity, path, mut state) in agent_query.iter_mut() {
+        let Some(mut path) = path else {
+            velocity.0 = Vec3::ZERO;
+            continue;
+        };
+        let position = trans.translation();
+
+        // corners within reach are passed
+        while path.next + 1 < path.points.len()
+            && position.xz().distance(path.points[path.next].xz()) <= settings.radius
+        {
+            path.next += 1;
+        }
+        let Some(next) = path.points.get(path.next).copied() else {
+            velocity.0 = Vec3::ZERO;
+            continue;
+        };
+        let to_next = next - position;
+        let distance = to_next.xz().length();
+        let is_last = path.next + 1 == path.points.len();
+        if is_last && distance <= ARRIVAL_DISTANCE {
+            velocity.0 = Vec3::ZERO;
+            state.set_if_neq(AgentState::ReachedTarget);
+            continue;
+        }
+
+        let speed = match is_last {
+            true => settings.desired_speed * (distance / settings.radius).min(1.0),
+            false => settings.desired_speed,
+        };
+        velocity.0 = to_next.normalize_or_zero() * speed;
+        state.set_if_neq(AgentState::Moving);
+    }
+}
+
+/// Moves [AgentMover] agents by their [AgentDesiredVelocity], which then becomes their [Velocity].
+#[allow(clippy::type_complexity)]
+pub(crate) fn move_agents(
+    mut agent_query: Query<
+        (&mut Transform, &AgentDesiredVelocity, &mut Velocity),
+        (With<Agent>, With<AgentMover>),
+    >,
+    time: Res<Time>,
+) {
+    for (mut transform, desired, mut velocity) in agent_query.iter_mut() {
+        transform.translation += desired.0 * time.delta_secs();
+        velocity.0 = desired.0;
+    }
+}
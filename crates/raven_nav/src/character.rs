@@ -1,7 +1,16 @@
-use bevy::prelude::*;
+use bevy::{ecs::entity::EntityHashMap, platform::collections::HashMap, prelude::*};
 
-use crate::{agent::Velocity, nav::NavCharacters};
+use crate::{
+    agent::{Agent, AgentDesiredVelocity, AgentNav, AgentSettings, AgentState, Velocity},
+    nav::{AgentOptions, Nav, NavAgents, NavCharacters, TileLookup},
+    path::NavPath,
+    tile::Tile,
+};
 
+/// A non-agent mover, like a player controlled character or an idle unit.
+///
+/// Characters never trigger tile rebuilds, they are soft obstacles agents avoid locally,
+/// see [CharacterSettings::path_cost] to also weight the polygons they stand on.
 #[derive(Component, Reflect)]
 #[require(Transform, Velocity, CharacterSettings)]
 pub struct Character;
@@ -17,10 +26,290 @@ pub struct CharacterWaymap(pub Entity);
 pub struct CharacterSettings {
     /// The radius of the character.
     pub radius: f32,
+    /// Extra cost multiplier for pathing through the polygon the character stands on,
+    /// a value of 1.0 doubles the cost. 0.0 disables it.
+    pub path_cost: f32,
 }
 
 impl Default for CharacterSettings {
     fn default() -> Self {
-        Self { radius: 0.5 }
+        Self {
+            radius: 0.5,
+            path_cost: 0.0,
+        }
+    }
+}
+
+/// Extra path cost multiplier per polygon of the tile from characters standing on it.
+#[derive(Component, Default, Debug, Clone, PartialEq, Deref, DerefMut, Reflect)]
+#[reflect(Component)]
+pub struct TileCharacterCosts(pub HashMap<u16, f32>);
+
+/// Rebuilds [TileCharacterCosts] from the characters with a [CharacterSettings::path_cost].
+///
+/// Only tiles whose costs actually changed are written to, so path queries see a stable set.
+pub(crate) fn update_character_costs(
+    nav_query: Query<(Entity, &TileLookup, &NavCharacters), With<Nav>>,
+    character_query: Query<(&GlobalTransform, &CharacterSettings), With<Character>>,
+    // NavPath reads the costs for pathfinding, so it can't be borrowed alongside the writes
+    mut params: ParamSet<(NavPath, Query<&mut TileCharacterCosts, With<Tile>>)>,
+    mut costs: Local<EntityHashMap<HashMap<u16, f32>>>,
+) {
+    for (nav_e, lookup, characters) in nav_query.iter() {
+        let nav_path = params.p0();
+        for (trans, settings) in character_query.iter_many(characters.iter()) {
+            if settings.path_cost <= 0.0 {
+                continue;
+            }
+            let Some((tile, polygon, _)) =
+                nav_path.find_closest_polygon_in_box(nav_e, trans.translation(), settings.radius)
+            else {
+                continue;
+            };
+            let Some(tile_e) = lookup.get(&tile) else {
+                continue;
+            };
+            *costs
+                .entry(*tile_e)
+                .or_default()
+                .entry(polygon)
+                .or_default() += settings.path_cost;
+        }
+
+        let mut cost_query = params.p1();
+        for tile_e in lookup.values() {
+            let Ok(mut tile_costs) = cost_query.get_mut(*tile_e) else {
+                continue;
+            };
+            let new_costs = costs.remove(tile_e).unwrap_or_default();
+            tile_costs.set_if_neq(TileCharacterCosts(new_costs));
+        }
+        costs.clear();
+    }
+}
+
+/// Measures each character's [Velocity] from how far it moved since the last frame.
+pub(crate) fn update_character_velocities(
+    mut character_query: Query<
+        (Entity, &GlobalTransform, &mut Velocity),
+        (With<Character>, Without<Agent>),
+    >,
+    mut last_positions: Local<EntityHashMap<Vec3>>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_secs();
+    let mut positions = EntityHashMap::default();
+    for (e, trans, mut velocity) in character_query.iter_mut() {
+        let position = trans.translation();
+        velocity.0 = match last_positions.get(&e) {
+            Some(last) if delta > 0.0 => (position - *last) / delta,
+            _ => Vec3::ZERO,
+        };
+        positions.insert(e, position);
+    }
+    *last_positions = positions;
+}
+
+/// Shortest time an overlap is cleared over, so touching neighbours don't push infinitely hard.
+const MIN_AVOIDANCE_TIME: f32 = 0.1;
+
+/// A disc an agent steers around, on the XZ-plane.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AvoidanceNeighbour {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub radius: f32,
+    /// Share of the avoidance this agent takes on, other agents steer around it too.
+    pub responsibility: f32,
+    /// How far ahead in seconds a collision is avoided.
+    pub horizon: f32,
+}
+
+/// ``velocity`` steered away from the ``neighbours`` it would come within their radius of.
+///
+/// Neighbours are treated as moving discs, the agent pushes away from where they would be closest.
+pub(crate) fn avoidance_velocity(
+    position: Vec2,
+    velocity: Vec2,
+    settings: &AgentSettings,
+    neighbours: impl IntoIterator<Item = AvoidanceNeighbour>,
+) -> Vec2 {
+    let mut avoidance = Vec2::ZERO;
+    for neighbour in neighbours {
+        let offset = neighbour.position - position;
+        let radius = settings.radius + neighbour.radius;
+        let horizon = neighbour.horizon;
+
+        // time of closest approach, then how far apart we would be
+        let relative_vel = velocity - neighbour.velocity;
+        let t = match relative_vel.length_squared() > f32::EPSILON {
+            true => (offset.dot(relative_vel) / relative_vel.length_squared()).clamp(0.0, horizon),
+            false => 0.0,
+        };
+        // from the neighbour to the agent at that time
+        let separation = relative_vel * t - offset;
+        let distance = separation.length();
+        if distance >= radius {
+            continue;
+        }
+
+        // head on, pick a side
+        let away = match distance > f32::EPSILON {
+            true => separation / distance,
+            false => offset.perp().normalize_or_zero(),
+        };
+        // the velocity change that clears the overlap by then, so sooner pushes harder
+        avoidance +=
+            away * (radius - distance) / t.max(MIN_AVOIDANCE_TIME) * neighbour.responsibility;
+    }
+    (velocity + avoidance).clamp_length_max(settings.max_speed)
+}
+
+/// Steers agents around nearby characters and agents, [AgentDesiredVelocity] is the agent's [Velocity]
+/// with the avoidance applied, see [avoidance_velocity].
+///
+/// Characters are avoided within [AgentOptions::obstacle_avoidance_time_horizon], other agents avoid
+/// this one too so each takes half within [AgentOptions::avoidance_time_horizon].
+#[allow(clippy::type_complexity)]
+pub(crate) fn avoid_characters(
+    nav_query: Query<(&AgentOptions, &NavCharacters, &NavAgents)>,
+    mut agent_query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &Velocity,
+            &AgentSettings,
+            &AgentState,
+            Option<&AgentNav>,
+            &mut AgentDesiredVelocity,
+        ),
+        With<Agent>,
+    >,
+    neighbour_agent_query: Query<(&GlobalTransform, &Velocity, &AgentSettings), With<Agent>>,
+    character_query: Query<(&GlobalTransform, &Velocity, &CharacterSettings), With<Character>>,
+) {
+    for (e, agent_trans, velocity, settings, state, agent_nav, mut desired) in
+        agent_query.iter_mut()
+    {
+        let Some((options, characters, agents)) =
+            agent_nav.and_then(|agent_nav| nav_query.get(agent_nav.0).ok())
+        else {
+            desired.0 = velocity.0;
+            continue;
+        };
+        let position = agent_trans.translation().xz();
+        let in_neighbourhood = |trans: &GlobalTransform| {
+            trans.translation().xz().distance(position) <= options.neighbourhood
+        };
+
+        // agents that got where they were going make way for the others
+        let share = match state {
+            AgentState::ReachedTarget => options.reached_destination_avoidance_responsibility,
+            _ => 1.0,
+        };
+        let characters = character_query
+            .iter_many(characters.iter())
+            .filter(|(trans, _, _)| in_neighbourhood(trans))
+            .map(|(trans, velocity, character)| AvoidanceNeighbour {
+                position: trans.translation().xz(),
+                velocity: velocity.0.xz(),
+                radius: character.radius,
+                responsibility: share,
+                horizon: options.obstacle_avoidance_time_horizon,
+            });
+        let agents = agents
+            .iter()
+            .filter(|other| *other != e)
+            .filter_map(|other| neighbour_agent_query.get(other).ok())
+            .filter(|(trans, _, _)| in_neighbourhood(trans))
+            .map(|(trans, velocity, other)| AvoidanceNeighbour {
+                position: trans.translation().xz(),
+                velocity: velocity.0.xz(),
+                radius: other.radius,
+                responsibility: 0.5 * share,
+                horizon: options.avoidance_time_horizon,
+            });
+
+        let result = avoidance_velocity(
+            position,
+            velocity.0.xz(),
+            settings,
+            characters.chain(agents),
+        );
+        desired.0 = Vec3::new(result.x, velocity.0.y, result.y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_avoid_oncoming_character() {
+        let settings = AgentSettings::default();
+        let character = AvoidanceNeighbour {
+            position: vec2(3.0, 0.2),
+            velocity: vec2(-1.0, 0.0),
+            radius: 0.5,
+            responsibility: 1.0,
+            horizon: 2.0,
+        };
+        // steers away from the side the character is on
+        let velocity = avoidance_velocity(Vec2::ZERO, Vec2::X, &settings, [character]);
+        assert!(velocity.y < 0.0, "{velocity}");
+        assert!(velocity.length() <= settings.max_speed + 1e-5);
+
+        // passing by far enough, nothing changes
+        let far = AvoidanceNeighbour {
+            position: vec2(3.0, 2.0),
+            ..character
+        };
+        assert_eq!(
+            avoidance_velocity(Vec2::ZERO, Vec2::X, &settings, [far]),
+            Vec2::X
+        );
+    }
+
+    #[test]
+    fn test_crossing_agents_deviate() {
+        let settings = AgentSettings::default();
+        let goals = [vec2(4.0, 0.0), vec2(0.0, 4.0)];
+        let mut positions = [vec2(-4.0, 0.0), vec2(0.0, -4.5)];
+        let mut velocities = [Vec2::ZERO; 2];
+        let mut max_deviation = [0.0f32; 2];
+        let mut min_distance = f32::MAX;
+
+        let dt = 0.05;
+        for _ in 0..300 {
+            let preferred = [0, 1].map(|i| {
+                let to_goal = goals[i] - positions[i];
+                to_goal.clamp_length_max(settings.desired_speed)
+            });
+            let desired = [0, 1].map(|i| {
+                let other = 1 - i;
+                let neighbour = AvoidanceNeighbour {
+                    position: positions[other],
+                    velocity: velocities[other],
+                    radius: settings.radius,
+                    responsibility: 0.5,
+                    horizon: 1.0,
+                };
+                avoidance_velocity(positions[i], preferred[i], &settings, [neighbour])
+            });
+            for i in 0..2 {
+                velocities[i] = desired[i];
+                positions[i] += desired[i] * dt;
+            }
+            max_deviation[0] = max_deviation[0].max(positions[0].y.abs());
+            max_deviation[1] = max_deviation[1].max(positions[1].x.abs());
+            min_distance = min_distance.min(positions[0].distance(positions[1]));
+        }
+
+        // without avoidance they would pass the origin half a second apart, well inside each other
+        assert!(max_deviation.iter().all(|d| *d > 0.1), "{max_deviation:?}");
+        assert!(min_distance > settings.radius, "{min_distance}");
+        for i in 0..2 {
+            assert!(positions[i].distance(goals[i]) < 0.5, "{i} didn't arrive");
+        }
     }
 }
@@ -2,10 +2,11 @@ use std::sync::Arc;
 
 use avian3d::parry::{
     math::Isometry,
-    na::Point3,
-    shape::{self, TypedShape},
+    na::{Point3, Vector3},
+    shape::{self, Shape, TypedShape},
+    transformation::convex_hull,
 };
-use bevy::{ecs::entity::EntityHashMap, prelude::*};
+use bevy::{ecs::entity::EntityHashMap, math::bounding::Aabb3d, prelude::*};
 
 /// Add this to any compoent with a Collider to indicate that it is a nav-mesh affector.
 #[derive(Component, Reflect)]
@@ -26,6 +27,33 @@ pub struct Area(pub u16);
 #[reflect(Component)]
 pub struct UpdateTileAffectors;
 
+/// Implement for custom parry shapes to use them as nav-mesh affectors, register them with [CustomNavShapes::register].
+pub trait CustomNavShape: Shape {
+    /// Vertices and triangle indices of the shape in its local space.
+    fn nav_triangles(&self) -> (Vec<Point3<f32>>, Vec<[u32; 3]>);
+}
+
+type CustomNavShapeFn = fn(&dyn Shape) -> Option<(Vec<Point3<f32>>, Vec<[u32; 3]>)>;
+
+/// Registered [CustomNavShape] types, checked for ``TypedShape::Custom`` colliders.
+///
+/// ```ignore
+/// app.world_mut().resource_mut::<CustomNavShapes>().register::<MyShape>();
+/// ```
+#[derive(Resource, Default)]
+pub struct CustomNavShapes(Vec<CustomNavShapeFn>);
+
+impl CustomNavShapes {
+    pub fn register<T: CustomNavShape>(&mut self) {
+        self.0
+            .push(|shape| shape.downcast_ref::<T>().map(T::nav_triangles));
+    }
+
+    fn triangles(&self, shape: &dyn Shape) -> Option<(Vec<Point3<f32>>, Vec<[u32; 3]>)> {
+        self.0.iter().find_map(|triangles| triangles(shape))
+    }
+}
+
 // Rest of this file is utility functions for converting colliders to triangles
 // TODO: convert GeometryCollection, HeightFieldCollection, HeightFieldCollection to single type with enum
 
@@ -33,6 +61,8 @@ pub enum GeometryResult<'a> {
     Compound(Vec<(Isometry<f32>, GeometryResult<'a>)>),
     GeometryToConvert(GeometryToConvert),
     Heightfield(&'a shape::HeightField),
+    /// Infinite plane, clipped to the tile once we know where it is.
+    HalfSpace(shape::HalfSpace),
     Unsupported,
 }
 
@@ -156,6 +186,7 @@ pub(crate) fn handle_geometry_result(
     entity: Entity,
     collider_transform: GlobalTransform,
     area: Option<Area>,
+    tile_aabb: &Aabb3d,
     geometry_collections: &mut Vec<GeometryCollection>,
     heightfield_collections: &mut Vec<HeightFieldCollection>,
     entity_heightfield_map: &mut EntityHashMap<Arc<shape::HeightField>>,
@@ -199,17 +230,139 @@ pub(crate) fn handle_geometry_result(
                     entity,
                     local_trans * collider_transform,
                     area,
+                    tile_aabb,
                     geometry_collections,
                     heightfield_collections,
                     entity_heightfield_map,
                 );
             }
         }
+        GeometryResult::HalfSpace(half_space) => {
+            // triangles are built in tile space, so no transform
+            if let Some((vertices, triangles)) =
+                clip_half_space(&half_space, &collider_transform, tile_aabb)
+            {
+                geometry_collections.push(GeometryCollection {
+                    transform: GlobalTransform::IDENTITY,
+                    geometry_to_convert: GeometryToConvert::ParryTriMesh(vertices, triangles),
+                    area,
+                });
+            }
+        }
         GeometryResult::Unsupported => {}
     }
 }
 
-pub fn get_geometry_type(collider: TypedShape) -> GeometryResult {
+/// Builds a quad on the half-space's plane covering ``tile_aabb``, both in tile space.
+///
+/// Returns ``None`` when the plane doesn't cross the tile.
+fn clip_half_space(
+    half_space: &shape::HalfSpace,
+    collider_transform: &GlobalTransform,
+    tile_aabb: &Aabb3d,
+) -> Option<(Box<[Point3<f32>]>, Box<[[u32; 3]]>)> {
+    let origin = collider_transform.translation();
+    let normal = half_space.normal;
+    let normal = (collider_transform.rotation() * Vec3::new(normal.x, normal.y, normal.z))
+        .normalize_or_zero();
+    if normal == Vec3::ZERO {
+        return None;
+    }
+
+    let (min, max) = (Vec3::from(tile_aabb.min), Vec3::from(tile_aabb.max));
+    let corners = [
+        Vec3::new(min.x, min.y, min.z),
+        Vec3::new(max.x, min.y, min.z),
+        Vec3::new(min.x, max.y, min.z),
+        Vec3::new(max.x, max.y, min.z),
+        Vec3::new(min.x, min.y, max.z),
+        Vec3::new(max.x, min.y, max.z),
+        Vec3::new(min.x, max.y, max.z),
+        Vec3::new(max.x, max.y, max.z),
+    ];
+
+    // plane has to pass between the corners
+    let above = corners
+        .iter()
+        .filter(|c| (**c - origin).dot(normal) > 0.0)
+        .count();
+    if above == 0 || above == corners.len() {
+        return None;
+    }
+
+    // bounds of the corners projected onto the plane, padded a little so edges are covered
+    let (u, v) = normal.any_orthonormal_pair();
+    let mut plane_min = Vec2::MAX;
+    let mut plane_max = Vec2::MIN;
+    for corner in corners {
+        let local = Vec2::new((corner - origin).dot(u), (corner - origin).dot(v));
+        plane_min = plane_min.min(local);
+        plane_max = plane_max.max(local);
+    }
+    let padding = Vec2::splat(1.0);
+    plane_min -= padding;
+    plane_max += padding;
+
+    let vertex = |p: Vec2| {
+        let world = origin + u * p.x + v * p.y;
+        Point3::new(world.x, world.y, world.z)
+    };
+    let vertices = Box::new([
+        vertex(plane_min),
+        vertex(Vec2::new(plane_max.x, plane_min.y)),
+        vertex(plane_max),
+        vertex(Vec2::new(plane_min.x, plane_max.y)),
+    ]);
+
+    // wind so the surface faces along the plane normal
+    let [a, b, c] = [vertices[0], vertices[1], vertices[2]].map(Vec3::from);
+    let triangles = match (b - a).cross(c - a).dot(normal) >= 0.0 {
+        true => Box::new([[0, 1, 2], [0, 2, 3]]),
+        false => Box::new([[0, 2, 1], [0, 3, 2]]),
+    };
+    Some((vertices, triangles))
+}
+
+/// Approximates a rounded shape, the convex hull of ``points`` pushed out by ``border_radius`` in 14 directions.
+fn rounded_hull(points: &[Point3<f32>], border_radius: f32) -> GeometryResult<'static> {
+    const D: f32 = 0.577_350_3; // 1 / sqrt(3)
+    const DIRECTIONS: [[f32; 3]; 14] = [
+        [1.0, 0.0, 0.0],
+        [-1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.0, -1.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [0.0, 0.0, -1.0],
+        [D, D, D],
+        [D, D, -D],
+        [D, -D, D],
+        [D, -D, -D],
+        [-D, D, D],
+        [-D, D, -D],
+        [-D, -D, D],
+        [-D, -D, -D],
+    ];
+
+    let dilated = points
+        .iter()
+        .flat_map(|p| {
+            DIRECTIONS
+                .iter()
+                .map(move |d| p + Vector3::from(*d) * border_radius)
+        })
+        .collect::<Vec<_>>();
+    let (vertices, triangles) = convex_hull(&dilated);
+
+    GeometryToConvert::ParryTriMesh(vertices.into_boxed_slice(), triangles.into_boxed_slice())
+        .into()
+}
+
+/// Converts a collider shape into geometry for the nav-mesh, ``entity`` is only used for warnings.
+pub fn get_geometry_type<'a>(
+    entity: Entity,
+    collider: TypedShape<'a>,
+    custom_shapes: &CustomNavShapes,
+) -> GeometryResult<'a> {
     match collider {
         TypedShape::Ball(ball) => GeometryToConvert::Collider(ColliderType::Ball(*ball)).into(),
         TypedShape::Cuboid(cuboid) => {
@@ -235,46 +388,61 @@ pub fn get_geometry_type(collider: TypedShape) -> GeometryResult {
             GeometryToConvert::Collider(ColliderType::Cylinder(*cylinder)).into()
         }
         TypedShape::Cone(cone) => GeometryToConvert::Collider(ColliderType::Cone(*cone)).into(),
-        TypedShape::RoundCuboid(round_cuboid) => {
-            GeometryToConvert::Collider(ColliderType::Cuboid(round_cuboid.inner_shape)).into()
-        }
-        TypedShape::RoundCylinder(round_cylinder) => {
-            GeometryToConvert::Collider(ColliderType::Cylinder(round_cylinder.inner_shape)).into()
-        }
-        TypedShape::RoundCone(round_cone) => {
-            GeometryToConvert::Collider(ColliderType::Cone(round_cone.inner_shape)).into()
-        }
-        TypedShape::RoundConvexPolyhedron(round_polyhedron) => {
-            let tri = round_polyhedron.inner_shape.to_trimesh();
-
-            GeometryToConvert::ParryTriMesh(tri.0.into_boxed_slice(), tri.1.into_boxed_slice())
-                .into()
-        }
+        TypedShape::RoundCuboid(round_cuboid) => rounded_hull(
+            &round_cuboid.inner_shape.to_trimesh().0,
+            round_cuboid.border_radius,
+        ),
+        TypedShape::RoundCylinder(round_cylinder) => rounded_hull(
+            &round_cylinder.inner_shape.to_trimesh(SUBDIVISIONS).0,
+            round_cylinder.border_radius,
+        ),
+        TypedShape::RoundCone(round_cone) => rounded_hull(
+            &round_cone.inner_shape.to_trimesh(SUBDIVISIONS).0,
+            round_cone.border_radius,
+        ),
+        TypedShape::RoundConvexPolyhedron(round_polyhedron) => rounded_hull(
+            round_polyhedron.inner_shape.points(),
+            round_polyhedron.border_radius,
+        ),
         TypedShape::Triangle(triangle) => {
             GeometryToConvert::Collider(ColliderType::Triangle(*triangle)).into()
         }
         TypedShape::RoundTriangle(triangle) => {
-            GeometryToConvert::Collider(ColliderType::Triangle(triangle.inner_shape)).into()
+            rounded_hull(triangle.inner_shape.vertices(), triangle.border_radius)
         }
         TypedShape::Compound(colliders) => {
             let results = colliders
                 .shapes()
                 .iter()
-                .map(|(isometry, shape)| (*isometry, get_geometry_type(shape.0.as_typed_shape())))
+                .map(|(isometry, shape)| {
+                    (
+                        *isometry,
+                        get_geometry_type(entity, shape.0.as_typed_shape(), custom_shapes),
+                    )
+                })
                 .collect();
 
             GeometryResult::Compound(results)
         }
-        // These ones do not make sense in this.
-        TypedShape::HalfSpace(_) => GeometryResult::Unsupported, /* This is like an infinite plane? We don't care. */
-        TypedShape::Polyline(_) => GeometryResult::Unsupported,  /* This is a line. */
-        TypedShape::Segment(_) => GeometryResult::Unsupported,   /* This is a line segment. */
-        TypedShape::Custom(_) => {
-            warn!(
-                "Custom shapes are not yet supported for nav-mesh generation, skipping for now.."
-            );
+        TypedShape::HalfSpace(half_space) => GeometryResult::HalfSpace(*half_space),
+        // Lines have no area to walk on or volume to block, there is nothing to voxelize.
+        TypedShape::Polyline(_) | TypedShape::Segment(_) => {
+            warn!("Line collider on {entity} has no area, skipping for nav-mesh generation");
             GeometryResult::Unsupported
         }
+        TypedShape::Custom(shape) => match custom_shapes.triangles(shape) {
+            Some((vertices, triangles)) => GeometryToConvert::ParryTriMesh(
+                vertices.into_boxed_slice(),
+                triangles.into_boxed_slice(),
+            )
+            .into(),
+            None => {
+                warn!(
+                    "Custom shape on {entity} has no registered CustomNavShape, skipping for nav-mesh generation"
+                );
+                GeometryResult::Unsupported
+            }
+        },
     }
 }
 
@@ -300,3 +468,86 @@ pub(crate) fn convert_geometry(
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use avian3d::parry::na::Unit;
+
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn tile_aabb() -> Aabb3d {
+        Aabb3d::new(Vec3::ZERO, vec3(5.0, 2.0, 5.0))
+    }
+
+    /// Vertices of the quad and the normal of each of its triangles.
+    fn quad(normal: Vec3, transform: Transform) -> Option<(Vec<Vec3>, Vec<Vec3>)> {
+        let half_space = shape::HalfSpace::new(Unit::new_normalize(Vector3::new(
+            normal.x, normal.y, normal.z,
+        )));
+        let (vertices, triangles) =
+            clip_half_space(&half_space, &GlobalTransform::from(transform), &tile_aabb())?;
+        let vertices = vertices.iter().map(|p| Vec3::from(*p)).collect::<Vec<_>>();
+        let normals = triangles
+            .iter()
+            .map(|[a, b, c]| {
+                let [a, b, c] = [a, b, c].map(|i| vertices[*i as usize]);
+                (b - a).cross(c - a).normalize()
+            })
+            .collect();
+        Some((vertices, normals))
+    }
+
+    #[test]
+    fn test_clip_half_space_covers_tile() {
+        // ground plane through the tile
+        let (vertices, normals) = quad(Vec3::Y, Transform::default()).unwrap();
+        assert_eq!(vertices.len(), 4);
+        assert!(vertices.iter().all(|v| v.y.abs() < EPSILON));
+        let min = vertices.iter().fold(Vec3::MAX, |a, b| a.min(*b));
+        let max = vertices.iter().fold(Vec3::MIN, |a, b| a.max(*b));
+        assert!(min.x <= -5.0 && min.z <= -5.0 && max.x >= 5.0 && max.z >= 5.0);
+        assert!(normals.iter().all(|n| n.distance(Vec3::Y) < EPSILON));
+
+        // the collider's rotation and translation move the plane, still facing along its normal
+        let transform = Transform::from_xyz(0.0, 1.0, 0.0)
+            .with_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_2));
+        let (vertices, normals) = quad(Vec3::X, transform).unwrap();
+        assert!(vertices.iter().all(|v| (v.y - 1.0).abs() < EPSILON));
+        assert!(normals.iter().all(|n| n.distance(Vec3::Y) < EPSILON));
+
+        // above, below and beside the tile
+        assert!(quad(Vec3::Y, Transform::from_xyz(0.0, 3.0, 0.0)).is_none());
+        assert!(quad(Vec3::Y, Transform::from_xyz(0.0, -3.0, 0.0)).is_none());
+        assert!(quad(Vec3::X, Transform::from_xyz(6.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn test_rounded_hull_grows_by_radius() {
+        let (points, _) = shape::Cuboid::new(Vector3::new(1.0, 2.0, 3.0)).to_trimesh();
+        let GeometryResult::GeometryToConvert(GeometryToConvert::ParryTriMesh(vertices, triangles)) =
+            rounded_hull(&points, 0.5)
+        else {
+            panic!("expected a trimesh");
+        };
+        assert!(!triangles.is_empty());
+
+        // bounds grow by the radius on every side
+        let vertices = vertices.iter().map(|p| Vec3::from(*p)).collect::<Vec<_>>();
+        let min = vertices.iter().fold(Vec3::MAX, |a, b| a.min(*b));
+        let max = vertices.iter().fold(Vec3::MIN, |a, b| a.max(*b));
+        assert!(min.distance(vec3(-1.5, -2.5, -3.5)) < EPSILON, "{min}");
+        assert!(max.distance(vec3(1.5, 2.5, 3.5)) < EPSILON, "{max}");
+
+        // every vertex is on the rounded surface, ``radius`` from the inner cuboid
+        let half_size = vec3(1.0, 2.0, 3.0);
+        for v in vertices {
+            let outside = (v.abs() - half_size).max(Vec3::ZERO).length();
+            assert!(
+                (outside - 0.5).abs() < EPSILON,
+                "{v} is {outside} from the cuboid"
+            );
+        }
+    }
+}
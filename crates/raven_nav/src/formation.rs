@@ -0,0 +1,563 @@
+use bevy::prelude::*;
+
+use crate::{agent::*, path::NavPath};
+
+/// Layout of a [Formation], slot offsets are on the XZ-plane relative to the formation's facing.
+#[derive(Debug, Clone, PartialEq, Reflect)]
+pub enum FormationShape {
+    /// Single row, side by side.
+    Line,
+    /// Rows and columns, as square as possible.
+    Box,
+    /// V shape with the first slot at the tip.
+    Wedge,
+    /// Custom slot offsets, ``x`` is to the right and ``y`` is backward.
+    /// Members beyond the given slots are placed in box rows behind them.
+    Custom(Vec<Vec2>),
+}
+
+impl FormationShape {
+    /// Returns ``count`` slot offsets, ``x`` is to the right and ``y`` is backward, centered on the formation.
+    pub fn slot_offsets(&self, count: usize, spacing: f32) -> Vec<Vec2> {
+        let mut offsets = match self {
+            FormationShape::Line => (0..count)
+                .map(|i| Vec2::new(i as f32 * spacing, 0.0))
+                .collect::<Vec<_>>(),
+            FormationShape::Box => box_offsets(count, spacing, 0.0),
+            FormationShape::Wedge => (0..count)
+                .map(|i| {
+                    // 0 is the tip, then alternate left and right going back
+                    let row = i.div_ceil(2) as f32;
+                    let side = if i % 2 == 1 { -1.0 } else { 1.0 };
+                    Vec2::new(side * row * spacing, row * spacing)
+                })
+                .collect(),
+            FormationShape::Custom(slots) => {
+                let mut offsets = slots.iter().copied().take(count).collect::<Vec<_>>();
+                if count > slots.len() {
+                    let back = slots.iter().map(|s| s.y).fold(0.0, f32::max) + spacing;
+                    offsets.extend(box_offsets(count - slots.len(), spacing, back));
+                }
+                offsets
+            }
+        };
+
+        // center the formation on its origin
+        if !offsets.is_empty() {
+            let center = offsets.iter().sum::<Vec2>() / offsets.len() as f32;
+            for offset in offsets.iter_mut() {
+                *offset -= center;
+            }
+        }
+        offsets
+    }
+}
+
+fn box_offsets(count: usize, spacing: f32, back: f32) -> Vec<Vec2> {
+    let columns = (count as f32).sqrt().ceil().max(1.0) as usize;
+    (0..count)
+        .map(|i| {
+            Vec2::new(
+                (i % columns) as f32 * spacing,
+                back + (i / columns) as f32 * spacing,
+            )
+        })
+        .collect()
+}
+
+/// Converts a slot offset to world space for a formation at ``center`` facing ``facing``.
+pub fn formation_slot_position(center: Vec3, facing: Vec3, offset: Vec2) -> Vec3 {
+    let forward = Vec3::new(facing.x, 0.0, facing.z).normalize_or(Vec3::NEG_Z);
+    let right = Vec3::new(-forward.z, 0.0, forward.x);
+    center + right * offset.x - forward * offset.y
+}
+
+/// Assigns each position a slot, minimizing the total distance traveled on the XZ-plane.
+/// Returns the slot index for each position.
+///
+/// Minimizing total distance also removes crossing paths, since uncrossing two paths is always shorter.
+/// Solved exactly with the Hungarian method, O(n^2 m) for n positions and m slots.
+///
+/// Panics if there are fewer slots than positions.
+pub fn assign_slots(positions: &[Vec3], slots: &[Vec3]) -> Vec<usize> {
+    let (n, m) = (positions.len(), slots.len());
+    assert!(n <= m, "not enough formation slots");
+    let cost = |i: usize, j: usize| positions[i].xz().distance(slots[j].xz());
+
+    // potentials and matching are 1-based, with column 0 as the free start
+    let mut u = vec![0.0f32; n + 1];
+    let mut v = vec![0.0f32; m + 1];
+    let mut matched = vec![0usize; m + 1];
+    let mut way = vec![0usize; m + 1];
+    for i in 1..=n {
+        matched[0] = i;
+        let mut j0 = 0;
+        let mut min_v = vec![f32::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+        loop {
+            used[j0] = true;
+            let i0 = matched[j0];
+            let mut delta = f32::INFINITY;
+            let mut j1 = 0;
+            for j in 1..=m {
+                if used[j] {
+                    continue;
+                }
+                let reduced = cost(i0 - 1, j - 1) - u[i0] - v[j];
+                if reduced < min_v[j] {
+                    min_v[j] = reduced;
+                    way[j] = j0;
+                }
+                if min_v[j] < delta {
+                    delta = min_v[j];
+                    j1 = j;
+                }
+            }
+            for j in 0..=m {
+                if used[j] {
+                    u[matched[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min_v[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if matched[j0] == 0 {
+                break;
+            }
+        }
+        // flip the augmenting path
+        while j0 != 0 {
+            let j1 = way[j0];
+            matched[j0] = matched[j1];
+            j0 = j1;
+        }
+    }
+
+    let mut assignment = vec![0; n];
+    for j in 1..=m {
+        if matched[j] != 0 {
+            assignment[matched[j] - 1] = j - 1;
+        }
+    }
+    assignment
+}
+
+/// A group of agents moving together, add [FormationMember] to agents to join the group
+/// and [FormationDestination] to the group to move it.
+///
+/// The group's [Transform] follows a shared leader path, each member is given a [FormationSlot] to follow.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+#[require(Transform, FormationMembers)]
+pub struct Formation {
+    pub shape: FormationShape,
+    /// Distance between slots.
+    pub spacing: f32,
+    /// Speed the leader moves along the path.
+    pub speed: f32,
+    /// The leader waits while any member is further than this from its slot.
+    pub max_slot_distance: f32,
+}
+
+impl Formation {
+    pub fn new(shape: FormationShape) -> Self {
+        Self { shape, ..default() }
+    }
+
+    /// Setter for [`Formation::spacing`]
+    pub fn with_spacing(mut self, spacing: f32) -> Self {
+        self.spacing = spacing;
+
+        self
+    }
+
+    /// Setter for [`Formation::speed`]
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+
+        self
+    }
+}
+
+impl Default for Formation {
+    fn default() -> Self {
+        Self {
+            shape: FormationShape::Box,
+            spacing: 1.5,
+            speed: 1.0,
+            max_slot_distance: 3.0,
+        }
+    }
+}
+
+/// Managed list of agents in the formation.
+#[derive(Component, Default, Debug, Reflect)]
+#[relationship_target(relationship = FormationMember)]
+pub struct FormationMembers(Vec<Entity>);
+
+/// Ref to the [Formation] an agent is part of
+///
+/// The agent is sent to its slot as its [AgentTarget], add [AgentMover] unless the game moves it.
+#[derive(Component, Debug, Reflect)]
+#[relationship(relationship_target = FormationMembers)]
+pub struct FormationMember(pub Entity);
+
+/// Where the [Formation] should move to, changing it will plan a new leader path.
+#[derive(Component, Debug, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct FormationDestination(pub Vec3);
+
+/// Leader path of a [Formation]
+#[derive(Component, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct FormationPath {
+    pub points: Vec<Vec3>,
+    /// Index of the point the leader is heading to.
+    pub next: usize,
+}
+
+/// Slot of a [FormationMember], ``position`` is kept on the nav-mesh and is mirrored to [AgentTarget::Point].
+#[derive(Component, Debug, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct FormationSlot {
+    pub index: usize,
+    pub position: Vec3,
+}
+
+impl NavPath<'_, '_> {
+    /// Returns world space slot positions for ``count`` members of a formation at ``center`` facing ``facing``.
+    ///
+    /// Each slot is clamped to the closest point on the nav-mesh, slots with no polygon within ``spacing`` stay at ``center``.
+    pub fn formation_slots(
+        &self,
+        nav_e: Entity,
+        center: Vec3,
+        facing: Vec3,
+        shape: &FormationShape,
+        count: usize,
+        spacing: f32,
+    ) -> Vec<Vec3> {
+        if !self.nav_query.contains(nav_e) {
+            return vec![center; count];
+        }
+        shape
+            .slot_offsets(count, spacing)
+            .into_iter()
+            .map(|offset| {
+                let slot = formation_slot_position(center, facing, offset);
+                self.find_closest_polygon_in_box(nav_e, slot, spacing)
+                    .map_or(center, |(_tile, _polygon, position)| position)
+            })
+            .collect()
+    }
+}
+
+/// Plans the leader path and assigns slots when a [FormationDestination] changes.
+#[allow(clippy::type_complexity)]
+pub(crate) fn plan_formations(
+    mut commands: Commands,
+    mut formation_query: Query<
+        (
+            Entity,
+            &Formation,
+            &FormationMembers,
+            &FormationDestination,
+            &mut Transform,
+        ),
+        Changed<FormationDestination>,
+    >,
+    member_query: Query<(&GlobalTransform, Option<&AgentNav>), With<FormationMember>>,
+    mut nav_path: NavPath,
+) {
+    for (e, formation, members, destination, mut transform) in formation_query.iter_mut() {
+        // members without a transform yet are left out, so entities and positions stay paired
+        let (members, positions): (Vec<_>, Vec<_>) = members
+            .iter()
+            .filter_map(|member| {
+                member_query
+                    .get(member)
+                    .ok()
+                    .map(|(trans, _)| (member, trans.translation()))
+            })
+            .unzip();
+        let Some(nav_e) = member_query
+            .iter_many(members.iter().copied())
+            .find_map(|(_, nav)| nav.map(|nav| nav.0))
+        else {
+            warn!("Formation {e} has no members on a nav");
+            continue;
+        };
+
+        // leader starts from the center of the group
+        let start = match positions.is_empty() {
+            true => transform.translation,
+            false => positions.iter().sum::<Vec3>() / positions.len() as f32,
+        };
+
+        let points = match nav_path.find_path(nav_e, start, destination.0, None, None) {
+            Ok(result) => result.points,
+            Err(err) => {
+                warn!("Formation {e} couldn't find path: {err}");
+                continue;
+            }
+        };
+
+        let facing = points
+            .get(1)
+            .map_or(transform.forward().as_vec3(), |next| *next - start);
+        transform.translation = start;
+        transform.look_to(
+            Vec3::new(facing.x, 0.0, facing.z).normalize_or(Vec3::NEG_Z),
+            Vec3::Y,
+        );
+
+        // assign slots relative to where everyone is now, so nobody crosses the group
+        let slots = nav_path.formation_slots(
+            nav_e,
+            start,
+            facing,
+            &formation.shape,
+            positions.len(),
+            formation.spacing,
+        );
+        let assignment = assign_slots(&positions, &slots);
+        for (member, slot) in members.iter().zip(assignment) {
+            commands.entity(*member).insert(FormationSlot {
+                index: slot,
+                position: slots[slot],
+            });
+        }
+
+        commands.entity(e).insert(FormationPath { points, next: 1 });
+    }
+}
+
+/// Moves the formation leader along its path and updates member slots.
+#[allow(clippy::type_complexity)]
+pub(crate) fn move_formations(
+    mut commands: Commands,
+    mut formation_query: Query<(
+        Entity,
+        &Formation,
+        &FormationMembers,
+        &mut FormationPath,
+        &mut Transform,
+    )>,
+    mut member_query: Query<
+        (
+            &GlobalTransform,
+            &mut FormationSlot,
+            &mut AgentTarget,
+            &AgentState,
+            Option<&AgentNav>,
+        ),
+        With<FormationMember>,
+    >,
+    nav_path: NavPath,
+    time: Res<Time>,
+) {
+    for (e, formation, members, mut path, mut transform) in formation_query.iter_mut() {
+        let count = members.len();
+        let mut nav_e = None;
+
+        // wait for stragglers, but not for members that can't reach their slot
+        let mut waiting = false;
+        for member in members.iter() {
+            if let Ok((trans, slot, _target, state, nav)) = member_query.get(member) {
+                nav_e = nav_e.or(nav.map(|nav| nav.0));
+                if trans.translation().xz().distance(slot.position.xz())
+                    > formation.max_slot_distance
+                    && matches!(state, AgentState::Moving | AgentState::ReachedTarget)
+                {
+                    waiting = true;
+                }
+            }
+        }
+        let Some(nav_e) = nav_e else {
+            continue;
+        };
+
+        if !waiting {
+            let mut step = formation.speed * time.delta_secs();
+            while step > 0.0 {
+                let Some(next) = path.points.get(path.next).copied() else {
+                    break;
+                };
+                let to_next = next - transform.translation;
+                let distance = to_next.length();
+                if distance <= step {
+                    transform.translation = next;
+                    path.next += 1;
+                    step -= distance;
+                } else {
+                    transform.translation += to_next / distance * step;
+                    step = 0.0;
+                }
+                if to_next.xz().length_squared() > 0.0 {
+                    transform.look_to(Vec3::new(to_next.x, 0.0, to_next.z), Vec3::Y);
+                }
+            }
+        }
+
+        let slots = nav_path.formation_slots(
+            nav_e,
+            transform.translation,
+            transform.forward().as_vec3(),
+            &formation.shape,
+            count,
+            formation.spacing,
+        );
+        for member in members.iter() {
+            if let Ok((_trans, mut slot, mut target, _state, _nav)) = member_query.get_mut(member) {
+                if let Some(position) = slots.get(slot.index) {
+                    slot.position = *position;
+                    *target = AgentTarget::Point(*position);
+                }
+            }
+        }
+
+        // path done, everyone has their final slot
+        if path.next >= path.points.len() {
+            commands.entity(e).remove::<FormationPath>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total_distance(positions: &[Vec3], slots: &[Vec3], assignment: &[usize]) -> f32 {
+        positions
+            .iter()
+            .zip(assignment)
+            .map(|(p, s)| p.xz().distance(slots[*s].xz()))
+            .sum()
+    }
+
+    /// Do the segments ``a0-a1`` and ``b0-b1`` properly cross
+    fn crosses(a0: Vec2, a1: Vec2, b0: Vec2, b1: Vec2) -> bool {
+        let side = |p: Vec2, q: Vec2, r: Vec2| (q - p).perp_dot(r - p);
+        side(a0, a1, b0) * side(a0, a1, b1) < 0.0 && side(b0, b1, a0) * side(b0, b1, a1) < 0.0
+    }
+
+    fn permutations(n: usize) -> Vec<Vec<usize>> {
+        if n == 0 {
+            return vec![Vec::new()];
+        }
+        let mut result = Vec::new();
+        for perm in permutations(n - 1) {
+            for i in 0..=perm.len() {
+                let mut perm = perm.clone();
+                perm.insert(i, n - 1);
+                result.push(perm);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_slot_offsets() {
+        let line = FormationShape::Line.slot_offsets(3, 2.0);
+        assert_eq!(line, vec![vec2(-2.0, 0.0), vec2(0.0, 0.0), vec2(2.0, 0.0)]);
+
+        let square = FormationShape::Box.slot_offsets(4, 1.0);
+        assert_eq!(
+            square,
+            vec![
+                vec2(-0.5, -0.5),
+                vec2(0.5, -0.5),
+                vec2(-0.5, 0.5),
+                vec2(0.5, 0.5)
+            ]
+        );
+
+        // tip in front, then pairs behind it on either side
+        let wedge = FormationShape::Wedge.slot_offsets(5, 1.0);
+        assert!(wedge[1..].iter().all(|slot| slot.y > wedge[0].y));
+        assert_eq!(wedge[1].x, -wedge[2].x);
+        assert_eq!(wedge[3].y, wedge[4].y);
+
+        // extra members go in rows behind the custom slots
+        let custom = FormationShape::Custom(vec![vec2(-1.0, 0.0), vec2(1.0, 0.0)]);
+        let slots = custom.slot_offsets(5, 1.0);
+        assert_eq!(slots.len(), 5);
+        assert!(slots[2..].iter().all(|slot| slot.y > slots[0].y));
+
+        for shape in [
+            FormationShape::Line,
+            FormationShape::Box,
+            FormationShape::Wedge,
+            custom,
+        ] {
+            assert!(shape.slot_offsets(0, 1.0).is_empty());
+            let center = shape.slot_offsets(7, 1.5).iter().sum::<Vec2>() / 7.0;
+            assert!(center.length() < 1e-5, "{shape:?} isn't centered");
+        }
+    }
+
+    #[test]
+    fn test_slot_position() {
+        // facing +X, right is +Z and backward is -X
+        let position = formation_slot_position(Vec3::ZERO, Vec3::X, vec2(1.0, 2.0));
+        assert!(position.distance(vec3(-2.0, 0.0, 1.0)) < 1e-6);
+    }
+
+    #[test]
+    fn test_assign_slots_swaps() {
+        // greedy gives the first position the closest slot, swapping is shorter overall
+        let positions = [vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0)];
+        let slots = [vec3(0.6, 0.0, 0.0), vec3(-1.0, 0.0, 0.0)];
+        assert_eq!(assign_slots(&positions, &slots), vec![1, 0]);
+
+        // extra slots are left free
+        let slots = [
+            vec3(5.0, 0.0, 0.0),
+            vec3(0.1, 0.0, 0.0),
+            vec3(1.1, 0.0, 0.0),
+        ];
+        assert_eq!(assign_slots(&positions, &slots), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_assign_slots_optimal() {
+        let mut seed = 7u32;
+        let mut random = || {
+            seed = seed.wrapping_mul(747796405).wrapping_add(2891336453);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 10.0
+        };
+        let count = 5;
+        let all = permutations(count);
+        for _ in 0..50 {
+            let positions = (0..count)
+                .map(|_| vec3(random(), 0.0, random()))
+                .collect::<Vec<_>>();
+            let slots = (0..count)
+                .map(|_| vec3(random(), 0.0, random()))
+                .collect::<Vec<_>>();
+            let assignment = assign_slots(&positions, &slots);
+
+            let mut sorted = assignment.clone();
+            sorted.sort();
+            assert_eq!(sorted, (0..count).collect::<Vec<_>>());
+
+            for i in 0..count {
+                for j in (i + 1)..count {
+                    assert!(!crosses(
+                        positions[i].xz(),
+                        slots[assignment[i]].xz(),
+                        positions[j].xz(),
+                        slots[assignment[j]].xz()
+                    ));
+                }
+            }
+
+            let best = all
+                .iter()
+                .map(|perm| total_distance(&positions, &slots, perm))
+                .fold(f32::MAX, f32::min);
+            assert!(total_distance(&positions, &slots, &assignment) <= best + 1e-4);
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    ecs::entity::EntityHashMap,
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+};
+
+use crate::{
+    nav::{Nav, TileLookup},
+    tile::{Link, Tile, TileWaymap, nav_mesh::TileNavMesh},
+};
+
+/// Island id of polygons that haven't been assigned one yet.
+pub const NO_ISLAND: u32 = u32::MAX;
+
+/// Connected component (island) id for each polygon of the tile's [TileNavMesh], indexed by polygon.
+///
+/// Polygons sharing an id can reach each other, see [crate::prelude::NavPath::is_reachable].
+#[derive(Component, Default, Debug, Clone, Deref, DerefMut, Reflect)]
+#[reflect(Component)]
+pub struct TileIslands(pub Vec<u32>);
+
+/// Island bookkeeping for a [Nav].
+#[derive(Component, Default, Debug, Reflect)]
+#[reflect(Component)]
+pub struct NavIslands {
+    next_id: u32,
+    /// Tiles holding polygons of each island, so a rebuild only revisits the tiles of the islands it touches.
+    tiles: HashMap<u32, HashSet<UVec2>>,
+}
+
+/// Sent when tile rebuilds change how the islands of a [Nav] are connected.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub enum NavIslandEvent {
+    /// Islands in ``from`` are now connected and all use the ``into`` id.
+    Merged {
+        nav: Entity,
+        from: Vec<u32>,
+        into: u32,
+    },
+    /// Island ``from`` was cut apart into the ``into`` islands.
+    Split {
+        nav: Entity,
+        from: u32,
+        into: Vec<u32>,
+    },
+}
+
+/// Reassigns islands around tiles whose [TileNavMesh] changed.
+///
+/// Only islands touching a changed tile are flood filled again, everything else keeps its id.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn update_islands(
+    mut nav_query: Query<(Entity, &TileLookup, &mut NavIslands), With<Nav>>,
+    changed_tiles: Query<(&Tile, &TileWaymap), Changed<TileNavMesh>>,
+    tile_info: Query<(&Tile, &TileWaymap)>,
+    mut removed_tiles: RemovedComponents<TileNavMesh>,
+    mut tile_query: Query<(Option<&TileNavMesh>, &mut TileIslands)>,
+    mut events: EventWriter<NavIslandEvent>,
+    mut dirty: Local<EntityHashMap<HashSet<UVec2>>>,
+) {
+    for (tile, waymap) in changed_tiles.iter() {
+        dirty.entry(waymap.0).or_default().insert(tile.0);
+    }
+    for e in removed_tiles.read() {
+        // tile may have been despawned with the nav rebuild
+        if let Ok((tile, waymap)) = tile_info.get(e) {
+            dirty.entry(waymap.0).or_default().insert(tile.0);
+        }
+    }
+
+    for (nav_e, dirty_tiles) in dirty.drain() {
+        let Ok((nav_e, lookup, mut nav_islands)) = nav_query.get_mut(nav_e) else {
+            continue;
+        };
+
+        // Step 1: reset dirty tiles, remembering which islands they were part of
+        let mut affected = HashSet::<u32>::new();
+        for tile_coord in dirty_tiles.iter() {
+            let Some(tile_e) = lookup.get(tile_coord) else {
+                continue;
+            };
+            let Ok((nav_mesh, mut islands)) = tile_query.get_mut(*tile_e) else {
+                continue;
+            };
+            affected.extend(islands.iter().copied().filter(|id| *id != NO_ISLAND));
+            islands.clear();
+            if let Some(nav_mesh) = nav_mesh {
+                islands.resize(nav_mesh.polygons.len(), NO_ISLAND);
+            }
+        }
+
+        // Step 2: collect seeds, the polygons of the dirty tiles and of the tiles the affected islands covered
+        let mut seed_tiles = dirty_tiles;
+        for id in affected.iter() {
+            if let Some(tiles) = nav_islands.tiles.remove(id) {
+                seed_tiles.extend(tiles);
+            }
+        }
+        // in a stable order so ids are handed out the same way every run
+        let mut seed_tiles = seed_tiles.into_iter().collect::<Vec<_>>();
+        seed_tiles.sort_unstable_by_key(|tile| (tile.y, tile.x));
+        let mut seeds = Vec::new();
+        for tile_coord in seed_tiles {
+            let Some(Ok((_, islands))) = lookup.get(&tile_coord).map(|e| tile_query.get(*e)) else {
+                continue;
+            };
+            for (polygon, id) in islands.iter().enumerate() {
+                if *id == NO_ISLAND || affected.contains(id) {
+                    seeds.push((tile_coord, polygon as u16));
+                }
+            }
+        }
+
+        // Step 3: flood fill from the seeds
+        let mut visited = HashSet::<(UVec2, u16)>::new();
+        let mut queue = VecDeque::new();
+        let mut claimed = HashSet::<u32>::new();
+        let mut split_into = HashMap::<u32, Vec<u32>>::new();
+        for seed in seeds {
+            if !visited.insert(seed) {
+                continue;
+            }
+            queue.push_back(seed);
+
+            let mut component = Vec::new();
+            let mut old_ids = Vec::new();
+            while let Some((tile_coord, polygon)) = queue.pop_front() {
+                component.push((tile_coord, polygon));
+                let Some(tile_e) = lookup.get(&tile_coord) else {
+                    continue;
+                };
+                let Ok((Some(nav_mesh), islands)) = tile_query.get(*tile_e) else {
+                    continue;
+                };
+
+                let Some(nav_polygon) = nav_mesh.polygons.get(polygon as usize) else {
+                    continue;
+                };
+
+                let old_id = islands.get(polygon as usize).copied().unwrap_or(NO_ISLAND);
+                if old_id != NO_ISLAND && !old_ids.contains(&old_id) {
+                    old_ids.push(old_id);
+                }
+
+                for link in nav_polygon.links.iter() {
+                    let neighbour = match link {
+                        Link::Internal {
+                            neighbour_polygon, ..
+                        } => (tile_coord, *neighbour_polygon),
+                        Link::External {
+                            neighbour_polygon,
+                            direction,
+                            ..
+                        } => match direction.offset(tile_coord) {
+                            Some(neighbour_tile) => (neighbour_tile, *neighbour_polygon),
+                            None => continue,
+                        },
+                    };
+                    if visited.insert(neighbour) {
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+
+            // Reuse the oldest id we can so unchanged islands keep their id
+            old_ids.sort_unstable();
+            let id = match old_ids.iter().find(|id| !claimed.contains(*id)) {
+                Some(id) => *id,
+                None => {
+                    let id = nav_islands.next_id;
+                    nav_islands.next_id += 1;
+                    id
+                }
+            };
+
+            // the component is the whole island now, replacing the islands it was made from,
+            // other than ones an earlier component split off from them kept
+            for old_id in old_ids.iter().filter(|old_id| !claimed.contains(*old_id)) {
+                nav_islands.tiles.remove(old_id);
+            }
+            claimed.insert(id);
+            nav_islands
+                .tiles
+                .insert(id, component.iter().map(|(tile, _)| *tile).collect());
+
+            for (tile_coord, polygon) in component {
+                let Some(tile_e) = lookup.get(&tile_coord) else {
+                    continue;
+                };
+                if let Ok((_, mut islands)) = tile_query.get_mut(*tile_e)
+                    && let Some(island) = islands.get_mut(polygon as usize)
+                {
+                    *island = id;
+                }
+            }
+
+            for old_id in old_ids.iter() {
+                split_into.entry(*old_id).or_default().push(id);
+            }
+            if old_ids.len() > 1 {
+                events.write(NavIslandEvent::Merged {
+                    nav: nav_e,
+                    from: old_ids,
+                    into: id,
+                });
+            }
+        }
+
+        for (from, into) in split_into {
+            if into.len() > 1 {
+                events.write(NavIslandEvent::Split {
+                    nav: nav_e,
+                    from,
+                    into,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use bevy::ecs::event::Events;
+
+    use super::*;
+    use crate::{
+        collider::Area,
+        tile::{
+            NavPolygon,
+            mesher::{EdgeConnection, EdgeConnectionDirection},
+        },
+    };
+
+    /// Tile nav-mesh of one polygon per entry in ``links``.
+    fn nav_mesh(links: &[&[Link]]) -> TileNavMesh {
+        TileNavMesh {
+            vertices: Vec::new(),
+            polygons: links
+                .iter()
+                .map(|links| NavPolygon {
+                    indices: [0; 3],
+                    links: links.iter().copied().collect(),
+                })
+                .collect(),
+            areas: Vec::new(),
+            edges: Vec::new(),
+        }
+    }
+
+    fn internal(neighbour_polygon: u16) -> Link {
+        Link::Internal {
+            edge: 0,
+            neighbour_polygon,
+        }
+    }
+
+    fn external(neighbour_polygon: u16, direction: EdgeConnectionDirection) -> Link {
+        Link::External {
+            edge: 1,
+            neighbour_polygon,
+            direction,
+            bound_min: 0,
+            bound_max: 100,
+        }
+    }
+
+    /// Tile at (1, 0), each polygon linked to the same polygon of the tile at (0, 0), ``bridged`` links its two polygons.
+    fn right_tile(bridged: bool) -> TileNavMesh {
+        let left = |polygon| external(polygon, EdgeConnectionDirection::XNegative);
+        match bridged {
+            true => nav_mesh(&[&[left(0), internal(1)], &[left(1), internal(0)]]),
+            false => nav_mesh(&[&[left(0)], &[left(1)]]),
+        }
+    }
+
+    fn islands(world: &World, tile_e: Entity) -> Vec<u32> {
+        world.get::<TileIslands>(tile_e).unwrap().0.clone()
+    }
+
+    fn island_tiles(world: &World, nav_e: Entity) -> Vec<(u32, HashSet<UVec2>)> {
+        let mut tiles = world
+            .get::<NavIslands>(nav_e)
+            .unwrap()
+            .tiles
+            .iter()
+            .map(|(id, tiles)| (*id, tiles.clone()))
+            .collect::<Vec<_>>();
+        tiles.sort_unstable_by_key(|(id, _)| *id);
+        tiles
+    }
+
+    fn run(world: &mut World, schedule: &mut Schedule) -> Vec<NavIslandEvent> {
+        schedule.run(world);
+        world
+            .resource_mut::<Events<NavIslandEvent>>()
+            .drain()
+            .collect()
+    }
+
+    /// Nav with two tiles side by side along X, centered on the origin, with islands assigned.
+    ///
+    /// Both tiles hold a 30x10 strip of two triangles, together spanning x -30..30 and z -5..5. Only the
+    /// z -5..0 half of the shared border at x = 0 is linked, the rest of it and the outer borders are walls.
+    /// The right tile also has a lone triangle at x 20..25, z 8..12 that nothing links to.
+    pub(crate) fn two_tile_nav(world: &mut World) -> Entity {
+        use EdgeConnection::{External, Internal, None};
+        use EdgeConnectionDirection::{XNegative, XPositive};
+
+        // wound like the mesher's output, counter-clockwise seen from above
+        let strip = vec![
+            vec3(-15.0, 0.0, -5.0),
+            vec3(-15.0, 0.0, 5.0),
+            vec3(15.0, 0.0, 5.0),
+            vec3(15.0, 0.0, -5.0),
+        ];
+        let polygon = |indices, links: &[Link]| NavPolygon {
+            indices,
+            links: links.iter().copied().collect(),
+        };
+        let link = |edge, neighbour_polygon, direction, bound_min, bound_max| Link::External {
+            edge,
+            neighbour_polygon,
+            direction,
+            bound_min,
+            bound_max,
+        };
+        let left = TileNavMesh {
+            vertices: strip.clone(),
+            polygons: vec![
+                polygon([0, 1, 2], &[internal_edge(2, 1)]),
+                polygon(
+                    [0, 2, 3],
+                    &[internal_edge(0, 0), link(1, 0, XPositive, 128, 255)],
+                ),
+            ],
+            areas: vec![Area(0); 2],
+            edges: vec![
+                [External(XNegative), None, Internal(1)],
+                [Internal(0), External(XPositive), None],
+            ],
+        };
+        let mut vertices = strip;
+        vertices.extend([
+            vec3(5.0, 0.0, 8.0),
+            vec3(10.0, 0.0, 12.0),
+            vec3(10.0, 0.0, 8.0),
+        ]);
+        let right = TileNavMesh {
+            vertices,
+            polygons: vec![
+                polygon(
+                    [0, 1, 2],
+                    &[internal_edge(2, 1), link(0, 1, XNegative, 0, 127)],
+                ),
+                polygon([0, 2, 3], &[internal_edge(0, 0)]),
+                polygon([4, 5, 6], &[]),
+            ],
+            areas: vec![Area(0); 3],
+            edges: vec![
+                [External(XNegative), None, Internal(1)],
+                [Internal(0), External(XPositive), None],
+                [None; 3],
+            ],
+        };
+
+        world.init_resource::<Events<NavIslandEvent>>();
+        let nav_e = world.spawn(Nav::new(0.5, 1.9, vec3(60.0, 10.0, 30.0))).id();
+        for (coord, x, nav_mesh) in [(UVec2::ZERO, -15.0, left), (UVec2::X, 15.0, right)] {
+            let tile_e = world
+                .spawn((
+                    Tile(coord),
+                    TileWaymap(nav_e),
+                    GlobalTransform::from_xyz(x, 0.0, 0.0),
+                    nav_mesh,
+                ))
+                .id();
+            world
+                .get_mut::<TileLookup>(nav_e)
+                .unwrap()
+                .insert(coord, tile_e);
+        }
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems(update_islands);
+        schedule.run(world);
+        nav_e
+    }
+
+    fn internal_edge(edge: u8, neighbour_polygon: u16) -> Link {
+        Link::Internal {
+            edge,
+            neighbour_polygon,
+        }
+    }
+
+    #[test]
+    fn test_update_islands_merge_and_split() {
+        let mut world = World::new();
+        world.init_resource::<Events<NavIslandEvent>>();
+        let mut schedule = Schedule::default();
+        schedule.add_systems(update_islands);
+
+        let nav_e = world.spawn(Nav::default()).id();
+        // two islands crossing both tiles, only the right tile is rebuilt
+        let right = |polygon| external(polygon, EdgeConnectionDirection::XPositive);
+        let left_e = world
+            .spawn((
+                Tile(UVec2::ZERO),
+                TileWaymap(nav_e),
+                nav_mesh(&[&[right(0)], &[right(1)]]),
+                TileIslands::default(),
+            ))
+            .id();
+        let right_e = world
+            .spawn((
+                Tile(UVec2::X),
+                TileWaymap(nav_e),
+                right_tile(false),
+                TileIslands::default(),
+            ))
+            .id();
+        let mut lookup = world.get_mut::<TileLookup>(nav_e).unwrap();
+        lookup.insert(UVec2::ZERO, left_e);
+        lookup.insert(UVec2::X, right_e);
+
+        assert!(run(&mut world, &mut schedule).is_empty());
+        let ids = islands(&world, left_e);
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(islands(&world, right_e), ids);
+
+        // bridging them joins both under the oldest id
+        world.entity_mut(right_e).insert(right_tile(true));
+        let merged = ids[0].min(ids[1]);
+        assert_eq!(
+            run(&mut world, &mut schedule),
+            [NavIslandEvent::Merged {
+                nav: nav_e,
+                from: vec![merged, ids[0].max(ids[1])],
+                into: merged,
+            }]
+        );
+        assert_eq!(islands(&world, left_e), [merged; 2]);
+        assert_eq!(islands(&world, right_e), [merged; 2]);
+        let both = HashSet::from_iter([UVec2::ZERO, UVec2::X]);
+        assert_eq!(island_tiles(&world, nav_e), [(merged, both.clone())]);
+
+        // removing the bridge again, one half keeps the id and the other gets a new one
+        world.entity_mut(right_e).insert(right_tile(false));
+        let events = run(&mut world, &mut schedule);
+        let ids = islands(&world, left_e);
+        assert!(ids.contains(&merged) && ids[0] != ids[1]);
+        assert!(!ids.contains(&NO_ISLAND));
+        assert_eq!(islands(&world, right_e), ids);
+        let [NavIslandEvent::Split { nav, from, into }] = &events[..] else {
+            panic!("{events:?}");
+        };
+        assert_eq!((*nav, *from), (nav_e, merged));
+        assert_eq!(into.len(), 2);
+        assert!(ids.iter().all(|id| into.contains(id)));
+        let mut expected = ids.iter().map(|id| (*id, both.clone())).collect::<Vec<_>>();
+        expected.sort_unstable_by_key(|(id, _)| *id);
+        assert_eq!(island_tiles(&world, nav_e), expected);
+    }
+}
@@ -6,8 +6,10 @@ extern crate test;
 mod agent;
 mod character;
 mod collider;
+mod formation;
 #[cfg(feature = "debug_draw")]
 pub mod debug_draw;
+mod island;
 mod math;
 mod nav;
 mod path;
@@ -18,6 +20,8 @@ use crate::{tile::mesher::EdgeConnectionDirection};
 use crate::agent::*;
 use crate::character::*;
 use crate::collider::*;
+use crate::formation::*;
+use crate::island::*;
 use crate::nav::*;
 
 use tile::{
@@ -35,7 +39,7 @@ use bevy::{
     
     ecs::entity::EntityHashMap,
     math::bounding::{Aabb3d },
-    platform::collections::HashSet,
+    platform::collections::{HashMap, HashSet},
     prelude::*,
     tasks::{AsyncComputeTaskPool, futures_lite::future},
 };
@@ -46,7 +50,7 @@ pub mod prelude {
     #[cfg(feature = "debug_draw")]
     pub use crate::debug_draw::*;
     pub use crate::{
-        NavPlugin, agent::*, character::*, collider::*, nav::*, path::*, tile::*,
+        NavPlugin, agent::*, character::*, collider::*, formation::*, island::*, nav::*, path::*, tile::*,
         utils::*,
     };
 }
@@ -64,6 +68,13 @@ impl Plugin for NavPlugin {
             (
                 nav_rebuild,
                 handle_removed_affectors, //.in_set(OxidizedNavigation::Main),
+                plan_formations,
+                move_formations,
+                update_character_velocities,
+                update_agent_paths,
+                steer_agents,
+                avoid_characters,
+                move_agents,
             )
                 .chain(),
         )
@@ -74,11 +85,15 @@ impl Plugin for NavPlugin {
                 (add_agents_to_nav, add_characters_to_waymap),
                 start_tile_build_tasks,
                 poll_tile_build_tasks,
+                update_islands,
+                update_character_costs,
                 //update_navigation,
             )
                 .chain()
                 .after(BvhSystems::Update),
         )
+        .add_event::<NavIslandEvent>()
+        .init_resource::<CustomNavShapes>()
         .register_type::<NavMeshAffector>()
         
         // Nav
@@ -87,6 +102,7 @@ impl Plugin for NavPlugin {
         .register_type::<NavAgents>()
         .register_type::<NavCharacters>()
         .register_type::<TileLookup>()
+        .register_type::<NavIslands>()
 
         // Tiles        
         .register_type::<Tile>()
@@ -94,14 +110,26 @@ impl Plugin for NavPlugin {
         .register_type::<TileAabb>()
         .register_type::<TileMeshAabb>()
         .register_type::<TileNavMesh>()
+        .register_type::<TileIslands>()
+        .register_type::<TileCharacterCosts>()
                 
         //agent and character        
         .register_type::<Agent>()
         .register_type::<AgentSettings>()
         .register_type::<AgentNav>()
+        .register_type::<AgentPath>()
+        .register_type::<AgentMover>()
         .register_type::<Character>()
         .register_type::<CharacterSettings>()
-        .register_type::<CharacterWaymap>();
+        .register_type::<CharacterWaymap>()
+
+        // formations
+        .register_type::<Formation>()
+        .register_type::<FormationMembers>()
+        .register_type::<FormationMember>()
+        .register_type::<FormationDestination>()
+        .register_type::<FormationPath>()
+        .register_type::<FormationSlot>();
     }
 }
 
@@ -160,6 +188,9 @@ fn add_characters_to_waymap(
     }
 }
 
+/// Largest extent of an affector's bounds we consider, anything past it is off every nav anyway.
+const MAX_AFFECTOR_EXTENT: f32 = 1.0e7;
+
 #[expect(clippy::type_complexity)]
 fn update_navmesh_affectors(
     mut commands: Commands,
@@ -204,16 +235,19 @@ fn update_navmesh_affectors(
                 ))
                 .transform_by(&iso);
 
+            // half-spaces are infinite, keep them finite so the tile math doesn't produce NaN
             let min_vec = Vec2::new(
                 aabb.mins.x - border_expansion,
                 aabb.mins.z - border_expansion,
-            );
+            )
+            .max(Vec2::splat(-MAX_AFFECTOR_EXTENT));
             let min_tile = nav.get_tile_containing_position(min_vec, nav_trans);
 
             let max_vec = Vec2::new(
                 aabb.maxs.x + border_expansion,
                 aabb.maxs.z + border_expansion,
-            );
+            )
+            .min(Vec2::splat(MAX_AFFECTOR_EXTENT));
             let max_tile = nav.get_tile_containing_position(max_vec, nav_trans);
 
             // TODO: looping though all tiles for every collider not ideal,
@@ -336,8 +370,9 @@ fn start_tile_build_tasks(
         &mut DirtyTiles,
         &mut NavGenerationTasks,
     )>,
-    mut tile_query: Query<(&TileAffectors, &GlobalTransform), With<Tile>>,
+    mut tile_query: Query<(&TileAffectors, &TileAabb, &GlobalTransform), With<Tile>>,
     collider_query: Query<(Entity, &Collider, &GlobalTransform, &NavMeshAffector)>,
+    custom_shapes: Res<CustomNavShapes>,
 ) {
     let thread_pool = AsyncComputeTaskPool::get();
 
@@ -352,13 +387,16 @@ fn start_tile_build_tasks(
         }
 
         let task_count = max.saturating_sub(active);
-        tiles_to_generate.extend(dirty_tiles.0.iter().take(task_count));
+        // build in a stable order so neighbour links connect the same way every run
+        tiles_to_generate.extend(dirty_tiles.0.iter());
+        tiles_to_generate.sort_unstable_by_key(|tile| (tile.y, tile.x));
+        tiles_to_generate.truncate(task_count);
 
         for tile_coord in tiles_to_generate.drain(..) {
             dirty_tiles.0.remove(&tile_coord);
 
             let tile_enity = tile_lookup.get(&tile_coord).unwrap();
-            let (affectors, tile_transform) = tile_query.get_mut(*tile_enity).unwrap();
+            let (affectors, tile_aabb, tile_transform) = tile_query.get_mut(*tile_enity).unwrap();
 
             // if tile has no affectors, remove it
             if affectors.is_empty() {                
@@ -367,14 +405,22 @@ fn start_tile_build_tasks(
             }
 
             // Step 1: Gather Collider Geometry for for tile affectors relative to the tile
+            // sorted so the geometry is always rasterized in the same order
+            let mut affectors = affectors.iter().copied().collect::<Vec<_>>();
+            affectors.sort_unstable();
+
             let mut geometry_collections = Vec::with_capacity(affectors.len());
             let mut heightfield_collections = Vec::new(); // Storing heightfields separately because they are massive.
 
             for (entity, collider, collider_transform, nav_mesh_affector) in
-                collider_query.iter_many(affectors.iter())
+                collider_query.iter_many(affectors.iter().copied())
             {
                 // Get the geometry type
-                let geometry_result = get_geometry_type(collider.shape_scaled().as_typed_shape());
+                let geometry_result = get_geometry_type(
+                    entity,
+                    collider.shape_scaled().as_typed_shape(),
+                    &custom_shapes,
+                );
 
                 // Convert the collider's transform to the tile's local space
                 let transform = GlobalTransform::from(
@@ -386,6 +432,7 @@ fn start_tile_build_tasks(
                     entity,
                     transform,
                     nav_mesh_affector.0,
+                    &tile_aabb.0,
                     &mut geometry_collections,
                     &mut heightfield_collections,
                     &mut heightfields,
@@ -399,6 +446,7 @@ fn start_tile_build_tasks(
             active_generation_tasks.0.push(NavMeshGenerationJob {
                 entity: *tile_enity,
                 task: thread_pool.spawn(build_tile(
+                    tile_coord,
                     nav.clone(),
                     geometry_collections,
                     heightfield_collections,
@@ -427,117 +475,149 @@ fn poll_tile_build_tasks(
     //#[cfg(feature = "debug_draw")] store: Res<GizmoConfigStore>,
 ) {
     for (e, nav, lookup, mut tasks, mut strat) in waymap_query.iter_mut() {
-        // check active tasks, canceling if not current
-        tasks.0.retain_mut(|job| {
-            if let Some(result) = future::block_on(future::poll_once(&mut job.task)) {
-                let tile_e = job.entity;
-                let (tile, tile_trans) = tile_query.get(tile_e).unwrap();
-                let previous_tile_existed = tile_edit_query.contains(tile_e);
-
-                match result {
-                    #[allow(unused_variables)]
-                    Some((mut nav_mesh, aabb, mesh, bvh)) => {
-                        // Update nav links to neighbours
-                        let step_height = nav.step_height as f32 * nav.cell_height;
-                        for direction in EdgeConnectionDirection::iter() {
-                            if let Some(neighbour_coord) = direction.offset(tile.0) {
-                                if let Some(neighbour_entity) = lookup.get(&neighbour_coord) {
-                                    if let Ok((mut neighbour, neighbour_trans)) =
-                                        tile_edit_query.get_mut(*neighbour_entity)
-                                    {
-                                        let opposite_direction = direction.flip();
-                                        tile::nav_mesh::connect_external_links(
-                                            &mut nav_mesh,
-                                            tile_trans,
-                                            &neighbour,
-                                            neighbour_trans,
-                                            direction,
-                                            opposite_direction,
-                                            false,
-                                            step_height,
-                                        );
-                                        tile::nav_mesh::connect_external_links(
-                                            &mut neighbour,
-                                            neighbour_trans,
-                                            &nav_mesh,
-                                            tile_trans,
-                                            opposite_direction,
-                                            direction,
-                                            previous_tile_existed,
-                                            step_height,
-                                        );
-                                    }
-                                }
-                            }
-                        }
+        // collect every finished build first, tiles finishing in the same frame are linked to each other too
+        let mut finished = Vec::new();
+        tasks.0.retain_mut(
+            |job| match future::block_on(future::poll_once(&mut job.task)) {
+                Some(result) => {
+                    finished.push((job.entity, result));
+                    false
+                }
+                None => true,
+            },
+        );
+        if finished.is_empty() {
+            continue;
+        }
+        // in tile order, so links come out the same however the builds were scheduled
+        finished.sort_unstable_by_key(|(tile_e, _)| {
+            tile_query
+                .get(*tile_e)
+                .map(|(tile, _)| (tile.y, tile.x))
+                .ok()
+        });
 
-                        // Update the tile
-                        commands.entity(job.entity).insert((
-                            nav_mesh,
-                            MeshBlas(blases.add(bvh)),
-                            TlasTarget(e),
-                            TileMeshAabb(aabb),
-                        ));
-
-                        // View mesh, like gizmos better for now
-                        // adding view mesh as child so we can use Tranform to offset
-                        // #[cfg(feature = "debug_draw")]
-                        //  {
-                        //     use crate::tile::TileViewMesh;
-                            
-                        //      let config = store.config::<NavGizmos>().1;
-                        //      commands.spawn((
-                        //         ChildOf(job.entity),
-                        //         TileViewMesh,
-                        //         Mesh3d(meshes.add(mesh)),
-                        //         MeshMaterial3d(materials.add(StandardMaterial {
-                        //             base_color: config.view_mesh_color.into(),
-                        //             unlit: true,
-                        //             alpha_mode: AlphaMode::Blend,
-                        //             ..default()
-                        //         })),
-                        //         NotShadowCaster,
-                        //         Pickable::IGNORE,
-                        //         Transform::from_translation(config.view_mesh_offset),
-                        //         match config.show_view_mesh {
-                        //             true => Visibility::Visible,
-                        //             false => Visibility::Hidden,
-                        //         },
-                        //     ));
-
-                        //     // TODO: set vertex color based on area cost
-                        // }
+        // nav meshes built this frame, only inserted once all of them are linked
+        let mut built = HashMap::<UVec2, (Entity, TileNavMesh)>::new();
+        let step_height = nav.step_height as f32 * nav.cell_height;
+        for (tile_e, result) in finished {
+            let (tile, tile_trans) = tile_query.get(tile_e).unwrap();
+            let previous_tile_existed = tile_edit_query.contains(tile_e);
+
+            match result {
+                #[allow(unused_variables)]
+                Some((mut nav_mesh, aabb, mesh, bvh)) => {
+                    // Update nav links to neighbours
+                    for direction in EdgeConnectionDirection::iter() {
+                        let Some(neighbour_coord) = direction.offset(tile.0) else {
+                            continue;
+                        };
+                        let Some(neighbour_entity) = lookup.get(&neighbour_coord) else {
+                            continue;
+                        };
+                        let Ok((_, neighbour_trans)) = tile_query.get(*neighbour_entity) else {
+                            continue;
+                        };
+                        // a neighbour built this frame isn't in the world yet
+                        let neighbour = match built.get_mut(&neighbour_coord) {
+                            Some((_, neighbour)) => neighbour,
+                            None => match tile_edit_query.get_mut(*neighbour_entity) {
+                                Ok((neighbour, _)) => neighbour.into_inner(),
+                                Err(_) => continue,
+                            },
+                        };
+                        let opposite_direction = direction.flip();
+                        tile::nav_mesh::connect_external_links(
+                            &mut nav_mesh,
+                            tile_trans,
+                            neighbour,
+                            neighbour_trans,
+                            direction,
+                            opposite_direction,
+                            false,
+                            step_height,
+                        );
+                        tile::nav_mesh::connect_external_links(
+                            neighbour,
+                            neighbour_trans,
+                            &nav_mesh,
+                            tile_trans,
+                            opposite_direction,
+                            direction,
+                            previous_tile_existed,
+                            step_height,
+                        );
                     }
-                    None => {
-                        // Remove any links to this tile
-                        // If the tile did not exist before, we do not need to remove links.
-                        if previous_tile_existed {
-                            for direction in EdgeConnectionDirection::iter() {
-                                if let Some(neighbour_coord) = direction.offset(tile.0) {
-                                    if let Some(neighbour_entity) = lookup.get(&neighbour_coord) {
-                                        if let Ok((mut neighbour, _)) =
-                                            tile_edit_query.get_mut(*neighbour_entity)
-                                        {
-                                            neighbour.remove_links_to_direction(direction.flip());
-                                        }
-                                    }
-                                }
+
+                    // Update the tile
+                    commands.entity(tile_e).insert((
+                        MeshBlas(blases.add(bvh)),
+                        TlasTarget(e),
+                        TileMeshAabb(aabb),
+                    ));
+                    built.insert(tile.0, (tile_e, nav_mesh));
+
+                    // View mesh, like gizmos better for now
+                    // adding view mesh as child so we can use Tranform to offset
+                    // #[cfg(feature = "debug_draw")]
+                    //  {
+                    //     use crate::tile::TileViewMesh;
+                        
+                    //      let config = store.config::<NavGizmos>().1;
+                    //      commands.spawn((
+                    //         ChildOf(job.entity),
+                    //         TileViewMesh,
+                    //         Mesh3d(meshes.add(mesh)),
+                    //         MeshMaterial3d(materials.add(StandardMaterial {
+                    //             base_color: config.view_mesh_color.into(),
+                    //             unlit: true,
+                    //             alpha_mode: AlphaMode::Blend,
+                    //             ..default()
+                    //         })),
+                    //         NotShadowCaster,
+                    //         Pickable::IGNORE,
+                    //         Transform::from_translation(config.view_mesh_offset),
+                    //         match config.show_view_mesh {
+                    //             true => Visibility::Visible,
+                    //             false => Visibility::Hidden,
+                    //         },
+                    //     ));
+
+                    //     // TODO: set vertex color based on area cost
+                    // }
+                }
+                None => {
+                    // Remove any links to this tile
+                    // If the tile did not exist before, we do not need to remove links.
+                    if previous_tile_existed {
+                        for direction in EdgeConnectionDirection::iter() {
+                            let Some(neighbour_coord) = direction.offset(tile.0) else {
+                                continue;
+                            };
+                            if let Some((_, neighbour)) = built.get_mut(&neighbour_coord) {
+                                neighbour.remove_links_to_direction(direction.flip());
+                            } else if let Some(neighbour_entity) = lookup.get(&neighbour_coord)
+                                && let Ok((mut neighbour, _)) =
+                                    tile_edit_query.get_mut(*neighbour_entity)
+                            {
+                                neighbour.remove_links_to_direction(direction.flip());
                             }
-                            commands
-                                .entity(tile_e)
-                                .remove::<TileNavMesh>()
-                                .remove::<TileMeshAabb>()
-                                .remove::<Children>(); // should delete view mesh
                         }
+                        commands
+                            .entity(tile_e)
+                            .remove::<TileNavMesh>()
+                            .remove::<TileMeshAabb>()
+                            .remove::<Children>(); // should delete view mesh
                     }
                 }
-                // trigger a rebuild of the tlas
-                *strat = TlasRebuildStrategy::Mannual(true);
-
-                return false;
             }
-            true
-        });
+        }
+
+        for (tile_e, nav_mesh) in built.into_values() {
+            commands.entity(tile_e).insert(nav_mesh);
+        }
+        // trigger a rebuild of the tlas
+        *strat = TlasRebuildStrategy::Mannual(true);
     }
 }
 
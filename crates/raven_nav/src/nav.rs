@@ -1,4 +1,4 @@
-use crate::{agent::*, character::*, tile::*};
+use crate::{agent::*, character::*, island::*, tile::*};
 use bevy::{
     math::bounding::Aabb3d,
     platform::collections::{HashMap, HashSet},
@@ -20,6 +20,7 @@ use std::num::{NonZeroU8, NonZeroU16};
     TileLookup, // lookup of tile entities by their coordinates
     DirtyTiles, // tracks tiles that need to be updated
     NavGenerationTasks, // list of tasks that are currently generating tiles
+    NavIslands, // island id bookkeeping
     AgentOptions, // used for agent avoidance
     Visibility, // used for rendering view mesh
     Tlas,
@@ -11,11 +11,16 @@ use crate::debug_draw::*;
 ///! Module for querying the nav-mesh.
 use crate::{
     Nav,
+    character::TileCharacterCosts,
+    island::{NO_ISLAND, TileIslands},
     nav::TileLookup,
-    tile::{Link, Tile, nav_mesh::TileNavMesh},
+    tile::{Link, Tile, mesher::EdgeConnection, nav_mesh::TileNavMesh},
 };
+use smallvec::SmallVec;
 
 const HEURISTIC_SCALE: f32 = 0.999;
+/// Radius used to find the polygon under a position when none is given.
+const DEFAULT_SEARCH_RADIUS: f32 = 50.0;
 
 #[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
 enum NodeState {
@@ -36,23 +41,85 @@ struct NavMeshNode {
     parent: Option<usize>,
 }
 
-/// Errors returned by [find_polygon_path]
-#[derive(Debug)]
+/// Errors returned by [NavPath::find_path]
+#[derive(Debug, Clone, PartialEq)]
 pub enum PathError {
     /// Nav couldn't be found.
-    NavNotFound,
+    NavNotFound(Entity),
     /// No polygon found near ``start_pos``.
-    NoValidStartPolygon,
+    NoValidStartPolygon { position: Vec3, search_radius: f32 },
     /// No polygon found near ``end_pos``.
-    NoValidEndPolygon,
-
-    PathEmpty,
-    MissingStartTile,
-    MissingStartTileLookup,
-    MissingEndTile,
-    MissingEndTileLookup,
-    MissingNodeTile,
-    NoLinkBetweenPathPoints,
+    NoValidEndPolygon { position: Vec3, search_radius: f32 },
+    /// The polygon search produced no polygons.
+    PathEmpty { start: Vec3, end: Vec3 },
+    /// A tile visited by the path has no entity in the [TileLookup] or no [TileNavMesh], usually the tile was rebuilt or removed mid query.
+    MissingNodeTile { tile: UVec2 },
+    /// Two consecutive polygons in the corridor are no longer linked, usually a neighbour tile was rebuilt.
+    NoLinkBetweenPathPoints {
+        tile: UVec2,
+        polygon: u16,
+        next_tile: UVec2,
+        next_polygon: u16,
+    },
+}
+
+impl std::fmt::Display for PathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathError::NavNotFound(e) => write!(f, "nav {e} not found"),
+            PathError::NoValidStartPolygon {
+                position,
+                search_radius,
+            } => write!(
+                f,
+                "no polygon found within {search_radius} of start position {position}"
+            ),
+            PathError::NoValidEndPolygon {
+                position,
+                search_radius,
+            } => write!(
+                f,
+                "no polygon found within {search_radius} of end position {position}"
+            ),
+            PathError::PathEmpty { start, end } => {
+                write!(f, "no polygons found between {start} and {end}")
+            }
+            PathError::MissingNodeTile { tile } => write!(f, "tile {tile} is missing a nav mesh"),
+            PathError::NoLinkBetweenPathPoints {
+                tile,
+                polygon,
+                next_tile,
+                next_polygon,
+            } => write!(
+                f,
+                "no link from polygon {polygon} in tile {tile} to polygon {next_polygon} in tile {next_tile}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// A polygon the path passes through.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CorridorPolygon {
+    /// Tile coordinate of the polygon.
+    pub tile: UVec2,
+    /// Index of the polygon in the tile's [TileNavMesh].
+    pub polygon: u16,
+    /// Left and right end of the edge shared with the next polygon in world space, ``None`` for the last polygon.
+    pub portal: Option<(Vec3, Vec3)>,
+}
+
+/// Result of [NavPath::find_path]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NavPathResult {
+    /// String pulled path in world space, starting at ``start_pos``.
+    pub points: Vec<Vec3>,
+    /// Polygons crossed by the path, in order.
+    pub corridor: Vec<CorridorPolygon>,
+    /// ``true`` when ``end_pos`` couldn't be reached and the path ends at the closest reachable polygon instead.
+    pub is_partial: bool,
 }
 
 // Based on Bevy's MeshRayCast
@@ -71,25 +138,22 @@ pub struct NavPath<'w, 's> {
         Query<'w, 's, (Entity, Read<Nav>, Read<TileLookup>, Read<GlobalTransform>), With<Nav>>,
     #[doc(hidden)]
     pub tile_query: Query<'w, 's, (Read<TileNavMesh>, Read<GlobalTransform>), With<Tile>>,
+    #[doc(hidden)]
+    pub island_query: Query<'w, 's, Read<TileIslands>, With<Tile>>,
+    #[doc(hidden)]
+    pub character_cost_query: Query<'w, 's, Read<TileCharacterCosts>, With<Tile>>,
     pub tlas_cast: TlasCast<'w, 's>,
 }
 
-/// Performs A* pathfinding on the supplied nav
-/// Returning the polygons crossed as a [Vec] containing the tile coordinate ([UVec2]) & polygon index ([u16]) or [FindPathError]
-///
-/// * ``nav_e`` - Nav to pathfind across.
-/// * ``start_pos`` - Starting position for the path.
-/// * ``end_pos`` - Destination position for the path, i.e where you want to go.
-/// * ``position_search_radius`` - Radius to search for a start & end polygon in. In world units. If **``None``** is supplied a default value of ``5.0`` is used.
-/// * ``area_cost_multipliers`` - Multipliers for area cost, use to prioritize or deprioritize taking certain paths. Values not present default to 1.0. Lesser value means the path costs less.
 impl<'w, 's> NavPath<'w, 's> {
     /// Performs A* pathfinding on the supplied nav-mesh.
-
     ///
-    /// * ``nav_e`` - Nav entity to pathfind across.    
+    /// When ``end_pos`` can't be reached, the path to the closest reachable polygon is returned with [NavPathResult::is_partial] set.
+    ///
+    /// * ``nav_e`` - Nav entity to pathfind across.
     /// * ``start_pos`` - Starting position for the path.
     /// * ``end_pos`` - Destination position for the path, i.e where you want to go.
-    /// * ``position_search_radius`` - Radius to search for a start & end polygon in. In world units. If **``None``** is supplied a default value of ``5.0`` is used.
+    /// * ``position_search_radius`` - Radius to search for a start & end polygon in. In world units. If **``None``** is supplied a default value of ``50.0`` is used.
     /// * ``area_cost_multipliers`` - Multipliers for area cost, use to prioritize or deprioritize taking certain paths. Values not present default to 1.0. Lesser value means the path costs less.
     pub fn find_path(
         &mut self,
@@ -98,432 +162,394 @@ impl<'w, 's> NavPath<'w, 's> {
         end_pos: Vec3,
         position_search_radius: Option<f32>,
         area_cost_multipliers: Option<&[f32]>, // TODO: A slice might not be the best choice when there are many area types.
-    ) -> Result<Vec<Vec3>, PathError> {
-        let search_radius = position_search_radius.unwrap_or(50.0);
+    ) -> Result<NavPathResult, PathError> {
+        let search_radius = position_search_radius.unwrap_or(DEFAULT_SEARCH_RADIUS);
 
-        let Ok((_e, _nav, lookup, _nav_trans)) = self.nav_query.get(nav_e) else {
-            return Err(PathError::NavNotFound);
-        };
+        if !self.nav_query.contains(nav_e) {
+            return Err(PathError::NavNotFound(nav_e));
+        }
 
         let Some((start_tile, start_poly, _start_pos)) =
             self.find_closest_polygon_in_box(nav_e, start_pos, search_radius)
         else {
-            return Err(PathError::NoValidStartPolygon);
+            return Err(PathError::NoValidStartPolygon {
+                position: start_pos,
+                search_radius,
+            });
         };
         #[cfg(feature = "debug_draw")]
-        
         self.gizmos
             .line(start_pos, start_pos + Vec3::Y, tailwind::GREEN_400);
 
         let Some((end_tile, end_poly, _end_pos)) =
             self.find_closest_polygon_in_box(nav_e, end_pos, search_radius)
         else {
-            return Err(PathError::NoValidEndPolygon);
+            return Err(PathError::NoValidEndPolygon {
+                position: end_pos,
+                search_radius,
+            });
         };
 
         #[cfg(feature = "debug_draw")]
         self.gizmos
             .line(end_pos, end_pos + Vec3::Y, tailwind::GREEN_400);
 
-        let path: Vec<(UVec2, u16)> = if start_tile == end_tile && start_poly == end_poly {
-            // start and end are in the same tile, so we can just return the start tile and polygon
-            vec![(start_tile, start_poly)]
-        } else {
-            // find path with A*, note that distance calculations are in world space
-
-            // the search graph.
-            let mut nodes = Vec::with_capacity(10);
+        let (path, is_partial) = self.find_polygon_path(
+            nav_e,
+            (start_tile, start_poly, start_pos),
+            (end_tile, end_poly, end_pos),
+            area_cost_multipliers,
+        )?;
 
-            // node indices to explore, sorted by increasing total cost.
-            let mut open_list = Vec::with_capacity(5);
-
-            // Initialize the first node.
-            nodes.push(NavMeshNode {
-                position: start_pos,
-                cost: 0.0,
-                total_cost: start_pos.distance(end_pos) * HEURISTIC_SCALE,
-                tile: start_tile,
-                polygon: start_poly,
-                state: NodeState::Open,
-                parent: None,
+        if path.is_empty() {
+            return Err(PathError::PathEmpty {
+                start: start_pos,
+                end: end_pos,
             });
-            open_list.push(0);
-
-            let mut last_best_node = 0;
-            let mut last_best_node_cost = nodes[0].total_cost;
-
-            // The A* search loop.
-            while let Some(best_node_index) = open_list.pop() {
-                // Take the next best node (lowest total cost due to ordering)
-                let (best_tile, best_polygon, best_position, best_cost, best_parent) = {
-                    let node = &mut nodes[best_node_index];
-                    node.state = NodeState::Closed;
-
-                    // test if we reached the goal
-                    if node.tile == end_tile && node.polygon == end_poly {
-                        last_best_node = best_node_index;
-                        break; // goal reached!
-                    }
-                    #[cfg(feature = "debug_draw")]
-                    self.gizmos.sphere(node.position, 0.1, tailwind::GRAY_700);
-                    // Unpack key data for neighbor expansion
-                    (
-                        node.tile,
-                        node.polygon,
-                        node.position,
-                        node.cost,
-                        node.parent,
-                    )
-                };
-                let tile_e = lookup.get(&best_tile).unwrap();
-                let (tile, tile_trans) = self.tile_query.get(*tile_e).unwrap();
-
-                // Find the best polygon in the tile
-                for link in tile.polygons[best_polygon as usize].links.iter() {
-                    let (link_tile, link_polygon) = match link {
-                        Link::Internal {
-                            neighbour_polygon, ..
-                        } => (best_tile, *neighbour_polygon),
-                        Link::External {
-                            neighbour_polygon,
-                            direction,
-                            ..
-                        } => {
-                            // check if the direction is valid
-                            if let Some(offset) = direction.offset(best_tile) {
-                                (offset, *neighbour_polygon)
-                            } else {
-                                continue; // skip invalid directions
-                            }
-                        }
-                    };
+        }
 
-                    // Prevent going backward in the path (cycles)
-                    if let Some(parent) = best_parent {
-                        if nodes[parent].tile == link_tile && nodes[parent].polygon == link_polygon
-                        {
-                            continue;
-                        }
-                    }
+        let corridor = self.build_corridor(nav_e, &path)?;
+
+        // when partial, head for the closest point on the last polygon we could reach
+        let end_pos = if is_partial {
+            let (tile, polygon) = *path.last().unwrap();
+            let (tile_mesh, tile_trans) = self.get_tile(nav_e, tile)?;
+            tile_mesh.get_closest_point_in_polygon(
+                &tile_mesh.polygons[polygon as usize],
+                end_pos,
+                tile_trans,
+            )
+        } else {
+            end_pos
+        };
 
-                    // Node creation or lookup
-                    let neighbour_node_index = if let Some(index) =
-                        nodes.iter().position(|element| {
-                            element.tile == link_tile && element.polygon == link_polygon
-                        }) {
-                        index
-                    } else {
-                        // Node hasn't been visited already, let's create it.
-                        let position = match link {
-                            Link::Internal { edge, .. } => {
-                                // Just the midpoint of the current edge.
-                                let indices = &tile.polygons[best_polygon as usize].indices;
-                                let a = tile.vertices[indices[*edge as usize] as usize];
-                                let b = tile.vertices
-                                    [indices[(*edge + 1) as usize % indices.len()] as usize];
-
-                                let local_mid = a.lerp(b, 0.5);
-                                tile_trans.transform_point(local_mid)
-                            }
-                            Link::External {
-                                edge,
-                                bound_min,
-                                bound_max,
-                                ..
-                            } => {
-                                // The mid point of the current-edge sliced by bound_min & bound_max.
-                                let indices = &tile.polygons[best_polygon as usize].indices;
-                                let a = tile.vertices[indices[*edge as usize] as usize];
-                                let b = tile.vertices
-                                    [indices[(*edge + 1) as usize % indices.len()] as usize];
-
-                                let a = tile_trans.transform_point(a);
-                                let b = tile_trans.transform_point(b);
-
-                                const S: f32 = 1.0 / 255.0;
-                                let bound_min = *bound_min as f32 * S;
-                                let bound_max = *bound_max as f32 * S;
-                                let clamped_a = a.lerp(b, bound_min);
-                                let clamped_b = a.lerp(b, bound_max);
-
-                                clamped_a.lerp(clamped_b, 0.5)
-                            }
-                        };
-
-                        nodes.push(NavMeshNode {
-                            position,
-                            cost: 0.0,
-                            total_cost: 0.0,
-                            tile: link_tile,
-                            polygon: link_polygon,
-                            state: NodeState::Unchecked,
-                            parent: None,
-                        });
-                        nodes.len() - 1
-                    };
+        // preform string pulling to get the actual path in world space
+        let points = string_pull(start_pos, end_pos, &corridor);
 
-                    // cost and heuristic evaluation
-                    let (old_state, total_cost) = {
-                        let neighbour_node = &mut nodes[neighbour_node_index];
+        #[cfg(feature = "debug_draw")]
+        {
+            for (left, right) in corridor.iter().filter_map(|c| c.portal) {
+                self.gizmos.line(left, right, tailwind::LIME_400);
+            }
+            self.gizmos.linestrip(points.clone(), tailwind::EMERALD_800);
+        }
 
-                        // Optional area cost multipliers
-                        // TODO: Ideally you want to be able to override this but for now we just go with the distance.
-                        let node_cost_multiplier =
-                            area_cost_multipliers.map_or(1.0, |multipliers| {
-                                let node_tile_e = lookup.get(&best_tile).unwrap();
-                                let (node_tile, _trans) =
-                                    self.tile_query.get(*node_tile_e).unwrap();
-                                let area = node_tile.areas[best_polygon as usize];
+        Ok(NavPathResult {
+            points,
+            corridor,
+            is_partial,
+        })
+    }
 
-                                *multipliers.get(area.0 as usize).unwrap_or(&1.0)
-                            });
+    /// Runs A* over the polygon graph, returning the polygons crossed and if the path is partial.
+    fn find_polygon_path(
+        &mut self,
+        nav_e: Entity,
+        (start_tile, start_poly, start_pos): (UVec2, u16, Vec3),
+        (end_tile, end_poly, end_pos): (UVec2, u16, Vec3),
+        area_cost_multipliers: Option<&[f32]>,
+    ) -> Result<(Vec<(UVec2, u16)>, bool), PathError> {
+        if start_tile == end_tile && start_poly == end_poly {
+            // start and end are in the same polygon, so we can just return it
+            return Ok((vec![(start_tile, start_poly)], false));
+        }
 
-                        let (cost, heuristic) = if end_tile == link_tile && end_poly == link_polygon
-                        {
-                            // Special case for the final node.
-                            let current_cost = best_position.distance(neighbour_node.position)
-                                * node_cost_multiplier;
-                            let end_cost = neighbour_node.position.distance(end_pos);
-                            let cost = best_cost + current_cost + end_cost;
-                            (cost, 0.0)
+        // find path with A*, note that distance calculations are in world space
+
+        // the search graph.
+        let mut nodes = Vec::with_capacity(10);
+
+        // node indices to explore, sorted by increasing total cost.
+        let mut open_list = Vec::with_capacity(5);
+
+        // Initialize the first node.
+        nodes.push(NavMeshNode {
+            position: start_pos,
+            cost: 0.0,
+            total_cost: start_pos.distance(end_pos) * HEURISTIC_SCALE,
+            tile: start_tile,
+            polygon: start_poly,
+            state: NodeState::Open,
+            parent: None,
+        });
+        open_list.push(0);
+
+        let mut last_best_node = 0;
+        let mut last_best_node_cost = nodes[0].total_cost;
+        let mut reached_goal = false;
+
+        // The A* search loop.
+        while let Some(best_node_index) = open_list.pop() {
+            // Take the next best node (lowest total cost due to ordering)
+            let (best_tile, best_polygon, best_position, best_cost, best_parent) = {
+                let node = &mut nodes[best_node_index];
+                node.state = NodeState::Closed;
+
+                // test if we reached the goal
+                if node.tile == end_tile && node.polygon == end_poly {
+                    last_best_node = best_node_index;
+                    reached_goal = true;
+                    break; // goal reached!
+                }
+                #[cfg(feature = "debug_draw")]
+                self.gizmos.sphere(node.position, 0.1, tailwind::GRAY_700);
+                // Unpack key data for neighbor expansion
+                (
+                    node.tile,
+                    node.polygon,
+                    node.position,
+                    node.cost,
+                    node.parent,
+                )
+            };
+            let (tile, tile_trans) = self.get_tile(nav_e, best_tile)?;
+
+            // Optional area cost multipliers
+            // TODO: Ideally you want to be able to override this but for now we just go with the distance.
+            let node_cost_multiplier = area_cost_multipliers.map_or(1.0, |multipliers| {
+                let area = tile.areas[best_polygon as usize];
+                *multipliers.get(area.0 as usize).unwrap_or(&1.0)
+            }) * (1.0 + self.character_cost(nav_e, best_tile, best_polygon));
+
+            // Find the best polygon in the tile
+            for link in tile.polygons[best_polygon as usize].links.iter() {
+                let (link_tile, link_polygon) = match link {
+                    Link::Internal {
+                        neighbour_polygon, ..
+                    } => (best_tile, *neighbour_polygon),
+                    Link::External {
+                        neighbour_polygon,
+                        direction,
+                        ..
+                    } => {
+                        // check if the direction is valid
+                        if let Some(offset) = direction.offset(best_tile) {
+                            (offset, *neighbour_polygon)
                         } else {
-                            let current_cost = best_position.distance(neighbour_node.position)
-                                * node_cost_multiplier;
-                            let cost = best_cost + current_cost;
-                            let heuristic =
-                                neighbour_node.position.distance(end_pos) * HEURISTIC_SCALE;
-                            (cost, heuristic)
-                        };
-                        let total_cost = cost + heuristic;
-
-                        // Don't update if already visited with better cost
-                        if neighbour_node.state != NodeState::Unchecked
-                            && total_cost >= neighbour_node.total_cost
-                        {
-                            continue;
+                            continue; // skip invalid directions
                         }
+                    }
+                };
 
-                        // Update node data
-                        let old_state = neighbour_node.state;
-                        neighbour_node.parent = Some(best_node_index);
-                        neighbour_node.state = NodeState::Open;
-                        neighbour_node.cost = cost;
-                        neighbour_node.total_cost = total_cost;
-
-                        // Remember best node if heuristic is better
-                        if heuristic < last_best_node_cost {
-                            last_best_node_cost = heuristic;
-                            last_best_node = neighbour_node_index;
-                        }
+                // Prevent going backward in the path (cycles)
+                if let Some(parent) = best_parent {
+                    if nodes[parent].tile == link_tile && nodes[parent].polygon == link_polygon {
+                        continue;
+                    }
+                }
 
-                        (old_state, total_cost)
+                // Node creation or lookup
+                let neighbour_node_index = if let Some(index) = nodes
+                    .iter()
+                    .position(|element| element.tile == link_tile && element.polygon == link_polygon)
+                {
+                    index
+                } else {
+                    // Node hasn't been visited already, let's create it.
+                    // The mid point of the portal between the two polygons.
+                    let (a, b) = portal_points(tile, tile_trans, best_polygon, link);
+                    nodes.push(NavMeshNode {
+                        position: a.lerp(b, 0.5),
+                        cost: 0.0,
+                        total_cost: 0.0,
+                        tile: link_tile,
+                        polygon: link_polygon,
+                        state: NodeState::Unchecked,
+                        parent: None,
+                    });
+                    nodes.len() - 1
+                };
+
+                // cost and heuristic evaluation
+                let (old_state, total_cost) = {
+                    let neighbour_node = &mut nodes[neighbour_node_index];
+
+                    let (cost, heuristic) = if end_tile == link_tile && end_poly == link_polygon {
+                        // Special case for the final node.
+                        let current_cost =
+                            best_position.distance(neighbour_node.position) * node_cost_multiplier;
+                        let end_cost = neighbour_node.position.distance(end_pos);
+                        let cost = best_cost + current_cost + end_cost;
+                        (cost, 0.0)
+                    } else {
+                        let current_cost =
+                            best_position.distance(neighbour_node.position) * node_cost_multiplier;
+                        let cost = best_cost + current_cost;
+                        let heuristic = neighbour_node.position.distance(end_pos) * HEURISTIC_SCALE;
+                        (cost, heuristic)
                     };
+                    let total_cost = cost + heuristic;
 
-                    // Open list maintenance
-                    if old_state == NodeState::Open {
-                        // Node already exists. Let's remove it.
-                        if let Some(existing_index) = open_list
-                            .iter()
-                            .position(|node| *node == neighbour_node_index)
-                        {
-                            open_list.remove(existing_index);
-                        }
+                    // Don't update if already visited with better cost
+                    if neighbour_node.state != NodeState::Unchecked
+                        && total_cost >= neighbour_node.total_cost
+                    {
+                        continue;
                     }
 
-                    // Insert based on total cost ordering (descending)
-                    if let Some(index) = open_list
+                    // Update node data
+                    let old_state = neighbour_node.state;
+                    neighbour_node.parent = Some(best_node_index);
+                    neighbour_node.state = NodeState::Open;
+                    neighbour_node.cost = cost;
+                    neighbour_node.total_cost = total_cost;
+
+                    // Remember best node if heuristic is better, used for partial paths
+                    if heuristic < last_best_node_cost {
+                        last_best_node_cost = heuristic;
+                        last_best_node = neighbour_node_index;
+                    }
+
+                    (old_state, total_cost)
+                };
+
+                // Open list maintenance
+                if old_state == NodeState::Open {
+                    // Node already exists. Let's remove it.
+                    if let Some(existing_index) = open_list
                         .iter()
-                        .position(|node_index| nodes[*node_index].total_cost < total_cost)
+                        .position(|node| *node == neighbour_node_index)
                     {
-                        open_list.insert(index, neighbour_node_index);
-                    } else {
-                        // There is no entry with a lower total.
-                        open_list.push(neighbour_node_index);
+                        open_list.remove(existing_index);
                     }
                 }
-            }
 
-            // Path Reconstruction
-            let path_count = {
-                let mut count = 0;
-                let mut parent = Some(last_best_node);
-                while let Some(parent_index) = parent {
-                    count += 1;
-                    parent = nodes[parent_index].parent;
+                // Insert based on total cost ordering (descending)
+                if let Some(index) = open_list
+                    .iter()
+                    .position(|node_index| nodes[*node_index].total_cost < total_cost)
+                {
+                    open_list.insert(index, neighbour_node_index);
+                } else {
+                    // There is no entry with a lower total.
+                    open_list.push(neighbour_node_index);
                 }
-                count
-            };
-            let mut path = Vec::with_capacity(path_count); // TODO: make local
-            let mut parent = Some(last_best_node);
-            while let Some(parent_index) = parent {
-                let node = &nodes[parent_index];
-                path.push((node.tile, node.polygon));
-                parent = node.parent;
             }
-            path.reverse(); // the path is constructed backwards
-            path
-        };
-
-        // preform string pulling to get the actual path in world space
-        if path.is_empty() {
-            return Err(PathError::PathEmpty);
         }
 
-        // let Some(start_tile_e) = lookup.get(&path[0].0) else {
-        //     return Err(PathError::MissingStartTileLookup);
-        // };
-
-        // let Ok((start_tile, start_trans)) = self.tile_query.get(*start_tile_e) else {
-        //     return Err(PathError::MissingStartTile);
-        // };
-
-        // let Some(end_tile_e) = lookup.get(&path.last().unwrap().0) else {
-        //     return Err(PathError::MissingEndTileLookup);
-        // };
-
-        // let Ok((end_tile, end_trans)) = self.tile_query.get(*end_tile_e) else {
-        //     return Err(PathError::MissingEndTile);
-        // };
-
-        //let start_pos = start_tile.get_closest_point_in_polygon(&start_tile.polygons[path[0].1 as usize], start_pos, start_trans);
-        //let end_pos = end_tile.get_closest_point_in_polygon(&end_tile.polygons[path.last().unwrap().1 as usize], end_pos, end_trans);
-
-        let mut string_path = Vec::with_capacity(path.len() / 3 + 2);
-        string_path.push(start_pos);
+        // Path Reconstruction
+        let mut path = Vec::new(); // TODO: make local
+        let mut parent = Some(last_best_node);
+        while let Some(parent_index) = parent {
+            let node = &nodes[parent_index];
+            path.push((node.tile, node.polygon));
+            parent = node.parent;
+        }
+        path.reverse(); // the path is constructed backwards
 
-        if path.len() > 1 {
-            let mut portal_apex = start_pos;
-            let mut portal_left = start_pos;
-            let mut portal_right = start_pos;
+        Ok((path, !reached_goal))
+    }
 
-            let mut left_index = 0;
-            let mut right_index = 0;
+    /// Finds the portal edges between each polygon in ``path`` and the next.
+    fn build_corridor(
+        &self,
+        nav_e: Entity,
+        path: &[(UVec2, u16)],
+    ) -> Result<Vec<CorridorPolygon>, PathError> {
+        let mut corridor = Vec::with_capacity(path.len());
+        for (i, &(tile_coord, polygon)) in path.iter().enumerate() {
+            let portal = match path.get(i + 1) {
+                Some(&(next_tile, next_polygon)) => {
+                    let (tile, tile_trans) = self.get_tile(nav_e, tile_coord)?;
 
-            let mut i = 0;
-            while i < path.len() {
-                let (left, right) = if let Some(next) = path.get(i + 1) {
-                    let current = &path[i];
                     // Find link between this and next in path.
-                    let Some(tile_e) = lookup.get(&current.0) else {
-                        return Err(PathError::MissingNodeTile);
-                    };
-                    let Ok((tile, tile_trans)) = self.tile_query.get(*tile_e) else {
-                        return Err(PathError::MissingNodeTile);
-                    };
-
-                    let is_internal = current.0 == next.0;
-                    let Some(link) = tile.polygons[current.1 as usize].links.iter().find(|link| {
-                        // This is a mess :)))
-                        match link {
-                            Link::Internal {
-                                neighbour_polygon, ..
-                            } => is_internal && next.1 == *neighbour_polygon,
-                            Link::External {
-                                neighbour_polygon,
-                                direction,
-                                ..
-                            } => match direction.offset(current.0) {
-                                Some(d) => d == next.0 && next.1 == *neighbour_polygon,
-                                None => false,
-                            },
-                        }
-                    }) else {
-                        return Err(PathError::NoLinkBetweenPathPoints);
+                    let is_internal = tile_coord == next_tile;
+                    let Some(link) =
+                        tile.polygons[polygon as usize]
+                            .links
+                            .iter()
+                            .find(|link| match link {
+                                Link::Internal {
+                                    neighbour_polygon, ..
+                                } => is_internal && next_polygon == *neighbour_polygon,
+                                Link::External {
+                                    neighbour_polygon,
+                                    direction,
+                                    ..
+                                } => {
+                                    direction.offset(tile_coord) == Some(next_tile)
+                                        && next_polygon == *neighbour_polygon
+                                }
+                            })
+                    else {
+                        return Err(PathError::NoLinkBetweenPathPoints {
+                            tile: tile_coord,
+                            polygon,
+                            next_tile,
+                            next_polygon,
+                        });
                     };
-
-                    let indices = &tile.polygons[current.1 as usize].indices;
-                    match link {
-                        Link::Internal { edge, .. } => {
-                            let a = tile.vertices[indices[*edge as usize] as usize];
-                            let b = tile.vertices
-                                [indices[(*edge + 1) as usize % indices.len()] as usize];
-                            let a = tile_trans.transform_point(a);
-                            let b = tile_trans.transform_point(b);
-
-                            #[cfg(feature = "debug_draw")]
-                            self.gizmos.line(a, b, tailwind::LIME_400);
-                            (a, b)
-                        }
-                        Link::External {
-                            edge,
-                            bound_min,
-                            bound_max,
-                            ..
-                        } => {
-                            let a = tile.vertices[indices[*edge as usize] as usize];
-                            let b = tile.vertices
-                                [indices[(*edge + 1) as usize % indices.len()] as usize];
-                            const S: f32 = 1.0 / 255.0;
-                            let a = a.lerp(b, *bound_min as f32 * S);
-                            let b = a.lerp(b, *bound_max as f32 * S);
-                            let a = tile_trans.transform_point(a);
-                            let b = tile_trans.transform_point(b);
-                            (a, b)
-                        }
-                    }
-                } else {
-                    (end_pos, end_pos)
-                };
-
-                // Right vertex.
-                if triangle_area_2d(portal_apex, portal_right, right) <= 0.0 {
-                    if portal_apex.distance_squared(portal_right) < (1.0 / 16384.0)
-                        || triangle_area_2d(portal_apex, portal_left, right) > 0.0
-                    {
-                        portal_right = right;
-                        right_index = i;
-                    } else {
-                        portal_apex = portal_left;
-
-                        if *string_path.last().unwrap() != portal_apex {
-                            string_path.push(portal_apex);
-                        }
-
-                        portal_left = portal_apex;
-                        portal_right = portal_apex;
-                        right_index = left_index;
-
-                        i = left_index + 1;
-                        continue;
-                    }
-                }
-
-                // Left vertex.
-                if triangle_area_2d(portal_apex, portal_left, left) >= 0.0 {
-                    if portal_apex.distance_squared(portal_left) < (1.0 / 16384.0)
-                        || triangle_area_2d(portal_apex, portal_right, left) < 0.0
-                    {
-                        portal_left = left;
-                        left_index = i;
-                    } else {
-                        portal_apex = portal_right;
-
-                        if *string_path.last().unwrap() != portal_apex {
-                            string_path.push(portal_apex);
-                        }
-
-                        portal_left = portal_apex;
-                        portal_right = portal_apex;
-                        left_index = right_index;
-
-                        i = right_index + 1;
-                        continue;
-                    }
+                    Some(portal_points(tile, tile_trans, polygon, link))
                 }
+                None => None,
+            };
+            corridor.push(CorridorPolygon {
+                tile: tile_coord,
+                polygon,
+                portal,
+            });
+        }
+        Ok(corridor)
+    }
 
-                i += 1;
-            }
+    /// Returns the island id of the polygon under ``point``, or ``None`` if no polygon is within the agent radius
+    /// ([Nav::get_border_size]) on the XZ-plane or it hasn't been assigned an island yet.
+    ///
+    /// Only the tiles within the agent radius are searched, found through the [TileLookup], so at most four tiles'
+    /// polygons are tested no matter how large the nav-mesh is. Use [Self::polygon_island] if the polygon is already known.
+    pub fn island_of(&self, nav_e: Entity, point: Vec3) -> Option<u32> {
+        let (_e, nav, _lookup, _trans) = self.nav_query.get(nav_e).ok()?;
+        let radius = nav.get_border_size().max(nav.cell_width);
+        let (tile, polygon, closest) = self.find_closest_polygon_in_box(nav_e, point, radius)?;
+        if closest.xz().distance(point.xz()) > radius {
+            return None;
         }
+        self.polygon_island(nav_e, tile, polygon)
+    }
 
-        if *string_path.last().unwrap() != end_pos {
-            string_path.push(end_pos);
+    /// Returns the island id of ``polygon`` in ``tile``.
+    pub fn polygon_island(&self, nav_e: Entity, tile: UVec2, polygon: u16) -> Option<u32> {
+        let (_e, _nav, lookup, _trans) = self.nav_query.get(nav_e).ok()?;
+        let islands = self.island_query.get(*lookup.get(&tile)?).ok()?;
+        islands
+            .get(polygon as usize)
+            .copied()
+            .filter(|id| *id != NO_ISLAND)
+    }
+
+    /// Returns true if a full path exists between ``a`` and ``b``.
+    ///
+    /// Cheap compared to [Self::find_path], the cost is the two [Self::island_of] lookups, comparing the ids is constant time.
+    pub fn is_reachable(&self, nav_e: Entity, a: Vec3, b: Vec3) -> bool {
+        match (self.island_of(nav_e, a), self.island_of(nav_e, b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
         }
+    }
 
-        #[cfg(feature = "debug_draw")]
-        self.gizmos
-            .linestrip(string_path.clone(), tailwind::EMERALD_800);
+    /// Returns the [TileNavMesh] and transform for the tile at ``tile_coord``.
+    fn get_tile(
+        &self,
+        nav_e: Entity,
+        tile_coord: UVec2,
+    ) -> Result<(&TileNavMesh, &GlobalTransform), PathError> {
+        self.nav_query
+            .get(nav_e)
+            .ok()
+            .and_then(|(_e, _nav, lookup, _trans)| lookup.get(&tile_coord))
+            .and_then(|tile_e| self.tile_query.get(*tile_e).ok())
+            .ok_or(PathError::MissingNodeTile { tile: tile_coord })
+    }
 
-        Ok(string_path)
+    /// Extra cost multiplier from characters standing on the polygon, see [TileCharacterCosts].
+    fn character_cost(&self, nav_e: Entity, tile_coord: UVec2, polygon: u16) -> f32 {
+        self.nav_query
+            .get(nav_e)
+            .ok()
+            .and_then(|(_e, _nav, lookup, _trans)| lookup.get(&tile_coord))
+            .and_then(|tile_e| self.character_cost_query.get(*tile_e).ok())
+            .and_then(|costs| costs.get(&polygon).copied())
+            .unwrap_or(0.0)
     }
 
     /// 
@@ -567,6 +593,232 @@ impl<'w, 's> NavPath<'w, 's> {
 
         out_polygon
     }
+
+    /// Finds the closest navmesh boundary to ``pos`` within ``max_radius``, measured on the XZ-plane.
+    ///
+    /// Boundaries are polygon edges with no neighbour ([EdgeConnection::None]) and the parts of tile border edges not linked to a neighbouring tile.
+    pub fn distance_to_wall(&self, nav_e: Entity, pos: Vec3, max_radius: f32) -> Option<WallHit> {
+        let (_e, nav, lookup, nav_trans) = self.nav_query.get(nav_e).ok()?;
+
+        let min_tile = nav.get_tile_containing_position((pos - max_radius).xz(), nav_trans);
+        let max_tile = nav.get_tile_containing_position((pos + max_radius).xz(), nav_trans);
+
+        let mut best: Option<WallHit> = None;
+        let mut best_distance_sq = max_radius * max_radius;
+        for x in min_tile.x..=max_tile.x {
+            for y in min_tile.y..=max_tile.y {
+                let Some(tile_entity) = lookup.get(&UVec2::new(x, y)) else {
+                    continue;
+                };
+                let Ok((tile, tile_trans)) = self.tile_query.get(*tile_entity) else {
+                    continue;
+                };
+                for (poly_i, polygon) in tile.polygons.iter().enumerate() {
+                    let vertices = polygon
+                        .indices
+                        .map(|i| tile_trans.transform_point(tile.vertices[i as usize]));
+                    let centroid = vertices.iter().sum::<Vec3>() / vertices.len() as f32;
+
+                    for edge in 0..vertices.len() {
+                        let a = vertices[edge];
+                        let b = vertices[(edge + 1) % vertices.len()];
+                        for (t_min, t_max) in wall_segments(tile, poly_i, edge) {
+                            let seg_a = a.lerp(b, t_min);
+                            let seg_b = a.lerp(b, t_max);
+                            let t = closest_t_on_segment_2d(pos, seg_a, seg_b);
+                            let point = seg_a.lerp(seg_b, t);
+                            let distance_sq = (point - pos).xz().length_squared();
+                            if distance_sq >= best_distance_sq {
+                                continue;
+                            }
+
+                            // normal faces into the polygon
+                            let edge_dir = (b - a).xz();
+                            let mut normal =
+                                Vec3::new(-edge_dir.y, 0.0, edge_dir.x).normalize_or_zero();
+                            if normal.dot(centroid - a) < 0.0 {
+                                normal = -normal;
+                            }
+
+                            best_distance_sq = distance_sq;
+                            best = Some(WallHit {
+                                distance: distance_sq.sqrt(),
+                                point,
+                                normal,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        best
+    }
+}
+
+/// Closest boundary found by [NavPath::distance_to_wall].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WallHit {
+    /// Distance from the query position to the wall on the XZ-plane.
+    pub distance: f32,
+    /// Closest point on the wall in world space.
+    pub point: Vec3,
+    /// Horizontal wall normal, pointing into the walkable side.
+    pub normal: Vec3,
+}
+
+/// Parts of a polygon edge that are boundaries, as ``(t_min, t_max)`` ranges along the edge.
+fn wall_segments(tile: &TileNavMesh, polygon: usize, edge: usize) -> SmallVec<[(f32, f32); 2]> {
+    let mut segments = SmallVec::new();
+    match tile.edges[polygon][edge] {
+        EdgeConnection::None => segments.push((0.0, 1.0)),
+        EdgeConnection::Internal(_) => {}
+        EdgeConnection::External(_) => {
+            // Tile border, anything not covered by a link to the neighbour tile is a wall
+            const S: f32 = 1.0 / 255.0;
+            let mut covered: SmallVec<[(f32, f32); 4]> = tile.polygons[polygon]
+                .links
+                .iter()
+                .filter_map(|link| match link {
+                    Link::External {
+                        edge: link_edge,
+                        bound_min,
+                        bound_max,
+                        ..
+                    } if *link_edge as usize == edge => {
+                        Some((*bound_min as f32 * S, *bound_max as f32 * S))
+                    }
+                    _ => None,
+                })
+                .collect();
+            covered.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut t = 0.0;
+            for (min, max) in covered {
+                if min > t {
+                    segments.push((t, min));
+                }
+                t = t.max(max);
+            }
+            if t < 1.0 {
+                segments.push((t, 1.0));
+            }
+        }
+    }
+    segments
+}
+
+/// Parametric position of the closest point to ``point`` on segment ``a``-``b`` on the XZ-plane.
+fn closest_t_on_segment_2d(point: Vec3, a: Vec3, b: Vec3) -> f32 {
+    let ab = (b - a).xz();
+    let d = ab.length_squared();
+    if d <= 0.0 {
+        return 0.0;
+    }
+    (ab.dot((point - a).xz()) / d).clamp(0.0, 1.0)
+}
+
+/// World space end points of the edge ``link`` crosses, clamped to the linked part of the edge for external links.
+fn portal_points(
+    tile: &TileNavMesh,
+    tile_trans: &GlobalTransform,
+    polygon: u16,
+    link: &Link,
+) -> (Vec3, Vec3) {
+    let indices = &tile.polygons[polygon as usize].indices;
+    let edge = match link {
+        Link::Internal { edge, .. } | Link::External { edge, .. } => *edge as usize,
+    };
+    let a = tile.vertices[indices[edge] as usize];
+    let b = tile.vertices[indices[(edge + 1) % indices.len()] as usize];
+    let (a, b) = match link {
+        Link::Internal { .. } => (a, b),
+        Link::External {
+            bound_min,
+            bound_max,
+            ..
+        } => {
+            const S: f32 = 1.0 / 255.0;
+            (
+                a.lerp(b, *bound_min as f32 * S),
+                a.lerp(b, *bound_max as f32 * S),
+            )
+        }
+    };
+    (tile_trans.transform_point(a), tile_trans.transform_point(b))
+}
+
+/// Simple stupid funnel algorithm over the corridor portals.
+fn string_pull(start_pos: Vec3, end_pos: Vec3, corridor: &[CorridorPolygon]) -> Vec<Vec3> {
+    let mut string_path = Vec::with_capacity(corridor.len() / 3 + 2);
+    string_path.push(start_pos);
+
+    if corridor.len() > 1 {
+        let mut portal_apex = start_pos;
+        let mut portal_left = start_pos;
+        let mut portal_right = start_pos;
+
+        let mut left_index = 0;
+        let mut right_index = 0;
+
+        let mut i = 0;
+        while i < corridor.len() {
+            let (left, right) = corridor[i].portal.unwrap_or((end_pos, end_pos));
+
+            // Right vertex.
+            if triangle_area_2d(portal_apex, portal_right, right) <= 0.0 {
+                if portal_apex.distance_squared(portal_right) < (1.0 / 16384.0)
+                    || triangle_area_2d(portal_apex, portal_left, right) > 0.0
+                {
+                    portal_right = right;
+                    right_index = i;
+                } else {
+                    portal_apex = portal_left;
+
+                    if *string_path.last().unwrap() != portal_apex {
+                        string_path.push(portal_apex);
+                    }
+
+                    portal_left = portal_apex;
+                    portal_right = portal_apex;
+                    right_index = left_index;
+
+                    i = left_index + 1;
+                    continue;
+                }
+            }
+
+            // Left vertex.
+            if triangle_area_2d(portal_apex, portal_left, left) >= 0.0 {
+                if portal_apex.distance_squared(portal_left) < (1.0 / 16384.0)
+                    || triangle_area_2d(portal_apex, portal_right, left) < 0.0
+                {
+                    portal_left = left;
+                    left_index = i;
+                } else {
+                    portal_apex = portal_right;
+
+                    if *string_path.last().unwrap() != portal_apex {
+                        string_path.push(portal_apex);
+                    }
+
+                    portal_left = portal_apex;
+                    portal_right = portal_apex;
+                    left_index = right_index;
+
+                    i = right_index + 1;
+                    continue;
+                }
+            }
+
+            i += 1;
+        }
+    }
+
+    if *string_path.last().unwrap() != end_pos {
+        string_path.push(end_pos);
+    }
+    string_path
 }
 
 fn triangle_area_2d(a: Vec3, b: Vec3, c: Vec3) -> f32 {
@@ -578,3 +830,128 @@ fn triangle_area_2d(a: Vec3, b: Vec3, c: Vec3) -> f32 {
 
     ac_x * ab_z - ab_x * ac_z
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+    use raven_bvh::prelude::Blas;
+
+    use super::*;
+    use crate::island::tests::two_tile_nav;
+
+    /// On the left tile, on the polygon linked to the right tile.
+    const START: Vec3 = vec3(-20.0, 0.0, -2.0);
+    /// On the right tile, on the polygon linked to the left tile.
+    const ACROSS: Vec3 = vec3(20.0, 0.0, 3.0);
+    /// On the right tile's lone triangle.
+    const ISLAND: Vec3 = vec3(23.0, 0.0, 10.0);
+
+    fn setup() -> (World, Entity) {
+        let mut world = World::new();
+        world.init_resource::<Assets<Blas>>();
+        let nav_e = two_tile_nav(&mut world);
+        (world, nav_e)
+    }
+
+    #[test]
+    fn test_is_reachable() {
+        let (mut world, nav_e) = setup();
+        let mut state = SystemState::<NavPath>::new(&mut world);
+        let nav_path = state.get_mut(&mut world);
+
+        assert!(nav_path.is_reachable(nav_e, START, ACROSS));
+        assert!(!nav_path.is_reachable(nav_e, START, ISLAND));
+        assert!(nav_path.island_of(nav_e, ISLAND).is_some());
+
+        // within the agent radius of the strip still counts, further out doesn't
+        assert!(nav_path.island_of(nav_e, vec3(-20.0, 0.0, 5.3)).is_some());
+        assert_eq!(nav_path.island_of(nav_e, vec3(-20.0, 0.0, 8.0)), None);
+    }
+
+    #[test]
+    fn test_find_path_across_tiles() {
+        let (mut world, nav_e) = setup();
+        let mut state = SystemState::<NavPath>::new(&mut world);
+        let mut nav_path = state.get_mut(&mut world);
+
+        let result = nav_path
+            .find_path(nav_e, START, ACROSS, None, None)
+            .unwrap();
+        assert!(!result.is_partial);
+        let polygons = result
+            .corridor
+            .iter()
+            .map(|c| (c.tile, c.polygon))
+            .collect::<Vec<_>>();
+        assert_eq!(polygons, [(UVec2::ZERO, 1), (UVec2::X, 0)]);
+
+        // only the linked half of the shared border is crossed
+        let (left, right) = result.corridor[0].portal.unwrap();
+        for end in [left, right] {
+            assert!(end.x.abs() < 1e-4, "{end}");
+            assert!((-5.0..=0.0).contains(&end.z), "{end}");
+        }
+        assert_eq!(result.corridor[1].portal, None);
+
+        // the straight line crosses the border at z = 0.5, outside the link, so the path bends at its end
+        assert_eq!(result.points.len(), 3, "{:?}", result.points);
+        assert_eq!((result.points[0], result.points[2]), (START, ACROSS));
+        let bend = vec3(0.0, 0.0, -5.0 + 10.0 * 127.0 / 255.0);
+        let point = result.points[1];
+        assert!(point.distance(bend) < 1e-3, "{point}");
+    }
+
+    #[test]
+    fn test_find_path_partial() {
+        let (mut world, nav_e) = setup();
+        let mut state = SystemState::<NavPath>::new(&mut world);
+        let mut nav_path = state.get_mut(&mut world);
+
+        let result = nav_path
+            .find_path(nav_e, START, ISLAND, None, None)
+            .unwrap();
+        assert!(result.is_partial);
+        // ends on the polygon closest to the lone triangle, at its closest point
+        let last = result.corridor.last().unwrap();
+        assert_eq!((last.tile, last.polygon), (UVec2::X, 1));
+        let end = *result.points.last().unwrap();
+        assert!(end.distance(vec3(25.2, 0.0, 3.4)) < 1e-3, "{end}");
+    }
+
+    #[test]
+    fn test_distance_to_wall() {
+        let (mut world, nav_e) = setup();
+        let mut state = SystemState::<NavPath>::new(&mut world);
+        let nav_path = state.get_mut(&mut world);
+
+        // open edge along the side of the strip
+        let hit = nav_path
+            .distance_to_wall(nav_e, vec3(-5.0, 0.0, 4.0), 3.0)
+            .unwrap();
+        assert!((hit.distance - 1.0).abs() < 1e-4, "{hit:?}");
+        assert!(hit.point.distance(vec3(-5.0, 0.0, 5.0)) < 1e-4, "{hit:?}");
+        assert!(hit.normal.distance(Vec3::NEG_Z) < 1e-4, "{hit:?}");
+
+        // the unlinked half of the shared border is a wall, seen from both tiles
+        let hit = nav_path
+            .distance_to_wall(nav_e, vec3(-1.0, 0.0, 3.0), 3.0)
+            .unwrap();
+        assert!((hit.distance - 1.0).abs() < 1e-4, "{hit:?}");
+        assert!(hit.point.distance(vec3(0.0, 0.0, 3.0)) < 1e-4, "{hit:?}");
+        assert!((hit.normal.x.abs() - 1.0).abs() < 1e-4, "{hit:?}");
+
+        // the linked half isn't, the far side of the strip is closer
+        let hit = nav_path
+            .distance_to_wall(nav_e, vec3(-1.0, 0.0, -2.5), 3.0)
+            .unwrap();
+        assert!((hit.distance - 2.5).abs() < 1e-4, "{hit:?}");
+        assert!(hit.point.distance(vec3(-1.0, 0.0, -5.0)) < 1e-4, "{hit:?}");
+        assert!(hit.normal.distance(Vec3::Z) < 1e-4, "{hit:?}");
+
+        // nothing within the radius
+        assert_eq!(
+            nav_path.distance_to_wall(nav_e, vec3(-15.0, 0.0, 0.0), 2.0),
+            None
+        );
+    }
+}
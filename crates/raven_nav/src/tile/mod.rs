@@ -12,12 +12,17 @@ use bevy::{
 use raven_bvh::prelude::*;
 use smallvec::SmallVec;
 
-use crate::{collider::*, nav::*, tile::mesher::*, tile::nav_mesh::*, utils::Aabb3dExt};
+use crate::{
+    character::TileCharacterCosts, collider::*, island::TileIslands, nav::*, tile::mesher::*, tile::nav_mesh::*,
+    utils::Aabb3dExt,
+};
 
 #[derive(Component, Reflect, Deref, DerefMut)]
 #[require(
     Transform,
     TileAffectors,
+    TileIslands,
+    TileCharacterCosts,
     Visibility // used for rendering mesh
 )]
 pub struct Tile(pub UVec2);
@@ -54,6 +59,7 @@ pub struct TileViewMesh;
 pub(crate) type TileBuildResult = Option<(TileNavMesh, Aabb3d, Mesh, Blas)>;
 
 pub(crate) async fn build_tile(
+    tile_coord: UVec2,
     waymap: Nav,
     geometry_collections: Vec<GeometryCollection>,
     heightfield_collections: Vec<HeightFieldCollection>,
@@ -77,7 +83,14 @@ pub(crate) async fn build_tile(
         // no vertices in nav mesh, can only happen when no walkable area is found
         return None;
     };
-    let blas = Blas::from(&mesh);
+    let blas = match Blas::try_from(&mesh) {
+        Ok(blas) => blas,
+        Err(e) => {
+            // vertices left over without any polygons, nothing to walk on
+            warn!("Skipping nav tile {tile_coord}: {e}");
+            return None;
+        }
+    };
 
     Some((nav_mesh, aabb, mesh, blas))
 }
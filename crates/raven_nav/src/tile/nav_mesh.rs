@@ -32,12 +32,38 @@ impl TileNavMesh {
         let local_pos = trans.affine().inverse().transform_point(position);
         let vertices: [Vec3; 3] = polygon.indices.map(|index| self.vertices[index as usize]);
         if let Some(height) = get_height_in_triangle(&vertices, local_pos) {
-            return Vec3::new(local_pos.x, height, local_pos.z);
+            return trans.transform_point(Vec3::new(local_pos.x, height, local_pos.z));
         }
         let local_closest = closest_point_on_edges(&vertices, local_pos);
         trans.transform_point(local_closest)
     }
 
+    /// Writes the nav-mesh as plain text, identical across runs for the same input.
+    ///
+    /// Used for golden tests, positions are rounded to keep float noise out of diffs.
+    pub fn to_text(&self) -> String {
+        use std::fmt::Write;
+
+        let mut text = String::new();
+        writeln!(text, "vertices {}", self.vertices.len()).unwrap();
+        for v in self.vertices.iter() {
+            writeln!(text, "  {:.3} {:.3} {:.3}", v.x, v.y, v.z).unwrap();
+        }
+        writeln!(text, "polygons {}", self.polygons.len()).unwrap();
+        for (i, polygon) in self.polygons.iter().enumerate() {
+            writeln!(
+                text,
+                "  {:?} area {} edges {:?}",
+                polygon.indices, self.areas[i].0, self.edges[i]
+            )
+            .unwrap();
+            for link in polygon.links.iter() {
+                writeln!(text, "    {link:?}").unwrap();
+            }
+        }
+        text
+    }
+
     pub fn remove_links_to_direction(&mut self, remove_direction: EdgeConnectionDirection) {
         for polygon in self.polygons.iter_mut() {
             polygon.links.retain(|link| match link {
@@ -282,6 +308,25 @@ pub fn connect_external_links(
             }));
             break; // We can only have one edge parallel to the direction in a triangle.
         }
+
+        // keep links in the same order no matter which tile was built first
+        polygon.links.sort_unstable_by_key(link_sort_key);
+    }
+}
+
+fn link_sort_key(link: &Link) -> (u8, u8, u8, u16, u8) {
+    match link {
+        Link::Internal {
+            edge,
+            neighbour_polygon,
+        } => (0, *edge, 0, *neighbour_polygon, 0),
+        Link::External {
+            edge,
+            neighbour_polygon,
+            direction,
+            bound_min,
+            ..
+        } => (1, *edge, *direction as u8, *neighbour_polygon, *bound_min),
     }
 }
 
@@ -546,8 +546,9 @@ fn merge_regions(
         let old_id = regions[i].id;
         let new_id = region_id_gen;
 
+        // only regions not remapped yet, a new id can match an old id further along
         for region in regions.iter_mut().skip(i) {
-            if region.id == old_id {
+            if region.remap && region.id == old_id {
                 region.id = new_id;
                 region.remap = false;
             }
@@ -0,0 +1,179 @@
+//! Bakes the example scenes headlessly and compares every tile's [TileNavMesh] against the files in ``tests/golden``.
+//!
+//! Run with ``RAVEN_BLESS=1`` to write new golden files after an intended change to generation.
+use std::{fmt::Write, path::PathBuf, time::Duration};
+
+use avian3d::prelude::*;
+use bevy::{prelude::*, render::mesh::MeshPlugin};
+use raven_nav::{prelude::*, tile::nav_mesh::TileNavMesh};
+
+/// Upper bound on frames to wait for all tiles to build.
+const MAX_UPDATES: usize = 10_000;
+
+#[test]
+fn golden_simple() {
+    check_golden("simple", |commands| {
+        commands.spawn((
+            Name::new("Nav"),
+            Nav::new(0.5, 1.9, Vec3::splat(50.0)).with_traversible_slope(40.0_f32.to_radians()),
+        ));
+        commands.spawn((
+            Name::new("Ground"),
+            Transform::default(),
+            Collider::trimesh_from_mesh(&Plane3d::new(Vec3::Y, vec2(10.0, 10.0)).mesh().build())
+                .unwrap(),
+            NavMeshAffector::default(),
+        ));
+        commands.spawn((
+            Name::new("Cube"),
+            Transform::from_xyz(0.0, 3.0, 0.0),
+            Collider::cuboid(1.0, 1.0, 1.0),
+            NavMeshAffector::default(),
+        ));
+    });
+}
+
+#[test]
+fn golden_heightfield() {
+    check_golden("heightfield", |commands| {
+        commands.spawn((Name::new("Nav"), Nav::new(0.5, 1.9, Vec3::splat(300.0))));
+
+        let resolution = 250;
+        let oct = 10.0;
+        let height_scale = 8.0;
+        let scale = Vec3::new(resolution as f32, height_scale, resolution as f32);
+
+        let mut heightfield = vec![vec![0.0; resolution]; resolution];
+        for x in 0..resolution {
+            for y in 0..resolution {
+                heightfield[x][y] = (x as f32 / oct).sin() + (y as f32 / oct).cos();
+            }
+        }
+
+        commands.spawn((
+            Name::new("Heightfield"),
+            Transform::from_xyz(0., -15.0, 0.),
+            Collider::heightfield(heightfield, scale),
+            NavMeshAffector::default(),
+        ));
+    });
+}
+
+#[test]
+fn golden_cubes() {
+    check_golden("cubes", |commands| {
+        commands.spawn((Name::new("Nav"), Nav::new(0.5, 1.9, Vec3::splat(100.0))));
+
+        commands.spawn((
+            Name::new("Ground"),
+            Transform::from_xyz(0.0, -2.0, 0.0),
+            Collider::cuboid(100.0, 1.0, 100.0),
+            NavMeshAffector::default(),
+        ));
+
+        let cube_size = 2.0;
+        for x in -2..2 {
+            for y in -2..2 {
+                for z in -2..2 {
+                    let position =
+                        Vec3::new(x as f32, y as f32 + 3.0, z as f32) * (cube_size + 0.05);
+                    commands.spawn((
+                        Transform::from_translation(position),
+                        Collider::cuboid(cube_size, cube_size, cube_size),
+                        NavMeshAffector::default(),
+                    ));
+                }
+            }
+        }
+    });
+}
+
+/// Spawns the scene, runs until no tile is dirty or building, then diffs the result with the golden file.
+fn check_golden(name: &str, setup: impl Fn(&mut Commands) + Send + Sync + 'static) {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        TransformPlugin,
+        AssetPlugin::default(),
+        ImagePlugin::default(),
+        MeshPlugin,
+        NavPlugin,
+    ))
+    .add_systems(Startup, move |mut commands: Commands| setup(&mut commands));
+
+    // a few frames so affectors are gathered and tiles marked dirty
+    for _ in 0..3 {
+        app.update();
+    }
+    let mut updates = 0;
+    while !is_idle(app.world_mut()) {
+        assert!(
+            updates < MAX_UPDATES,
+            "{name}: tiles never finished building"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+        app.update();
+        updates += 1;
+    }
+
+    let actual = serialize_tiles(app.world_mut());
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.txt"));
+
+    if std::env::var_os("RAVEN_BLESS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, &actual).unwrap();
+        println!("{name}: wrote golden file {}", path.display());
+        return;
+    }
+
+    let Ok(expected) = std::fs::read_to_string(&path) else {
+        panic!(
+            "{name}: missing golden file {}, run with RAVEN_BLESS=1 to write it",
+            path.display()
+        );
+    };
+    if let Some((line, (expected, actual))) = expected
+        .lines()
+        .zip(actual.lines())
+        .enumerate()
+        .find(|(_, (expected, actual))| expected != actual)
+    {
+        panic!(
+            "{name}: nav-mesh differs from {} at line {}\n  expected: {expected}\n  actual:   {actual}",
+            path.display(),
+            line + 1
+        );
+    }
+    assert_eq!(
+        expected.lines().count(),
+        actual.lines().count(),
+        "{name}: nav-mesh differs from {} in length",
+        path.display()
+    );
+}
+
+/// Every nav is done building and at least one tile has polygons, so generation has started and finished.
+fn is_idle(world: &mut World) -> bool {
+    let mut query = world.query::<(&DirtyTiles, &NavGenerationTasks)>();
+    let mut navs = query.iter(world).peekable();
+    if navs.peek().is_none() || !navs.all(|(dirty, tasks)| dirty.is_empty() && tasks.is_empty()) {
+        return false;
+    }
+    let mut tiles = world.query::<&TileNavMesh>();
+    tiles.iter(world).any(|nav_mesh| !nav_mesh.areas.is_empty())
+}
+
+fn serialize_tiles(world: &mut World) -> String {
+    let mut query = world.query::<(&Tile, &TileNavMesh)>();
+    let mut tiles = query.iter(world).collect::<Vec<_>>();
+    tiles.sort_unstable_by_key(|(tile, _)| (tile.y, tile.x));
+
+    let mut text = String::new();
+    for (tile, nav_mesh) in tiles {
+        writeln!(text, "tile {} {}", tile.x, tile.y).unwrap();
+        text.push_str(&nav_mesh.to_text());
+    }
+    text
+}
@@ -72,14 +72,19 @@ fn update_destination(
             None,              // no filter
             Some(&[1.0, 0.5]), // no filter
         ) {
-            Ok(path) => match path.split_first() {
+            Ok(result) => match result.points.split_first() {
                 Some((first, remaining)) => {
                     let mut remaining = remaining.to_vec();
                     remaining.reverse();
+                    // partial paths stop at the closest reachable point instead of the destination
+                    let target = match result.is_partial {
+                        true => *result.points.last().unwrap(),
+                        false => end,
+                    };
                     let path = Path {
                         current: *first,
                         next: remaining,
-                        target: PathTarget::Position(end),
+                        target: PathTarget::Position(target),
                     };
                     commands
                         .entity(e)
@@ -90,7 +95,7 @@ fn update_destination(
                     error!(" Path has only one point");
                 }
             },
-            Err(_) => error!("Error finding path from {:?} to {:?}", start, end),
+            Err(err) => error!("Error finding path: {}", err),
         }
     }
 
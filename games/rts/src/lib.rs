@@ -171,6 +171,7 @@ fn ray_cast(
     window: Single<&Window>,
     tlas_query: Single<Entity, (With<Nav>, With<Tlas>)>,
     tlas: TlasCast,
+    tile_query: Query<(), With<Tile>>,
     mut gizmos: Gizmos,
     mut nav_path: NavPath,
     input: Res<ButtonInput<MouseButton>>,
@@ -179,6 +180,9 @@ fn ray_cast(
 ) {
     let (camera, camera_transform) = *camera_query;
     let tlas_entity = *tlas_query;
+    // only want the ground, skip anything else in the tlas
+    let ground_only = |e: Entity| tile_query.contains(e);
+    let settings = TlasCastSettings::default().with_filter(&ground_only);
 
     // Use Right mouse buttons to set start
     if input.pressed(MouseButton::Right) {
@@ -190,8 +194,8 @@ fn ray_cast(
         };
 
         let ray_cast = RayCast3d::from_ray(ray, 100.0);
-        if let Some((_e, hit)) = tlas.intersect_tlas(&ray_cast, tlas_entity) {
-            *start_pos = ray.get_point(hit.distance);
+        if let Some(hit) = tlas.cast_ray(&ray_cast, tlas_entity, &settings) {
+            *start_pos = hit.point;
         }
     }
 
@@ -204,8 +208,8 @@ fn ray_cast(
             return;
         };
         let ray_cast = RayCast3d::from_ray(ray, 100.0);
-        if let Some((_e, hit)) = tlas.intersect_tlas(&ray_cast, tlas_entity) {
-            *end_pos = ray.get_point(hit.distance);
+        if let Some(hit) = tlas.cast_ray(&ray_cast, tlas_entity, &settings) {
+            *end_pos = hit.point;
         }
     }
 
@@ -215,7 +219,7 @@ fn ray_cast(
 
     // Run pathfinding to get a polygon path.
     match nav_path.find_path(tlas_entity, *start_pos, *end_pos, None, Some(&[1.0, 0.5])) {
-        Ok(path) => gizmos.linestrip(path, tailwind::BLUE_300),
+        Ok(path) => gizmos.linestrip(path.points, tailwind::BLUE_300),
         Err(error) => error_once!("Error with pathfinding: {:?}", error),
     }
 }
@@ -1,4 +1,8 @@
-use bevy::platform::collections::HashMap;
+use bevy::{
+    platform::collections::HashMap,
+    render::primitives::{Frustum, HalfSpace},
+};
+use raven_bvh::prelude::{SpawnBvhForTlas, Tlas, TlasCast, TlasCastSettings, TlasTarget};
 
 use crate::prelude::*;
 
@@ -11,21 +15,32 @@ const SELECT_GROUP_KEYS: [KeyCode; 6] = [
     KeyCode::Digit6,
 ];
 
+/// Pixels the cursor has to move before a press becomes a box selection.
+const DRAG_THRESHOLD: f32 = 8.0;
+
+/// How far from the camera box selection reaches.
+const SELECT_DISTANCE: f32 = 500.0;
+
 pub fn plugin(app: &mut App) {
     app.init_resource::<Selected>()
         .init_resource::<SelectedGroups>()
         .init_resource::<CursorPos>()
         .add_systems(Update, (valid_selected, selection_groups, draw_selected))
+        .add_systems(OnEnter(AppState::InGame), spawn_selection_tlas)
         .add_systems(
             Update,
             (
                 get_cursor_world_pos,
-                //(
-                // start_drag.run_if(input_just_pressed(MouseButton::Left)),
-                // end_drag.run_if(input_just_released(MouseButton::Left)),
-                // drag.run_if(resource_exists::<CursorDrag>),
-                //),
-            ), //.chain(),
+                add_selectable,
+                (
+                    start_drag.run_if(input_just_pressed(MouseButton::Left)),
+                    drag.run_if(resource_exists::<CursorDrag>),
+                    end_drag.run_if(input_just_released(MouseButton::Left)),
+                )
+                    .chain()
+                    .after(get_cursor_world_pos)
+                    .run_if(in_state(AppState::InGame)),
+            ),
         )
         .add_systems(
             OnExit(AppState::InGame),
@@ -89,16 +104,59 @@ fn draw_selected(selected: Res<Selected>, query: Query<&Transform>, mut gizmos:
     }
 }
 
+/// Tlas of the selectable units, used for box selection.
+#[derive(Component)]
+pub struct SelectionTlas;
+
+fn spawn_selection_tlas(mut commands: Commands) {
+    commands.spawn((
+        Name::new("SelectionTlas"),
+        Tlas::default(),
+        SelectionTlas,
+        StateScoped(AppState::InGame),
+    ));
+}
+
+/// Adds units to the [SelectionTlas] once they have a mesh.
+#[allow(clippy::type_complexity)]
+fn add_selectable(
+    mut commands: Commands,
+    selection_tlas: Single<Entity, With<SelectionTlas>>,
+    query: Query<
+        Entity,
+        (
+            With<Unit>,
+            With<Mesh3d>,
+            Without<TlasTarget>,
+            Without<SpawnBvhForTlas>,
+        ),
+    >,
+) {
+    for e in query.iter() {
+        commands.entity(e).insert(SpawnBvhForTlas(*selection_tlas));
+    }
+}
+
 /// The projected 2D world coordinates of the cursor (if it's within primary window bounds).
 #[derive(Resource, Default)]
 struct CursorPos {
+    screen: Option<Vec2>,
     world: Option<Vec3>,
 }
 
-/// The current drag operation including the offset with which we grabbed the Bevy logo.
+/// The current drag operation, where it started and if it has moved far enough to be a box selection.
 #[derive(Resource)]
-struct CursorDrag {
-    world: Vec3,
+pub struct CursorDrag {
+    screen: Vec2,
+    world: Option<Vec3>,
+    active: bool,
+}
+
+impl CursorDrag {
+    /// Is this drag a box selection, clicks should be ignored while it is.
+    pub fn is_box_select(&self) -> bool {
+        self.active
+    }
 }
 
 /// Project the cursor into the world coordinates and store it in a resource for easy use
@@ -113,7 +171,7 @@ fn get_cursor_world_pos(
         return;
     };
 
-    // cursor_pos.screen = Some(cursor_position);
+    cursor_pos.screen = Some(cursor_position);
 
     // Calculate a ray pointing from the camera into the world based on the cursor's position.
     let Ok(ray) = main_camera.viewport_to_world(main_camera_transform, cursor_position) else {
@@ -126,31 +184,33 @@ fn get_cursor_world_pos(
     };
     cursor_pos.world = Some(ray.get_point(distance));
 }
-/// Start the drag operation and record the offset we started dragging from
-#[allow(dead_code)]
+/// Start the drag operation and record where we started dragging from
 fn start_drag(mut commands: Commands, cursor_pos: Res<CursorPos>) {
     // If the cursor is not within the primary window skip this system
-    let Some(world) = cursor_pos.world else {
+    let Some(screen) = cursor_pos.screen else {
         return;
     };
-    // let Some(screen) = cursor_pos.screen else {
-    //     return;
-    // };
-    commands.insert_resource(CursorDrag { world });
+    commands.insert_resource(CursorDrag {
+        screen,
+        world: cursor_pos.world,
+        active: false,
+    });
 }
 
 /// Stop the current drag operation
-#[allow(dead_code)]
 fn end_drag(mut commands: Commands) {
     commands.remove_resource::<CursorDrag>();
 }
 
-/// Drag the Bevy logo
-#[allow(dead_code)]
+/// Box select the player's units inside the dragged screen rectangle
+#[allow(clippy::too_many_arguments)]
 fn drag(
-    cursor_drag: Res<CursorDrag>,
+    mut cursor_drag: ResMut<CursorDrag>,
     cursor_pos: Res<CursorPos>,
-    canidates: Query<(Entity, &Team, &Transform), With<Unit>>,
+    camera: Single<(&Camera, &GlobalTransform), With<GameCamera>>,
+    selection_tlas: Single<Entity, With<SelectionTlas>>,
+    tlas_cast: TlasCast,
+    canidates: Query<&Team, With<Unit>>,
     mut selected: ResMut<Selected>,
     inputs: Res<ButtonInput<KeyCode>>,
     player: Single<&Team, With<ActivePlayer>>,
@@ -158,55 +218,86 @@ fn drag(
 ) {
     let player_team = *player;
     // If the cursor is not within the primary window skip this system
-    let Some(world_pos) = cursor_pos.world else {
+    let Some(screen) = cursor_pos.screen else {
         return;
     };
+    if !cursor_drag.active {
+        if screen.distance(cursor_drag.screen) < DRAG_THRESHOLD {
+            return;
+        }
+        cursor_drag.active = true;
+    }
 
-    gizmos.circle(
-        Isometry3d::new(
-            vec3(world_pos.x, 0.01, world_pos.z),
-            Quat::from_rotation_arc(Vec3::Z, Vec3::Y),
-        ),
-        0.2,
-        Color::WHITE,
-    );
-    gizmos.line(world_pos, cursor_drag.world, Color::WHITE);
-
-    let mid_point = (world_pos + cursor_drag.world) / 2.0;
-    let size = Vec2::new(
-        (world_pos.x - cursor_drag.world.x).abs(),
-        (world_pos.z - cursor_drag.world.z).abs(),
-    );
-    gizmos.rect(
-        Isometry3d::new(mid_point, Quat::from_rotation_arc(Vec3::Z, Vec3::Y)),
-        size,
-        Color::WHITE,
-    );
+    if let (Some(world_pos), Some(drag_world)) = (cursor_pos.world, cursor_drag.world) {
+        gizmos.line(world_pos, drag_world, Color::WHITE);
+    }
+
+    let (camera, camera_transform) = *camera;
+    let min = screen.min(cursor_drag.screen);
+    let max = screen.max(cursor_drag.screen);
+    let corners = [min, vec2(max.x, min.y), max, vec2(min.x, max.y)];
+    let mut rays = [Ray3d::new(Vec3::ZERO, Dir3::NEG_Z); 4];
+    for (ray, corner) in rays.iter_mut().zip(corners) {
+        let Ok(corner_ray) = camera.viewport_to_world(camera_transform, corner) else {
+            return;
+        };
+        *ray = corner_ray;
+    }
+
+    // outline of the box on the ground
+    let ground = rays
+        .iter()
+        .filter_map(|ray| {
+            ray.intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Y))
+                .map(|distance| ray.get_point(distance) + Vec3::Y * 0.01)
+        })
+        .collect::<Vec<_>>();
+    if ground.len() == rays.len() {
+        gizmos.linestrip(ground.iter().copied().chain([ground[0]]), Color::WHITE);
+    }
+
+    // too thin to make a frustum
+    if (max - min).min_element() < 1.0 {
+        return;
+    }
+    let frustum = rays_frustum(&rays, camera_transform.forward().as_vec3(), SELECT_DISTANCE);
 
     // update selected
     if !inputs.pressed(KeyCode::ShiftLeft) {
         selected.clear();
     }
 
-    canidates
-        .iter()
-        .filter(|(_, team, _)| *team == player_team)
-        .filter_map(|(e, _, transform)| {
-            let x = transform.translation.x;
-            let z = transform.translation.z;
-            if x > cursor_drag.world.x.min(world_pos.x)
-                && x < cursor_drag.world.x.max(world_pos.x)
-                && z > cursor_drag.world.z.min(world_pos.z)
-                && z < cursor_drag.world.z.max(world_pos.z)
-            {
-                Some(e)
-            } else {
-                None
-            }
-        })
-        .for_each(|e| {
-            if !selected.contains(&e) {
-                selected.push(e);
-            }
-        });
+    let own_units = |e: Entity| canidates.get(e).is_ok_and(|team| team == player_team);
+    let settings = TlasCastSettings::default().with_filter(&own_units);
+    for e in tlas_cast.query_frustum(&frustum, *selection_tlas, false, &settings) {
+        if !selected.contains(&e) {
+            selected.push(e);
+        }
+    }
+}
+
+/// Frustum bounded by 4 corner rays in order around the screen rectangle, from the near plane out to ``far``.
+fn rays_frustum(rays: &[Ray3d; 4], forward: Vec3, far: f32) -> Frustum {
+    let near = rays.iter().map(|ray| ray.origin).sum::<Vec3>() / 4.0;
+    let inside = rays.iter().map(|ray| ray.get_point(1.0)).sum::<Vec3>() / 4.0;
+
+    // half space normals point inward
+    let half_space = |normal: Vec3, point: Vec3| {
+        let normal = match normal.dot(inside - point) < 0.0 {
+            true => -normal,
+            false => normal,
+        };
+        HalfSpace::new(normal.extend(-normal.dot(point)))
+    };
+
+    let mut half_spaces = [HalfSpace::default(); 6];
+    for (i, ray) in rays.iter().enumerate() {
+        // plane through this ray and the next, works for orthographic cameras too
+        let next = rays[(i + 1) % rays.len()];
+        let normal = ray.direction.cross(next.get_point(1.0) - ray.origin);
+        half_spaces[i] = half_space(normal, ray.origin);
+    }
+    half_spaces[4] = half_space(forward, near);
+    half_spaces[5] = half_space(-forward, near + forward * far);
+    Frustum { half_spaces }
 }
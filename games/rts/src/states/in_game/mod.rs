@@ -90,16 +90,48 @@ fn setup_game(
             StateScoped(AppState::InGame),
         ))
         .observe(
-            |trigger: Trigger<Pointer<Click>>, mut commands: Commands, selected: Res<Selected>| {
+            |trigger: Trigger<Pointer<Click>>,
+             mut commands: Commands,
+             selected: Res<Selected>,
+             unit_query: Query<&GlobalTransform>,
+             nav_query: Query<Entity, With<Nav>>,
+             nav_path: NavPath,
+             drag: Option<Res<CursorDrag>>| {
+                // releasing a box selection isn't a move order
+                if drag.is_some_and(|drag| drag.is_box_select()) {
+                    return;
+                }
                 let e = trigger.event();
-                if let Some(pos) = e.hit.position {
-                    if selected.0.len() > 0 {
-                        // TODO: Grid based movement
-                        commands.trigger_targets(
-                            Goal::Move(vec3(pos.x, 0.0, pos.z)),
-                            selected.0.clone(),
-                        );
-                    }
+                let Some(pos) = e.hit.position else {
+                    return;
+                };
+                let target = vec3(pos.x, 0.0, pos.z);
+                let Ok(nav_e) = nav_query.single() else {
+                    commands.trigger_targets(Goal::Move(target), selected.0.clone());
+                    return;
+                };
+
+                // spread the selection out in a box facing the direction of travel
+                let units = unit_query
+                    .iter_many(selected.iter().copied())
+                    .map(|trans| trans.translation())
+                    .collect::<Vec<_>>();
+                if units.len() != selected.len() || units.is_empty() {
+                    commands.trigger_targets(Goal::Move(target), selected.0.clone());
+                    return;
+                }
+                let center = units.iter().sum::<Vec3>() / units.len() as f32;
+                let slots = nav_path.formation_slots(
+                    nav_e,
+                    target,
+                    target - center,
+                    &FormationShape::Box,
+                    units.len(),
+                    1.5,
+                );
+                let assignment = assign_slots(&units, &slots);
+                for (unit, slot) in selected.iter().zip(assignment) {
+                    commands.trigger_targets(Goal::Move(slots[slot]), *unit);
                 }
             },
         );
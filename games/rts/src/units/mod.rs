@@ -51,7 +51,11 @@ fn select(
     query: Query<&Team, Without<ActivePlayer>>,
     mut commands: Commands,
     inputs: Res<ButtonInput<KeyCode>>,
+    drag: Option<Res<CursorDrag>>,
 ) {
+    if drag.is_some_and(|drag| drag.is_box_select()) {
+        return;
+    }
     let e = trigger.target();
     let player_team = *player;
     let team = query.get(e).unwrap();